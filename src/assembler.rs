@@ -0,0 +1,1818 @@
+use super::linker::{Object, Relocation, RelocationField, ADDRESS_INSTRUCTIONS};
+use super::simulator::*;
+use super::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+
+/// A single piece of a source line, split on commas and
+/// whitespace, along with the column (0-indexed) it starts at
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub column: usize,
+}
+
+/// A source line broken up into its parts. Every part is optional,
+/// as a line can be blank, a comment, a label on its own, or a full
+/// (optionally numbered) instruction
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedLine {
+    pub line_number: Option<Token>,
+    /// Label definitions, stored without the trailing ':'
+    pub labels: Vec<Token>,
+    pub mnemonic: Option<Token>,
+    pub operands: Vec<Token>,
+    /// Comment, including the leading '#'
+    pub comment: Option<String>,
+}
+
+impl ParsedLine {
+    /// True if the line has nothing for the assembler to do
+    pub fn is_empty(&self) -> bool {
+        self.line_number.is_none() && self.labels.is_empty() && self.mnemonic.is_none()
+    }
+}
+
+/// Result of assembling a HMMM program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assembly {
+    /// Memory image starting at address 0, with any gaps
+    /// left by .org filled with blank data
+    pub instructions: Vec<Instruction>,
+    /// Every label defined in the program, mapped to its memory address
+    pub symbols: BTreeMap<String, usize>,
+    /// Every constant defined with .equ, mapped to its value
+    pub constants: BTreeMap<String, i32>,
+    /// Where each word in memory came from in the source
+    pub source_map: SourceMap,
+}
+
+impl Assembly {
+    /// Wraps a program that has no source, eg: one read from a .hb file
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Self {
+        Assembly {
+            instructions,
+            symbols: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            source_map: SourceMap::new(),
+        }
+    }
+}
+
+/// Where in the source a word in memory came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// File the line is in, if the source was loaded from one
+    pub file: Option<String>,
+    /// Line number in the file (1-indexed)
+    pub line: usize,
+    /// Column (0-indexed) the instruction or value starts at
+    pub column: usize,
+    /// Raw text of the line
+    pub text: String,
+    /// Comment on the line, including the '#'
+    pub comment: Option<String>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+
+        write!(f, "line {}", self.line)
+    }
+}
+
+/// Maps memory addresses back to the source they were compiled from.
+/// Words placed by macros and pseudo-instructions map to the line they
+/// were used on, and blank words left by .org aren't mapped at all
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    locations: BTreeMap<usize, SourceLocation>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn insert(&mut self, address: usize, location: SourceLocation) {
+        self.locations.insert(address, location);
+    }
+
+    pub fn get(&self, address: usize) -> Option<&SourceLocation> {
+        self.locations.get(&address)
+    }
+
+    /// Every mapped address, in order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &SourceLocation)> {
+        self.locations
+            .iter()
+            .map(|(address, location)| (*address, location))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+/// A line of source, along with where it came from. Included
+/// files are spliced into the program as a list of these
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SourceLine {
+    file: Option<String>,
+    /// Index of the line in its file (0-indexed)
+    index: usize,
+    text: String,
+    /// Macro call this line was expanded from, if any
+    expansion: Option<Expansion>,
+    /// Whether the line comes after the first line of an expansion. The
+    /// line number of the call goes on the first line, so these have none
+    continuation: bool,
+}
+
+/// The macro or pseudo-instruction call a line was expanded from.
+/// For nested macros, this is the outermost call
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Expansion {
+    name: String,
+    /// Raw text of the line the macro was called on
+    call: String,
+    /// Columns of the macro name on the call line
+    span: Range<usize>,
+}
+
+impl SourceLine {
+    /// Location of something at `column` on this line. Expanded lines
+    /// are located at the macro call they came from
+    fn location(&self, column: usize) -> SourceLocation {
+        let (text, column) = match &self.expansion {
+            Some(expansion) => (&expansion.call, expansion.span.start),
+            None => (&self.text, column),
+        };
+
+        SourceLocation {
+            file: self.file.clone(),
+            line: self.index + 1,
+            column,
+            text: text.clone(),
+            comment: tokenize_line(text).1,
+        }
+    }
+
+    /// Points a diagnostic found on this line back at the file and
+    /// line it came from. Errors in expanded lines point at the call
+    fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        diagnostic.file = self.file.clone();
+        diagnostic.line = self.index + 1;
+
+        if let Some(expansion) = &self.expansion {
+            let context = format!(
+                "in \"{}\", expanded from \"{}\"",
+                self.text.trim(),
+                expansion.name
+            );
+
+            diagnostic.suggestion = Some(match diagnostic.suggestion {
+                Some(suggestion) => format!("{}: {}", context, suggestion),
+                None => context,
+            });
+            diagnostic.source = expansion.call.clone();
+            diagnostic.span = expansion.span.clone();
+        }
+
+        diagnostic
+    }
+}
+
+/// Keeps track of files while includes are being expanded
+struct IncludeState {
+    /// Files currently being included, along with the path used to
+    /// include them, used to find cycles
+    stack: Vec<(PathBuf, String)>,
+    /// Every file included so far, so each is only included once
+    included: HashSet<PathBuf>,
+    lines: Vec<SourceLine>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Names the assembler knows about while it works through a program
+#[derive(Clone, Debug, Default)]
+struct SymbolTable {
+    labels: BTreeMap<String, usize>,
+    constants: BTreeMap<String, i32>,
+    /// Labels listed with .global
+    exports: BTreeSet<String>,
+    /// True when assembling an object, where names that aren't
+    /// defined are imported from other objects when linking
+    relocatable: bool,
+}
+
+impl SymbolTable {
+    /// True if `name` will be filled in by the linker
+    fn is_import(&self, name: &str) -> bool {
+        self.relocatable && is_valid_label(name) && !self.contains(name)
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.constants.contains_key(name)
+    }
+
+    /// Value of a label or constant
+    fn lookup(&self, name: &str) -> Option<i32> {
+        match self.labels.get(name) {
+            Some(address) => Some(*address as i32),
+            None => self.constants.get(name).copied(),
+        }
+    }
+
+    /// Diagnostic for a reference to a name that was never defined,
+    /// suggesting a similarly named symbol if there is one
+    fn undefined(&self, index: usize, raw_line: &str, token: &Token) -> Diagnostic {
+        let diagnostic = Diagnostic::at_token(CompileErr::UndefinedLabel, index, raw_line, token);
+        let names = self
+            .labels
+            .keys()
+            .chain(self.constants.keys())
+            .map(|name| name.as_str());
+
+        match closest_match(&token.text, names) {
+            Some(closest) => diagnostic.suggest(format!("did you mean \"{}\"?", closest)),
+            None => diagnostic.suggest(format!(
+                "define it with \"{}:\" or \".equ {}\"",
+                token.text, token.text
+            )),
+        }
+    }
+}
+
+/// Directives the assembler understands, along with a description
+/// of their arguments for error messages
+static DIRECTIVES: [(&str, &str); 9] = [
+    (".word", "one or more values"),
+    (".fill", "a count and an optional value"),
+    (".space", "a count"),
+    (".equ", "a name and a value"),
+    (".org", "an address"),
+    (".include", "a path in double quotes"),
+    (".macro", "a name, followed by any parameters"),
+    (".endm", "nothing"),
+    (".global", "one or more labels"),
+];
+
+/// Splits a line on commas, spaces and tabs, stopping at the
+/// first '#'. Anything in single or double quotes is kept together,
+/// so 'A' and ' ' are single tokens. Returns the tokens and the
+/// comment (if any)
+pub fn tokenize_line(line: &str) -> (Vec<Token>, Option<String>) {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut comment = None;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (column, c) in line.chars().enumerate() {
+        if let Some(open) = quote {
+            current.push(c);
+
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == open {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' {
+            if current.is_empty() {
+                start = column;
+            }
+            current.push(c);
+            quote = Some(c);
+        } else if c == '#' {
+            comment = Some(line.chars().skip(column).collect());
+            break;
+        } else if c == ',' || c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(Token {
+                    text: current.clone(),
+                    column: start,
+                });
+                current.clear();
+            }
+        } else {
+            if current.is_empty() {
+                start = column;
+            }
+            current.push(c);
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(Token {
+            text: current,
+            column: start,
+        });
+    }
+
+    (tokens, comment)
+}
+
+/// Parses a line into a line number, labels, mnemonic and operands
+///
+/// A leading integer is treated as a line number, and any tokens
+/// ending in ':' before the mnemonic are label definitions
+pub fn parse_line(line: &str) -> ParsedLine {
+    let (tokens, comment) = tokenize_line(line);
+    let mut tokens = tokens.into_iter().peekable();
+    let mut parsed = ParsedLine {
+        comment,
+        ..Default::default()
+    };
+
+    if let Some(first) = tokens.peek() {
+        if first.text.parse::<i128>().is_ok() {
+            parsed.line_number = tokens.next();
+        }
+    }
+
+    while let Some(token) = tokens.next_if(|t| t.text.ends_with(':')) {
+        parsed.labels.push(Token {
+            text: token.text.trim_end_matches(':').to_string(),
+            column: token.column,
+        });
+    }
+
+    parsed.mnemonic = tokens.next();
+    parsed.operands = tokens.collect();
+
+    parsed
+}
+
+/// Labels must start with a letter or underscore, followed
+/// by any number of letters, digits or underscores
+pub fn is_valid_label(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// A single problem found while compiling, with enough context
+/// to point at exactly what went wrong
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub error: CompileErr,
+    /// File the error is in, if the source was loaded from one
+    pub file: Option<String>,
+    /// Line number in the source file (1-indexed)
+    pub line: usize,
+    /// Raw text of the line
+    pub source: String,
+    /// Columns (0-indexed, end exclusive) of the offending token
+    pub span: Range<usize>,
+    /// Argument type expected at this position, as used in
+    /// InstructionType::arguments
+    pub expected: Option<char>,
+    /// Suggestion on how to fix the error
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for the line at `index` (0-indexed)
+    pub fn new(error: CompileErr, index: usize, source: &str, span: Range<usize>) -> Self {
+        Diagnostic {
+            error,
+            file: None,
+            line: index + 1,
+            source: source.to_string(),
+            span,
+            expected: None,
+            suggestion: None,
+        }
+    }
+
+    fn at_token(error: CompileErr, index: usize, source: &str, token: &Token) -> Self {
+        let span = token.column..token.column + token.text.chars().count();
+        Diagnostic::new(error, index, source, span)
+    }
+
+    fn expecting(mut self, expected: char) -> Self {
+        self.expected = Some(expected);
+        self
+    }
+
+    fn suggest(mut self, suggestion: String) -> Self {
+        self.suggestion = Some(suggestion);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line,
+            self.span.start + 1,
+            self.error
+        )?;
+
+        if let Some(expected) = self.expected {
+            write!(
+                f,
+                " (expected {})",
+                InstructionType::describe_argument(expected)
+            )?;
+        }
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; {}", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Number of single character edits needed to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Finds the closest candidate to a misspelled name, if any are close enough
+fn closest_match<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Lists the arguments an instruction takes, eg: "register, signed number (-128 to 127)"
+fn describe_arguments(argument_kinds: &[char]) -> String {
+    if argument_kinds.is_empty() {
+        return "no arguments".to_string();
+    }
+
+    argument_kinds
+        .iter()
+        .map(|kind| InstructionType::describe_argument(*kind))
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// Suggestion for a number that doesn't fit in the space it's going in
+fn out_of_range(value: i32, range: RangeInclusive<i32>) -> String {
+    format!(
+        "{} is outside the allowed range of {} to {}",
+        value,
+        range.start(),
+        range.end()
+    )
+}
+
+/// Resolves a directive argument that's either a number or a symbol
+fn resolve_value(
+    index: usize,
+    raw_line: &str,
+    token: &Token,
+    symbols: &SymbolTable,
+) -> Result<i32, Diagnostic> {
+    if let Some(number) = parse_literal(&token.text) {
+        Ok(number)
+    } else if is_valid_label(&token.text) {
+        symbols
+            .lookup(&token.text)
+            .ok_or_else(|| symbols.undefined(index, raw_line, token))
+    } else {
+        Err(
+            Diagnostic::at_token(CompileErr::InvalidNumber, index, raw_line, token)
+                .suggest("expected a number, label or constant".to_string()),
+        )
+    }
+}
+
+/// Resolves a count for .fill or .space, which has to be known in
+/// the first pass, so it can only use constants defined above it
+fn resolve_count(
+    index: usize,
+    raw_line: &str,
+    token: &Token,
+    symbols: &SymbolTable,
+) -> Result<usize, Diagnostic> {
+    let count = resolve_value(index, raw_line, token, symbols)?;
+
+    if (0..=256).contains(&count) {
+        Ok(count as usize)
+    } else {
+        Err(
+            Diagnostic::at_token(CompileErr::InvalidNumber, index, raw_line, token)
+                .suggest(out_of_range(count, 0..=256)),
+        )
+    }
+}
+
+/// Checks a directive has the right number of arguments
+fn check_directive_arguments(
+    index: usize,
+    raw_line: &str,
+    parsed: &ParsedLine,
+    allowed: Range<usize>,
+) -> Result<(), Diagnostic> {
+    let directive = parsed.mnemonic.as_ref().unwrap();
+    let name = directive.text.to_lowercase();
+    let usage = DIRECTIVES
+        .iter()
+        .find(|(directive, _)| *directive == name)
+        .map(|(directive, arguments)| format!("\"{}\" takes {}", directive, arguments))
+        .unwrap_or_default();
+
+    if parsed.operands.len() >= allowed.end {
+        let extra = &parsed.operands[allowed.end - 1];
+        Err(
+            Diagnostic::at_token(CompileErr::TooManyArguments, index, raw_line, extra)
+                .suggest(usage),
+        )
+    } else if parsed.operands.len() < allowed.start {
+        let last = parsed.operands.last().unwrap_or(directive);
+        let end = last.column + last.text.chars().count();
+
+        Err(
+            Diagnostic::new(CompileErr::TooFewArguments, index, raw_line, end..end + 1)
+                .suggest(usage),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+/// First pass handling of a directive. Defines constants, and returns
+/// the number of memory words the directive takes up
+fn layout_directive(
+    index: usize,
+    raw_line: &str,
+    parsed: &ParsedLine,
+    symbols: &mut SymbolTable,
+) -> Result<usize, Diagnostic> {
+    let directive = parsed.mnemonic.as_ref().unwrap();
+    let operands = &parsed.operands;
+
+    match directive.text.to_lowercase().as_str() {
+        ".word" => {
+            check_directive_arguments(index, raw_line, parsed, 1..257)?;
+            Ok(operands.len())
+        }
+        ".fill" => {
+            check_directive_arguments(index, raw_line, parsed, 1..3)?;
+            resolve_count(index, raw_line, &operands[0], symbols)
+        }
+        ".space" => {
+            check_directive_arguments(index, raw_line, parsed, 1..2)?;
+            resolve_count(index, raw_line, &operands[0], symbols)
+        }
+        ".equ" => {
+            check_directive_arguments(index, raw_line, parsed, 2..3)?;
+            let name = &operands[0];
+
+            if !is_valid_label(&name.text) {
+                return Err(
+                    Diagnostic::at_token(CompileErr::InvalidLabel, index, raw_line, name)
+                        .suggest("constants follow the same naming rules as labels".to_string()),
+                );
+            } else if symbols.contains(&name.text) {
+                return Err(Diagnostic::at_token(
+                    CompileErr::DuplicateLabel,
+                    index,
+                    raw_line,
+                    name,
+                )
+                .suggest("rename one of them".to_string()));
+            }
+
+            let value = resolve_value(index, raw_line, &operands[1], symbols)?;
+            symbols.constants.insert(name.text.clone(), value);
+            Ok(0)
+        }
+        // .org is handled before the line is given an address
+        ".org" => Ok(0),
+        ".global" => {
+            check_directive_arguments(index, raw_line, parsed, 1..257)?;
+
+            for name in operands {
+                symbols.exports.insert(name.text.clone());
+            }
+
+            Ok(0)
+        }
+        name => {
+            let mut diagnostic =
+                Diagnostic::at_token(CompileErr::UnknownDirective, index, raw_line, directive);
+
+            if let Some(closest) = closest_match(name, DIRECTIVES.iter().map(|(name, _)| *name)) {
+                diagnostic = diagnostic.suggest(format!("did you mean \"{}\"?", closest));
+            }
+
+            Err(diagnostic)
+        }
+    }
+}
+
+/// Works out the new address for a .org directive, which can only move forwards
+fn layout_org(
+    index: usize,
+    raw_line: &str,
+    parsed: &ParsedLine,
+    address: usize,
+    symbols: &SymbolTable,
+) -> Result<usize, Diagnostic> {
+    check_directive_arguments(index, raw_line, parsed, 1..2)?;
+    let target = &parsed.operands[0];
+    let new_address = resolve_value(index, raw_line, target, symbols)?;
+
+    if !(0..=256).contains(&new_address) {
+        Err(
+            Diagnostic::at_token(CompileErr::InvalidNumber, index, raw_line, target)
+                .suggest(out_of_range(new_address, 0..=256)),
+        )
+    } else if (new_address as usize) < address {
+        Err(
+            Diagnostic::at_token(CompileErr::InvalidOrigin, index, raw_line, target).suggest(
+                format!(
+                    "address {} is already in use, .org can only move forwards",
+                    new_address
+                ),
+            ),
+        )
+    } else {
+        Ok(new_address as usize)
+    }
+}
+
+/// Creates a data word holding a value
+fn data_word(
+    index: usize,
+    raw_line: &str,
+    token: &Token,
+    symbols: &SymbolTable,
+) -> Result<Instruction, Diagnostic> {
+    // Imported addresses are left as 0 until the object is linked
+    let value = if symbols.is_import(&token.text) {
+        0
+    } else {
+        resolve_value(index, raw_line, token, symbols)?
+    };
+    let range = InstructionType::argument_range('n').unwrap();
+
+    if range.contains(&value) {
+        Ok(Instruction::new_data(&format!("{:016b}", value as u16)))
+    } else {
+        Err(
+            Diagnostic::at_token(CompileErr::InvalidNumber, index, raw_line, token)
+                .suggest(out_of_range(value, range)),
+        )
+    }
+}
+
+/// Second pass handling of a directive, returning the words it places in memory.
+/// The size is what the first pass laid out, so a bad count is only reported once
+fn assemble_directive(
+    index: usize,
+    raw_line: &str,
+    parsed: &ParsedLine,
+    size: usize,
+    symbols: &SymbolTable,
+) -> Result<Vec<Instruction>, Diagnostic> {
+    let directive = parsed.mnemonic.as_ref().unwrap();
+    let operands = &parsed.operands;
+
+    match directive.text.to_lowercase().as_str() {
+        ".word" => operands
+            .iter()
+            .map(|operand| data_word(index, raw_line, operand, symbols))
+            .collect(),
+        ".fill" => {
+            let value = match operands.get(1) {
+                Some(value) => data_word(index, raw_line, value, symbols)?,
+                None => Instruction::new_blank_data(),
+            };
+
+            Ok(vec![value; size])
+        }
+        ".space" => Ok(vec![Instruction::new_blank_data(); size]),
+        // Only labels can be exported, as constants aren't addresses
+        ".global" => match operands
+            .iter()
+            .find(|name| !symbols.labels.contains_key(&name.text))
+        {
+            Some(name) => Err(symbols.undefined(index, raw_line, name)),
+            None => Ok(Vec::new()),
+        },
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// True if the line's mnemonic is an assembler directive, eg: ".word"
+fn is_directive(parsed: &ParsedLine) -> bool {
+    parsed
+        .mnemonic
+        .as_ref()
+        .map(|mnemonic| mnemonic.text.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Checks a single instruction line, returning the encoded instruction
+/// or a diagnostic pointing at the first bad token on the line
+fn assemble_line(
+    index: usize,
+    raw_line: &str,
+    parsed: &ParsedLine,
+    symbols: &SymbolTable,
+) -> Result<Instruction, Diagnostic> {
+    let mnemonic = parsed.mnemonic.as_ref().unwrap();
+    let name = mnemonic.text.to_lowercase();
+
+    let instruction_type = match InstructionType::from_name(&name) {
+        Some(instruction_type) => instruction_type,
+        None => {
+            let mut diagnostic = Diagnostic::at_token(
+                CompileErr::InstructionDoesNotExist,
+                index,
+                raw_line,
+                mnemonic,
+            );
+            let all_names = InstructionType::all_names().into_iter();
+
+            if let Some(closest) = closest_match(&name, all_names) {
+                diagnostic = diagnostic.suggest(format!("did you mean \"{}\"?", closest));
+            }
+
+            return Err(diagnostic);
+        }
+    };
+
+    let argument_kinds: Vec<char> = instruction_type
+        .arguments
+        .chars()
+        .filter(|c| *c != 'z')
+        .collect();
+
+    let usage = format!(
+        "\"{}\" takes {}",
+        instruction_type.names[0],
+        describe_arguments(&argument_kinds)
+    );
+
+    if parsed.operands.len() > argument_kinds.len() {
+        let extra = &parsed.operands[argument_kinds.len()];
+        return Err(
+            Diagnostic::at_token(CompileErr::TooManyArguments, index, raw_line, extra)
+                .suggest(usage),
+        );
+    } else if parsed.operands.len() < argument_kinds.len() {
+        let last = parsed.operands.last().unwrap_or(mnemonic);
+        let end = last.column + last.text.chars().count();
+
+        return Err(
+            Diagnostic::new(CompileErr::TooFewArguments, index, raw_line, end..end + 1)
+                .expecting(argument_kinds[parsed.operands.len()])
+                .suggest(usage),
+        );
+    }
+
+    let mut operands: Vec<String> = Vec::new();
+
+    for (operand, kind) in parsed.operands.iter().zip(argument_kinds.iter()) {
+        let text = operand.text.as_str();
+        let range = InstructionType::argument_range(*kind);
+
+        // Numeric slots take literals, labels or constants, which are
+        // all turned into plain decimal before encoding
+        let value = match parse_literal(text) {
+            Some(value) if range.is_some() => Some(value),
+            _ if range.is_some() && is_valid_label(text) => match symbols.lookup(text) {
+                Some(value) => Some(value),
+                // Imported addresses are left as 0 until the object is linked
+                None if symbols.is_import(text) => Some(0),
+                None => return Err(symbols.undefined(index, raw_line, operand).expecting(*kind)),
+            },
+            _ => None,
+        };
+        let resolved = value
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| text.to_string());
+
+        if let Err(err) = Instruction::encode_argument(*kind, &resolved) {
+            let suggestion = match (value, range) {
+                (Some(value), Some(range)) => out_of_range(value, range),
+                _ => usage,
+            };
+
+            return Err(Diagnostic::at_token(err, index, raw_line, operand)
+                .expecting(*kind)
+                .suggest(suggestion));
+        }
+
+        operands.push(resolved);
+    }
+
+    let line = format!("{} {}", name, operands.join(" "));
+
+    Instruction::new_from_text(line.trim())
+        .map_err(|err| Diagnostic::at_token(err, index, raw_line, mnemonic))
+}
+
+/// Words on a line that refer to addresses, which the linker has to
+/// change when it moves the object. These are labels used anywhere a
+/// number can go, and numbers used as the address of a jump, call, loadn
+/// or storen. Constants are never moved
+fn line_relocations(
+    parsed: &ParsedLine,
+    address: usize,
+    size: usize,
+    symbols: &SymbolTable,
+) -> Vec<Relocation> {
+    let name = parsed.mnemonic.as_ref().unwrap().text.to_lowercase();
+    let reference = |operand: &Token, number_is_address: bool| {
+        let text = &operand.text;
+
+        if symbols.labels.contains_key(text) || (number_is_address && parse_literal(text).is_some())
+        {
+            Some(None)
+        } else if symbols.is_import(text) {
+            Some(Some(text.clone()))
+        } else {
+            None
+        }
+    };
+
+    let mut relocations = Vec::new();
+
+    match name.as_str() {
+        ".word" => {
+            for (offset, operand) in parsed.operands.iter().enumerate() {
+                if let Some(symbol) = reference(operand, false) {
+                    relocations.push(Relocation {
+                        address: address + offset,
+                        field: RelocationField::Word,
+                        symbol,
+                    });
+                }
+            }
+        }
+        ".fill" => {
+            if let Some(symbol) = parsed
+                .operands
+                .get(1)
+                .and_then(|value| reference(value, false))
+            {
+                for offset in 0..size {
+                    relocations.push(Relocation {
+                        address: address + offset,
+                        field: RelocationField::Word,
+                        symbol: symbol.clone(),
+                    });
+                }
+            }
+        }
+        _ if name.starts_with('.') => {}
+        _ => {
+            let instruction_type = InstructionType::from_name(&name).unwrap();
+            let is_address = ADDRESS_INSTRUCTIONS.contains(&instruction_type.names[0]);
+            let has_number = matches!(
+                instruction_type.arguments.chars().last(),
+                Some('s') | Some('u')
+            );
+
+            if let (true, Some(operand)) = (has_number, parsed.operands.last()) {
+                if let Some(symbol) = reference(operand, is_address) {
+                    relocations.push(Relocation {
+                        address,
+                        field: RelocationField::Operand,
+                        symbol,
+                    });
+                }
+            }
+        }
+    }
+
+    relocations
+}
+
+/// Gets the path out of an .include line, which must be in double quotes
+fn include_path(index: usize, raw_line: &str, parsed: &ParsedLine) -> Result<String, Diagnostic> {
+    let directive = parsed.mnemonic.as_ref().unwrap();
+    let (first, last) = match (parsed.operands.first(), parsed.operands.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            let end = directive.column + directive.text.chars().count();
+
+            return Err(Diagnostic::new(
+                CompileErr::TooFewArguments,
+                index,
+                raw_line,
+                end..end + 1,
+            )
+            .suggest("\".include\" takes a path in double quotes".to_string()));
+        }
+    };
+
+    // Paths can contain spaces and commas, so take the raw text rather than the tokens
+    let span = first.column..last.column + last.text.chars().count();
+    let text: String = raw_line.chars().skip(span.start).take(span.len()).collect();
+
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Ok(text[1..text.len() - 1].to_string())
+    } else {
+        Err(
+            Diagnostic::new(CompileErr::InvalidArgumentType, index, raw_line, span).suggest(
+                format!(
+                    "put the path in double quotes: .include \"{}\"",
+                    text.trim_matches('"')
+                ),
+            ),
+        )
+    }
+}
+
+/// Splices the lines of a file into the program, recursively expanding
+/// any .include directives it contains. Paths are relative to `directory`
+fn expand_includes(
+    lines: &[String],
+    file: Option<String>,
+    directory: &Path,
+    state: &mut IncludeState,
+) {
+    for (index, raw_line) in lines.iter().enumerate() {
+        let parsed = parse_line(raw_line);
+        let is_include = parsed
+            .mnemonic
+            .as_ref()
+            .map(|mnemonic| mnemonic.text.eq_ignore_ascii_case(".include"))
+            .unwrap_or(false);
+
+        if !is_include {
+            state.lines.push(SourceLine {
+                file: file.clone(),
+                index,
+                text: raw_line.clone(),
+                expansion: None,
+                continuation: false,
+            });
+            continue;
+        }
+
+        let error_at = |diagnostic: Diagnostic| Diagnostic {
+            file: file.clone(),
+            ..diagnostic
+        };
+
+        let path = match include_path(index, raw_line, &parsed) {
+            Ok(path) => directory.join(path),
+            Err(diagnostic) => {
+                state.diagnostics.push(error_at(diagnostic));
+                continue;
+            }
+        };
+
+        let span = parsed.operands[0].column..raw_line.trim_end().chars().count();
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => {
+                let diagnostic =
+                    Diagnostic::new(CompileErr::IncludeNotFound, index, raw_line, span)
+                        .suggest(format!("\"{}\" could not be found", path.display()));
+                state.diagnostics.push(error_at(diagnostic));
+                continue;
+            }
+        };
+
+        let display_path = path.display().to_string();
+
+        if let Some(position) = state
+            .stack
+            .iter()
+            .position(|(included, _)| *included == canonical)
+        {
+            let chain: Vec<&str> = state.stack[position..]
+                .iter()
+                .map(|(_, included)| included.as_str())
+                .chain(std::iter::once(display_path.as_str()))
+                .collect();
+            let diagnostic = Diagnostic::new(CompileErr::IncludeCycle, index, raw_line, span)
+                .suggest(format!("files include each other: {}", chain.join(" -> ")));
+            state.diagnostics.push(error_at(diagnostic));
+            continue;
+        }
+
+        // A file included from more than one place is only spliced in once
+        if !state.included.insert(canonical.clone()) {
+            continue;
+        }
+
+        let included_lines = match load_file(&path.to_string_lossy()) {
+            Ok(included_lines) => included_lines,
+            Err(err) => {
+                let diagnostic =
+                    Diagnostic::new(CompileErr::IncludeNotFound, index, raw_line, span)
+                        .suggest(format!("\"{}\" could not be read: {}", path.display(), err));
+                state.diagnostics.push(error_at(diagnostic));
+                continue;
+            }
+        };
+
+        let included_directory = path.parent().unwrap_or(directory).to_path_buf();
+
+        state.stack.push((canonical, display_path.clone()));
+        expand_includes(
+            &included_lines,
+            Some(display_path),
+            &included_directory,
+            state,
+        );
+        state.stack.pop();
+    }
+}
+
+/// A macro defined with .macro, whose body is spliced in wherever it's used
+#[derive(Clone, Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+/// Pseudo-instructions built in to the assembler, with the range of
+/// arguments they take, and what they expand to
+static PSEUDO_INSTRUCTIONS: [(&str, Range<usize>, &str); 5] = [
+    ("inc", 1..2, "\"inc rX\" is short for \"addn rX 1\""),
+    ("dec", 1..2, "\"dec rX\" is short for \"addn rX -1\""),
+    ("ret", 0..1, "\"ret\" is short for \"jumpr r14\""),
+    (
+        "push",
+        1..17,
+        "\"push rX rY ...\" pushes each register onto the stack at r15",
+    ),
+    (
+        "pop",
+        1..17,
+        "\"pop rX rY ...\" pops each register off the stack at r15, last first",
+    ),
+];
+
+/// True if `name` is one of the built in pseudo-instructions, eg: "inc"
+pub fn is_pseudo_instruction(name: &str) -> bool {
+    PSEUDO_INSTRUCTIONS
+        .iter()
+        .any(|(pseudo, _, _)| *pseudo == name)
+}
+
+/// Most macros that can be nested inside each other before giving up
+const MAX_MACRO_DEPTH: usize = 32;
+
+/// Lines a pseudo-instruction expands to
+fn expand_pseudo_instruction(name: &str, arguments: &[String]) -> Vec<String> {
+    match name {
+        "inc" => vec![format!("addn {} 1", arguments[0])],
+        "dec" => vec![format!("addn {} -1", arguments[0])],
+        "ret" => vec!["jumpr r14".to_string()],
+        "push" => arguments
+            .iter()
+            .map(|register| format!("pushr {} r15", register))
+            .collect(),
+        // Registers are popped in reverse, so "pop" undoes a "push" of the same list
+        "pop" => arguments
+            .iter()
+            .rev()
+            .map(|register| format!("popr {} r15", register))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Replaces each \parameter in a macro body line with its argument,
+/// and \@ with a number unique to this expansion, for local labels
+fn substitute_parameters(
+    line: &str,
+    parameters: &[String],
+    arguments: &[String],
+    unique: usize,
+) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'@') {
+            chars.next();
+            result.push_str(&unique.to_string());
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(next) = chars
+            .peek()
+            .filter(|next| next.is_alphanumeric() || **next == '_')
+        {
+            name.push(*next);
+            chars.next();
+        }
+
+        match parameters.iter().position(|parameter| *parameter == name) {
+            Some(position) => result.push_str(&arguments[position]),
+            None => {
+                result.push('\\');
+                result.push_str(&name);
+            }
+        }
+    }
+
+    result
+}
+
+/// Checks a .macro line and the body that follows it, returning the
+/// macro's name and definition
+fn define_macro(
+    line: &SourceLine,
+    parsed: &ParsedLine,
+    body: Vec<SourceLine>,
+    macros: &BTreeMap<String, Macro>,
+) -> Result<(String, Macro), Diagnostic> {
+    let directive = parsed.mnemonic.as_ref().unwrap();
+    let name = match parsed.operands.first() {
+        Some(name) => name,
+        None => {
+            let end = directive.column + directive.text.chars().count();
+
+            return Err(line.locate(
+                Diagnostic::new(
+                    CompileErr::TooFewArguments,
+                    line.index,
+                    &line.text,
+                    end..end + 1,
+                )
+                .suggest("\".macro\" takes a name, followed by any parameters".to_string()),
+            ));
+        }
+    };
+
+    let invalid = |token: &Token, suggestion: String| {
+        line.locate(
+            Diagnostic::at_token(CompileErr::InvalidMacro, line.index, &line.text, token)
+                .suggest(suggestion),
+        )
+    };
+
+    let lowercase = name.text.to_lowercase();
+    let is_reserved =
+        InstructionType::from_name(&lowercase).is_some() || is_pseudo_instruction(&lowercase);
+
+    if !is_valid_label(&name.text) {
+        return Err(invalid(
+            name,
+            "macros follow the same naming rules as labels".to_string(),
+        ));
+    } else if is_reserved {
+        return Err(invalid(
+            name,
+            format!("\"{}\" is already an instruction", name.text),
+        ));
+    } else if macros.contains_key(&name.text) {
+        return Err(invalid(
+            name,
+            format!("\"{}\" is already defined", name.text),
+        ));
+    }
+
+    let parameters: Vec<String> = parsed.operands[1..]
+        .iter()
+        .map(|parameter| parameter.text.trim_start_matches('\\').to_string())
+        .collect();
+
+    for (position, parameter) in parameters.iter().enumerate() {
+        if !is_valid_label(parameter) || parameters[..position].contains(parameter) {
+            return Err(invalid(
+                &parsed.operands[position + 1],
+                "parameters must be unique, and follow the same naming rules as labels".to_string(),
+            ));
+        }
+    }
+
+    Ok((name.text.clone(), Macro { parameters, body }))
+}
+
+/// Removes .macro definitions from the program, returning the
+/// remaining lines and every macro found
+fn collect_macros(
+    lines: Vec<SourceLine>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (Vec<SourceLine>, BTreeMap<String, Macro>) {
+    let mut macros = BTreeMap::new();
+    let mut remaining = Vec::new();
+    let mut lines = lines.into_iter();
+
+    while let Some(line) = lines.next() {
+        let parsed = parse_line(&line.text);
+        let directive = parsed
+            .mnemonic
+            .as_ref()
+            .map(|mnemonic| mnemonic.text.to_lowercase());
+
+        match directive.as_deref() {
+            Some(".macro") => {
+                let mut body = Vec::new();
+                let mut ended = false;
+
+                for body_line in lines.by_ref() {
+                    let body_parsed = parse_line(&body_line.text);
+                    let body_directive = body_parsed
+                        .mnemonic
+                        .as_ref()
+                        .map(|mnemonic| mnemonic.text.to_lowercase());
+
+                    match body_directive.as_deref() {
+                        Some(".endm") => {
+                            ended = true;
+                            break;
+                        }
+                        Some(".macro") => {
+                            let token = body_parsed.mnemonic.as_ref().unwrap();
+                            diagnostics.push(
+                                body_line.locate(
+                                    Diagnostic::at_token(
+                                        CompileErr::InvalidMacro,
+                                        body_line.index,
+                                        &body_line.text,
+                                        token,
+                                    )
+                                    .suggest(
+                                        "macros cannot be defined inside other macros".to_string(),
+                                    ),
+                                ),
+                            );
+                        }
+                        _ => body.push(body_line),
+                    }
+                }
+
+                if !ended {
+                    let token = parsed.mnemonic.as_ref().unwrap();
+                    diagnostics.push(
+                        line.locate(
+                            Diagnostic::at_token(
+                                CompileErr::InvalidMacro,
+                                line.index,
+                                &line.text,
+                                token,
+                            )
+                            .suggest("end the macro with \".endm\"".to_string()),
+                        ),
+                    );
+                }
+
+                match define_macro(&line, &parsed, body, &macros) {
+                    Ok((name, definition)) => {
+                        macros.insert(name, definition);
+                    }
+                    Err(diagnostic) => diagnostics.push(diagnostic),
+                }
+            }
+            Some(".endm") => {
+                let token = parsed.mnemonic.as_ref().unwrap();
+                diagnostics.push(
+                    line.locate(
+                        Diagnostic::at_token(
+                            CompileErr::InvalidMacro,
+                            line.index,
+                            &line.text,
+                            token,
+                        )
+                        .suggest("\".endm\" without a matching \".macro\"".to_string()),
+                    ),
+                );
+            }
+            _ => remaining.push(line),
+        }
+    }
+
+    (remaining, macros)
+}
+
+/// Keeps track of macros while they're being expanded
+struct MacroState {
+    macros: BTreeMap<String, Macro>,
+    /// Number of expansions so far, used for \@
+    expansions: usize,
+    lines: Vec<SourceLine>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Expands a single line if it calls a macro or pseudo-instruction,
+/// recursively expanding any calls in the result
+fn expand_line(line: SourceLine, depth: usize, state: &mut MacroState) {
+    let parsed = parse_line(&line.text);
+    let mnemonic = match &parsed.mnemonic {
+        Some(mnemonic) => mnemonic.clone(),
+        None => {
+            state.lines.push(line);
+            return;
+        }
+    };
+
+    let lowercase = mnemonic.text.to_lowercase();
+    let pseudo = PSEUDO_INSTRUCTIONS
+        .iter()
+        .find(|(name, _, _)| *name == lowercase);
+    let definition = state.macros.get(&mnemonic.text).cloned();
+
+    let (allowed, usage) = match (pseudo, &definition) {
+        (Some((_, allowed, usage)), _) => (allowed.clone(), usage.to_string()),
+        (None, Some(definition)) => {
+            let count = definition.parameters.len();
+            let parameters: Vec<String> = definition
+                .parameters
+                .iter()
+                .map(|parameter| format!(" {}", parameter))
+                .collect();
+
+            (
+                count..count + 1,
+                format!("usage: {}{}", mnemonic.text, parameters.concat()),
+            )
+        }
+        (None, None) => {
+            state.lines.push(line);
+            return;
+        }
+    };
+
+    let arguments: Vec<String> = parsed
+        .operands
+        .iter()
+        .map(|operand| operand.text.clone())
+        .collect();
+
+    let error = if depth >= MAX_MACRO_DEPTH {
+        Some(
+            Diagnostic::at_token(
+                CompileErr::MacroRecursion,
+                line.index,
+                &line.text,
+                &mnemonic,
+            )
+            .suggest(format!(
+                "macros can only be nested {} deep",
+                MAX_MACRO_DEPTH
+            )),
+        )
+    } else if arguments.len() >= allowed.end {
+        let extra = &parsed.operands[allowed.end - 1];
+        Some(
+            Diagnostic::at_token(CompileErr::TooManyArguments, line.index, &line.text, extra)
+                .suggest(usage),
+        )
+    } else if arguments.len() < allowed.start {
+        let last = parsed.operands.last().unwrap_or(&mnemonic);
+        let end = last.column + last.text.chars().count();
+        Some(
+            Diagnostic::new(
+                CompileErr::TooFewArguments,
+                line.index,
+                &line.text,
+                end..end + 1,
+            )
+            .suggest(usage),
+        )
+    } else {
+        None
+    };
+
+    if let Some(diagnostic) = error {
+        state.diagnostics.push(line.locate(diagnostic));
+        return;
+    }
+
+    state.expansions += 1;
+    let unique = state.expansions;
+
+    let expanded: Vec<String> = match &definition {
+        Some(definition) if pseudo.is_none() => definition
+            .body
+            .iter()
+            .map(|body_line| {
+                substitute_parameters(&body_line.text, &definition.parameters, &arguments, unique)
+            })
+            .collect(),
+        _ => expand_pseudo_instruction(&lowercase, &arguments),
+    };
+
+    // Errors in the expansion point at the outermost call
+    let expansion = line.expansion.clone().unwrap_or(Expansion {
+        name: mnemonic.text.clone(),
+        call: line.text.clone(),
+        span: mnemonic.column..mnemonic.column + mnemonic.text.chars().count(),
+    });
+
+    // Line numbers and labels on the call line go on the first expanded line
+    let mut prefix: String = line.text.chars().take(mnemonic.column).collect();
+
+    for (offset, text) in expanded.into_iter().enumerate() {
+        let expanded_line = SourceLine {
+            file: line.file.clone(),
+            index: line.index,
+            text: format!("{}{}", prefix, text),
+            expansion: Some(expansion.clone()),
+            continuation: line.continuation || offset > 0,
+        };
+
+        prefix.clear();
+        expand_line(expanded_line, depth + 1, state);
+    }
+}
+
+/// Removes macro definitions from the program, and expands every
+/// use of a macro or pseudo-instruction into real instructions
+fn expand_macros(lines: Vec<SourceLine>, diagnostics: &mut Vec<Diagnostic>) -> Vec<SourceLine> {
+    let (lines, macros) = collect_macros(lines, diagnostics);
+    let mut state = MacroState {
+        macros,
+        expansions: 0,
+        lines: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for line in lines {
+        expand_line(line, 0, &mut state);
+    }
+
+    diagnostics.extend(state.diagnostics);
+    state.lines
+}
+
+/// Assembles HMMM source that isn't tied to a file. Any .include
+/// paths are relative to the current directory
+pub fn assemble(uncompiled_text: &[String]) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_source(uncompiled_text, None, false).map(|object| object.assembly)
+}
+
+/// Assembles HMMM source that was loaded from `path`. Any .include
+/// paths are relative to the directory the file is in, and every
+/// diagnostic records the file it was found in
+pub fn assemble_from(uncompiled_text: &[String], path: &str) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_source(uncompiled_text, Some(Path::new(path)), false).map(|object| object.assembly)
+}
+
+/// Assembles HMMM source loaded from `path` into an object, to be linked
+/// with others. Labels listed with .global can be used by other objects,
+/// and any name that isn't defined is imported from them
+pub fn assemble_object(uncompiled_text: &[String], path: &str) -> Result<Object, Vec<Diagnostic>> {
+    assemble_source(uncompiled_text, Some(Path::new(path)), true)
+}
+
+/// Address of the first word each line of a file is assembled into, if
+/// it places any, with macro and pseudo-instruction calls taking up every
+/// word they expand to. Line numbers are ignored, so a file can be laid out
+/// before it's renumbered, and names that aren't defined are taken to be
+/// imports, as they are in an object
+pub(crate) fn line_addresses(
+    uncompiled_text: &[String],
+    path: Option<&Path>,
+) -> Result<Vec<Option<usize>>, Vec<Diagnostic>> {
+    let unnumbered: Vec<String> = uncompiled_text
+        .iter()
+        .map(|line| match parse_line(line).line_number {
+            // Blanked out rather than removed, so columns stay the same
+            Some(number) => {
+                let columns = number.column..number.column + number.text.chars().count();
+                line.chars()
+                    .enumerate()
+                    .map(|(column, c)| if columns.contains(&column) { ' ' } else { c })
+                    .collect()
+            }
+            None => line.clone(),
+        })
+        .collect();
+    let file = path.map(|path| path.display().to_string());
+
+    let object = assemble_source(&unnumbered, path, true).map_err(|diagnostics| {
+        diagnostics
+            .into_iter()
+            .map(|mut diagnostic| {
+                let original = diagnostic
+                    .line
+                    .checked_sub(1)
+                    .and_then(|index| uncompiled_text.get(index));
+
+                if let (true, Some(original)) = (diagnostic.file == file, original) {
+                    diagnostic.source = original.clone();
+                }
+                diagnostic
+            })
+            .collect::<Vec<Diagnostic>>()
+    })?;
+    let mut addresses = vec![None; uncompiled_text.len()];
+
+    for (address, location) in object.assembly.source_map.iter() {
+        if location.file == file {
+            addresses[location.line - 1].get_or_insert(address);
+        }
+    }
+
+    Ok(addresses)
+}
+
+fn assemble_source(
+    uncompiled_text: &[String],
+    path: Option<&Path>,
+    relocatable: bool,
+) -> Result<Object, Vec<Diagnostic>> {
+    let mut state = IncludeState {
+        stack: Vec::new(),
+        included: HashSet::new(),
+        lines: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let directory = match path.and_then(|path| path.parent()) {
+        Some(parent) => parent.to_path_buf(),
+        None => PathBuf::new(),
+    };
+
+    if let Some(path) = path {
+        if let Ok(canonical) = path.canonicalize() {
+            state
+                .stack
+                .push((canonical.clone(), path.display().to_string()));
+            state.included.insert(canonical);
+        }
+    }
+
+    expand_includes(
+        uncompiled_text,
+        path.map(|path| path.display().to_string()),
+        &directory,
+        &mut state,
+    );
+
+    if !state.diagnostics.is_empty() {
+        return Err(state.diagnostics);
+    }
+
+    let mut diagnostics = Vec::new();
+    let lines = expand_macros(state.lines, &mut diagnostics);
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    let mut object = assemble_lines(&lines, relocatable)?;
+
+    if let Some(path) = path {
+        object.name = path.display().to_string();
+    }
+
+    Ok(object)
+}
+
+/// Assembles HMMM source in two passes. The first pass assigns an
+/// address to every instruction and data word, and records label
+/// and constant definitions. The second resolves references to them
+/// and encodes each instruction and data word.
+///
+/// Line numbers are optional, but if the first instruction is
+/// numbered then every instruction must be, and all numbers must
+/// match the address of the instruction. A numbered macro or
+/// pseudo-instruction call takes up every address it expands to.
+///
+/// Every word that refers to an address is recorded as a relocation,
+/// so the program can be linked as an object.
+///
+/// Assembly does not stop at the first error: every problem found
+/// is returned as a Diagnostic, in the order they appear in the program
+fn assemble_lines(lines: &[SourceLine], relocatable: bool) -> Result<Object, Vec<Diagnostic>> {
+    let parsed_lines: Vec<(usize, ParsedLine)> = lines
+        .iter()
+        .map(|line| parse_line(&line.text))
+        .enumerate()
+        .filter(|(_, parsed)| !parsed.is_empty())
+        .collect();
+
+    let is_numbered = parsed_lines
+        .iter()
+        .find(|(_, parsed)| {
+            parsed.line_number.is_some() || (parsed.mnemonic.is_some() && !is_directive(parsed))
+        })
+        .map(|(_, parsed)| parsed.line_number.is_some())
+        .unwrap_or(false);
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    // First pass: work out the address of every line and label
+    let mut symbols = SymbolTable {
+        relocatable,
+        ..SymbolTable::default()
+    };
+    let mut addresses: Vec<usize> = Vec::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    let mut address: usize = 0;
+
+    for (index, parsed) in &parsed_lines {
+        let raw_line = lines[*index].text.as_str();
+        let directive = parsed
+            .mnemonic
+            .as_ref()
+            .filter(|_| is_directive(parsed))
+            .map(|directive| directive.text.to_lowercase());
+
+        // .org moves the address before anything on its line is placed,
+        // so labels on the same line point to the new address
+        if directive.as_deref() == Some(".org") {
+            match layout_org(*index, raw_line, parsed, address, &symbols) {
+                Ok(new_address) => address = new_address,
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+
+        addresses.push(address);
+
+        if let Some(line_number) = &parsed.line_number {
+            if line_number.text.parse::<i128>() != Ok(address as i128) {
+                diagnostics.push(
+                    Diagnostic::at_token(
+                        CompileErr::InvalidLineNumber,
+                        *index,
+                        raw_line,
+                        line_number,
+                    )
+                    .suggest(format!("this line should be numbered {}", address)),
+                );
+            }
+        } else if let (true, None, Some(mnemonic), false) = (
+            is_numbered,
+            &directive,
+            &parsed.mnemonic,
+            // Numbered by the call line it was expanded from
+            lines[*index].continuation,
+        ) {
+            diagnostics.push(
+                Diagnostic::at_token(CompileErr::LineNumberNotPresent, *index, raw_line, mnemonic)
+                    .suggest(format!(
+                        "add the line number {} to the start of the line",
+                        address
+                    )),
+            );
+        }
+
+        for label in &parsed.labels {
+            if !is_valid_label(&label.text) {
+                diagnostics.push(
+                    Diagnostic::at_token(CompileErr::InvalidLabel, *index, raw_line, label).suggest(
+                        "labels must start with a letter or underscore, followed by letters, numbers or underscores"
+                            .to_string(),
+                    ),
+                );
+            } else if symbols.contains(&label.text) {
+                diagnostics.push(
+                    Diagnostic::at_token(CompileErr::DuplicateLabel, *index, raw_line, label)
+                        .suggest("rename one of the labels".to_string()),
+                );
+            } else {
+                symbols.labels.insert(label.text.clone(), address);
+            }
+        }
+
+        let size = match &directive {
+            Some(_) => match layout_directive(*index, raw_line, parsed, &mut symbols) {
+                Ok(size) => size,
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    0
+                }
+            },
+            None if parsed.line_number.is_some() || parsed.mnemonic.is_some() => 1,
+            None => 0,
+        };
+
+        if address <= 256 && address + size > 256 {
+            let token = parsed
+                .mnemonic
+                .as_ref()
+                .or(parsed.line_number.as_ref())
+                .unwrap();
+            diagnostics.push(
+                Diagnostic::at_token(CompileErr::ProgramTooLarge, *index, raw_line, token)
+                    .suggest("programs and their data can be at most 256 words long".to_string()),
+            );
+        }
+
+        sizes.push(size);
+        address += size;
+    }
+
+    // Second pass: resolve symbols and encode each instruction and data word
+    let mut compiled_text: Vec<Instruction> = Vec::new();
+    let mut source_map = SourceMap::new();
+    let mut relocations: Vec<Relocation> = Vec::new();
+
+    for (((index, parsed), address), size) in parsed_lines.iter().zip(addresses).zip(sizes) {
+        let raw_line = lines[*index].text.as_str();
+
+        // Fill any gap left by .org with blank data
+        if compiled_text.len() < address {
+            compiled_text.resize(address.min(256), Instruction::new_blank_data());
+        }
+
+        if parsed.mnemonic.is_none() {
+            if let Some(line_number) = &parsed.line_number {
+                diagnostics.push(
+                    Diagnostic::at_token(
+                        CompileErr::InstructionDoesNotExist,
+                        *index,
+                        raw_line,
+                        line_number,
+                    )
+                    .suggest("add an instruction after the line number".to_string()),
+                );
+            }
+            continue;
+        }
+
+        let words = if is_directive(parsed) {
+            assemble_directive(*index, raw_line, parsed, size, &symbols)
+        } else {
+            assemble_line(*index, raw_line, parsed, &symbols).map(|instruction| vec![instruction])
+        };
+
+        match words {
+            Ok(words) => {
+                let mnemonic = parsed.mnemonic.as_ref().unwrap();
+                let is_word = mnemonic.text.eq_ignore_ascii_case(".word");
+
+                // Each value of a .word is mapped to its own column
+                for offset in 0..words.len() {
+                    let column = match parsed.operands.get(offset) {
+                        Some(operand) if is_word => operand.column,
+                        _ => mnemonic.column,
+                    };
+
+                    source_map.insert(address + offset, lines[*index].location(column));
+                }
+
+                relocations.extend(line_relocations(parsed, address, size, &symbols));
+                compiled_text.extend(words);
+            }
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(Object {
+            name: String::new(),
+            assembly: Assembly {
+                instructions: compiled_text,
+                symbols: symbols.labels,
+                constants: symbols.constants,
+                source_map,
+            },
+            exports: symbols.exports,
+            relocations,
+        })
+    } else {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+        // Point each diagnostic back at the file and line it came from
+        Err(diagnostics
+            .into_iter()
+            .map(|diagnostic| lines[diagnostic.line - 1].locate(diagnostic))
+            .collect())
+    }
+}
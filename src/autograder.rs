@@ -2,6 +2,7 @@ use super::simulator::*;
 use super::*;
 use csv;
 use chrono;
+use std::fmt;
 use std::fs;
 // Maximum number of iterations the autograder will
 // tolerate on each grade case before declaring the
@@ -52,8 +53,19 @@ impl GradeCase {
         self.test_case.clone()
     }
 
+    pub fn get_exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    pub fn get_exit_name(&self) -> String {
+        self.exit_name.clone()
+    }
+
     pub fn test_case_matches(&self) -> bool {
-        self.test_case.clone().unwrap().outputs == self.outputs
+        match &self.test_case {
+            Some(test_case) => test_case.outputs == self.outputs,
+            None => false,
+        }
     }
 
     pub fn passes(&self) -> bool {
@@ -77,133 +89,119 @@ pub struct AutoGrader {
     pub results: Vec<Vec<GradeCase>>,
 }
 
+/// Reasons the AutoGrader can fail to start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoGraderErr {
+    InvalidTestCase,
+    CannotReadDirectory,
+    CannotReadFile,
+}
+
+impl fmt::Display for AutoGraderErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            AutoGraderErr::InvalidTestCase => "test case string is not formatted correctly",
+            AutoGraderErr::CannotReadDirectory => "cannot read input directory",
+            AutoGraderErr::CannotReadFile => "cannot read file in input directory",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for AutoGraderErr {}
+
+/// Parses a comma separated list of numbers, allowing it to be empty
+fn parse_numbers(list: &str) -> Result<Vec<i16>, AutoGraderErr> {
+    list.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<i16>().map_err(|_| AutoGraderErr::InvalidTestCase))
+        .collect()
+}
+
 impl AutoGrader {
-    pub fn new_from_cmd(input_dir: &str, test_case_string: &str) -> Self {
+    pub fn new_from_cmd(input_dir: &str, test_case_string: &str) -> Result<Self, AutoGraderErr> {
         let mut test_cases: Vec<TestCase> = Vec::new();
         let test_case_string = test_case_string.trim_end_matches(';');
-        if test_case_string.contains(';') {
-            for test_case in test_case_string.split(';') {
-                let test_case_split: Vec<String> =
-                    test_case.split('|').map(|x| x.to_string()).collect();
-                test_cases.push(TestCase {
-                    inputs: test_case_split
-                        .first()
-                        .unwrap()
-                        .split(',')
-                        .map(|x| x.trim().parse::<i16>().unwrap())
-                        .collect(),
-                    outputs: test_case_split
-                        .last()
-                        .unwrap()
-                        .split(',')
-                        .map(|x| x.trim().parse::<i16>().unwrap())
-                        .collect(),
-                });
+
+        for test_case in test_case_string.split(';') {
+            let test_case_split: Vec<&str> = test_case.split('|').collect();
+
+            if test_case_split.len() != 2 {
+                return Err(AutoGraderErr::InvalidTestCase);
             }
-        } else {
-            let test_case_split: Vec<String> =
-                test_case_string.split('|').map(|x| x.to_string()).collect();
+
             test_cases.push(TestCase {
-                inputs: test_case_split
-                    .first()
-                    .unwrap()
-                    .split(',')
-                    .map(|x| x.trim().parse::<i16>().unwrap())
-                    .collect(),
-                outputs: test_case_split
-                    .last()
-                    .unwrap()
-                    .split(',')
-                    .map(|x| x.trim().parse::<i16>().unwrap())
-                    .collect(),
+                inputs: parse_numbers(test_case_split[0])?,
+                outputs: parse_numbers(test_case_split[1])?,
             });
         }
 
         // Open dir and perform load_file on each .hmmm file
         let mut grade_cases: Vec<GradeCase> = Vec::new();
         let mut file_names: Vec<String> = Vec::new();
-        for file in fs::read_dir(input_dir).unwrap() {
-            let file_path = file.unwrap().path();
-            if file_path.to_str().unwrap().ends_with(UNCOMPILED) {
-                let input_file = load_file(file_path.to_str().unwrap().clone()).unwrap();
-                let instructions = Simulator::compile_hmmm(input_file, true);
-                let grade_case: GradeCase;
+        let directory = fs::read_dir(input_dir).map_err(|_| AutoGraderErr::CannotReadDirectory)?;
+
+        for file in directory {
+            let file_path = file.map_err(|_| AutoGraderErr::CannotReadFile)?.path();
+            let file_path_string = file_path.to_string_lossy().to_string();
+
+            if file_path_string.ends_with(UNCOMPILED) {
+                let input_file =
+                    load_file(&file_path_string).map_err(|_| AutoGraderErr::CannotReadFile)?;
+                let instructions = assembler::assemble_from(&input_file, &file_path_string)
+                    .map(|assembly| assembly.instructions);
 
                 file_names.push(
                     file_path
                         .as_path()
                         .file_name()
-                        .unwrap()
+                        .unwrap_or_default()
                         .to_string_lossy()
                         .into(),
                 );
 
-                if instructions.is_err() {
-                    grade_case = GradeCase {
+                let grade_case = match instructions {
+                    Err(diagnostics) => GradeCase {
                         sim: None,
                         test_case: None,
                         outputs: Vec::new(),
-                        exit_code: instructions.clone().unwrap_err().as_code(),
-                        exit_name: format!("{:?}", instructions.clone().unwrap_err()),
-                    };
-                } else {
-                    grade_case = GradeCase {
-                        sim: Some(Simulator::new_headless(instructions.unwrap())),
+                        exit_code: diagnostics[0].error.as_code(),
+                        exit_name: format!("{:?}", diagnostics[0].error),
+                    },
+                    Ok(instructions) => GradeCase {
+                        sim: Some(Simulator::new_headless(instructions)),
                         test_case: None,
                         outputs: Vec::new(),
                         exit_code: -1,
                         exit_name: "".to_string(),
-                    };
-                }
+                    },
+                };
 
                 grade_cases.push(grade_case);
             }
         }
 
-        AutoGrader {
+        Ok(AutoGrader {
             file_names,
             test_cases,
             grade_cases,
             results: Vec::new(),
-        }
+        })
     }
 
+    /// Grades every file against every test case. Results are stored
+    /// in self.results, indexed by test case and then by file
     pub fn grade_all(&mut self) {
         let mut results: Vec<Vec<GradeCase>> = Vec::new();
         for test_case in self.test_cases.clone() {
-            println!("{} [{}]", "Grading Testcase".bold().blue(), test_case.as_string().bold());
-
             let mut test_case_results: Vec<GradeCase> = Vec::new();
 
             // Don't modify self, so we can reuse grade_cases
-            let grade_cases = self.grade_cases.clone();
-
-            let mut i = 0;
-            for mut grade_case in grade_cases {
+            for mut grade_case in self.grade_cases.clone() {
                 grade_case.set_test_case(test_case.clone());
-                
-                let grade_result = AutoGrader::grade_single(grade_case);
-
-                let grade_result_string: String;
-
-                if grade_result.exit_code != 0 {
-                    grade_result_string = format!("{} [{}]", "FAILED".bold().red(), grade_result.exit_name);
-                } else if grade_result.exit_code == 0 && !grade_result.test_case_matches() {
-                    grade_result_string = format!("{} [{}]", "FAILED".bold().red(), "Outputs don't match");
-                } else {
-                    grade_result_string = format!("{} [{}]", "PASSED".bold().green(), grade_result.exit_name);
-                }
-
-                test_case_results.push(grade_result);
-
-                println!(
-                    "- {} {:45} {} {}",
-                    "Graded".bold().green(),
-                    self.file_names[i],
-                    ":".bold(),
-                    grade_result_string,
-                );
-                i += 1;
+                test_case_results.push(AutoGrader::grade_single(grade_case));
             }
             results.push(test_case_results);
         }
@@ -214,122 +212,42 @@ impl AutoGrader {
     pub fn grade_single(grade_case: GradeCase) -> GradeCase {
         let mut iterations_left = AUTOGRADER_MAX_ITERATIONS;
         let sim = grade_case.sim.clone();
-        let test_case = grade_case.get_test_case().unwrap();
+        let test_case = grade_case.get_test_case().unwrap_or(TestCase {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        });
         // If the simulator failed on compile, just return it
-        if sim.is_none() {
-            grade_case
-        } else {
-            let mut sim = sim.unwrap();
-            sim.set_inputs(test_case.inputs.clone());
-
-            while iterations_left > 0 {
-                let step_result = sim.step();
-                if step_result.is_err() {
-                    let outputs = sim.get_outputs();
-
-                    return GradeCase {
-                        sim: Some(sim),
-                        test_case: Some(test_case),
-                        outputs,
-                        exit_code: step_result.clone().unwrap_err().as_code(),
-                        exit_name: format!("{:?}", step_result.unwrap_err()),
-                    };
-                }
-
-                iterations_left -= 1;
-            }
-            let outputs = sim.get_outputs();
-
-            return GradeCase {
-                sim: Some(sim),
-                test_case: Some(test_case),
-                outputs,
-                exit_code: RuntimeErr::MaximumIterationsReached.as_code(),
-                exit_name: format!("{:?}", RuntimeErr::MaximumIterationsReached),
-            };
-        }
-    }
-
-    pub fn print_results(&self) {
-        let top_line =
-            "█▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀█";
-        let bottom_line =
-            "█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄█";
-
-        println!("\n{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
-        println!(
-            "{}{}{}",
-            "████".yellow(),
-            "       GRADING SUCCESSFUL       ".green().bold(),
-            "████".yellow()
-        );
-        println!("{}", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
-        println!();
-        println!("{}", top_line);
-        println!(
-            "█ {}                                  █ {} █ {} █ {} █ {} █",
-            "Name of File".bold(),
-            "# Error Cases".bold(),
-            "# Fail Cases".bold(),
-            "# Pass Cases".bold(),
-            "Pass/Fail".bold()
-        );
-        println!("{}", bottom_line);
-        println!();
-        println!("{}", top_line);
-        for i in 0..self.results[0].len() {
-            let grade_cases_all = self
-                .results
-                .iter()
-                .map(|result| result[i].clone())
-                .collect::<Vec<GradeCase>>();
-
-            // Cases that failed with a runtime error
-            let cases_errored: Vec<&GradeCase> = grade_cases_all
-                .iter()
-                .filter(|x| x.exit_code != 0)
-                .collect();
-
-            // Cases that did not match expected test case
-            let cases_failed: Vec<&GradeCase> = grade_cases_all
-                .iter()
-                .filter(|x| x.exit_code == 0 && !x.test_case_matches())
-                .collect();
-
-            // Cases that passed
-            let cases_passed: Vec<&GradeCase> = grade_cases_all
-                .iter()
-                .filter(|x| x.passes())
-                .collect();
-
-            let pass_fail_emoji: ColoredString;
-
-            if cases_passed.len() == self.results.len() {
-                pass_fail_emoji = "P".to_string().bold().green();
-            } else {
-                pass_fail_emoji = "F".to_string().bold().red();
-            }
-            let mut output_string = format!(
-                "█ {:45} █ {:13} █ {:12} █ {:12} █ {}  {:6} █",
-                self.file_names[i],
-                cases_errored.len(),
-                cases_failed.len(),
-                cases_passed.len(),
-                pass_fail_emoji,
-                format!("{}/{}", cases_passed.len(), self.results.len(),),
-            );
-
-            // Increase readability by making every other line a different color
-            if i % 2 == 0 {
-                // "bright black" is just grey
-                output_string = format!("{}", output_string.on_bright_black());
+        let mut sim = match sim {
+            Some(sim) => sim,
+            None => return grade_case,
+        };
+
+        sim.set_inputs(test_case.inputs.clone());
+
+        while iterations_left > 0 {
+            if let Err(err) = sim.step() {
+                let outputs = sim.get_outputs();
+
+                return GradeCase {
+                    sim: Some(sim),
+                    test_case: Some(test_case),
+                    outputs,
+                    exit_code: err.as_code(),
+                    exit_name: format!("{:?}", err.kind),
+                };
             }
 
-            output_string = format!("{}", output_string.bold());
-
-            println!("{}", output_string);
+            iterations_left -= 1;
+        }
+        let outputs = sim.get_outputs();
+
+        GradeCase {
+            sim: Some(sim),
+            test_case: Some(test_case),
+            outputs,
+            exit_code: RuntimeErr::MaximumIterationsReached.as_code(),
+            exit_name: format!("{:?}", RuntimeErr::MaximumIterationsReached),
         }
-        println!("{}", bottom_line);
     }
 
     /// Exports the GradeResults to a CSV file
@@ -342,15 +260,19 @@ impl AutoGrader {
         .from_path(&out_path)?;
 
         // Write the header
-        wtr.write_record(&["File Name", "Test Case", "Exit Code", "Exit String", "Pass/Fail"])?;
+        wtr.write_record(["File Name", "Test Case", "Exit Code", "Exit String", "Pass/Fail"])?;
 
         // Write the results
-        for i in 0..self.results[0].len() {
+        for i in 0..self.file_names.len() {
             for j in 0..self.results.len() {
                 let grade_case = &self.results[j][i];
-                wtr.write_record(&[
+                wtr.write_record([
                     &self.file_names[i],
-                    &grade_case.test_case.clone().unwrap().as_string(),
+                    &grade_case
+                        .test_case
+                        .as_ref()
+                        .map(|test_case| test_case.as_string())
+                        .unwrap_or_default(),
                     &grade_case.exit_code.to_string(),
                     &grade_case.exit_name,
                     &grade_case.passes_as_string(),
@@ -0,0 +1,323 @@
+use super::simulator::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A register, or a word of memory, that can be watched or compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    Register(u8),
+    Memory(u8),
+}
+
+impl Location {
+    /// Current value of the location. Memory is read as a number,
+    /// whatever it holds
+    pub fn value(&self, sim: &Simulator) -> i16 {
+        match self {
+            Location::Register(register) => sim.get_register(*register as usize).unwrap_or(0),
+            Location::Memory(address) => sim.get_word(*address as usize).unwrap_or(0) as i16,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Register(register) => write!(f, "r{}", register),
+            Location::Memory(address) => write!(f, "[{}]", address),
+        }
+    }
+}
+
+/// Reads "r3" as a register, and "[200]" as a word of memory
+impl FromStr for Location {
+    type Err = ConditionErr;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+
+        if let Some(address) = text
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            match parse_literal(address.trim()) {
+                Some(address @ 0..=255) => Ok(Location::Memory(address as u8)),
+                _ => Err(ConditionErr::InvalidOperand),
+            }
+        } else {
+            match text.strip_prefix(&['r', 'R'][..]).map(str::parse::<u8>) {
+                Some(Ok(register @ 0..=15)) => Ok(Location::Register(register)),
+                _ => Err(ConditionErr::InvalidOperand),
+            }
+        }
+    }
+}
+
+/// How a watched location has to be used for a watchpoint to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// An instruction reads it
+    Read,
+    /// An instruction writes it, even with the value it already had
+    Write,
+    /// Its value is different after a step
+    Change,
+}
+
+/// One side of a condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Location(Location),
+    ProgramCounter,
+    Number(i32),
+}
+
+impl Operand {
+    fn value(&self, sim: &Simulator) -> i32 {
+        match self {
+            Operand::Location(location) => location.value(sim) as i32,
+            Operand::ProgramCounter => sim.get_program_counter() as i32,
+            Operand::Number(number) => *number,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Location(location) => write!(f, "{}", location),
+            Operand::ProgramCounter => write!(f, "pc"),
+            Operand::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = ConditionErr;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+
+        if text.eq_ignore_ascii_case("pc") {
+            Ok(Operand::ProgramCounter)
+        } else if let Some(number) = parse_literal(text) {
+            Ok(Operand::Number(number))
+        } else {
+            text.parse().map(Operand::Location)
+        }
+    }
+}
+
+/// Compares the values on either side of a condition
+type Comparison = fn(i32, i32) -> bool;
+
+/// Comparisons a condition can make, written as in Rust
+static COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", |a, b| a == b),
+    ("!=", |a, b| a != b),
+    ("<=", |a, b| a <= b),
+    (">=", |a, b| a >= b),
+    ("<", |a, b| a < b),
+    (">", |a, b| a > b),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionErr {
+    MissingComparison,
+    InvalidOperand,
+}
+
+impl fmt::Display for ConditionErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ConditionErr::MissingComparison => {
+                "condition needs one of ==, !=, <, <=, > or >= between two values"
+            }
+            ConditionErr::InvalidOperand => {
+                "expected a register (r3), a memory address ([200]), pc or a number"
+            }
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for ConditionErr {}
+
+/// A comparison between two values, such as "r3 < 0", "[200] == r1"
+/// or "pc >= 0x10". Numbers are written as they are in a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub left: Operand,
+    /// Index into COMPARISONS
+    comparison: usize,
+    pub right: Operand,
+}
+
+impl Condition {
+    pub fn evaluate(&self, sim: &Simulator) -> bool {
+        (COMPARISONS[self.comparison].1)(self.left.value(sim), self.right.value(sim))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.left, COMPARISONS[self.comparison].0, self.right
+        )
+    }
+}
+
+/// Length of the operand at the start of `text`, so a comparison symbol
+/// inside it, such as in '<', isn't taken for the comparison
+fn operand_length(text: &str) -> usize {
+    let end = match text.chars().next() {
+        // The closing quote comes after one character, or after an escape
+        Some('\'') => {
+            let closing = if text[1..].starts_with('\\') { 3 } else { 2 };
+
+            text.char_indices()
+                .nth(closing)
+                .filter(|(_, c)| *c == '\'')
+                .map(|(position, _)| position + 1)
+        }
+        Some('[') => text.find(']').map(|position| position + 1),
+        _ => None,
+    };
+
+    end.unwrap_or_else(|| {
+        text.find(|c: char| c.is_whitespace() || "=!<>".contains(c))
+            .unwrap_or(text.len())
+    })
+}
+
+impl FromStr for Condition {
+    type Err = ConditionErr;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim_start();
+        let (left, rest) = text.split_at(operand_length(text));
+        let rest = rest.trim_start();
+
+        // Two character comparisons come first, so "<=" isn't read as "<"
+        let comparison = COMPARISONS
+            .iter()
+            .position(|(symbol, _)| rest.starts_with(symbol))
+            .ok_or(ConditionErr::MissingComparison)?;
+
+        Ok(Condition {
+            left: left.parse()?,
+            comparison,
+            right: rest[COMPARISONS[comparison].0.len()..].parse()?,
+        })
+    }
+}
+
+/// Stops a run before the instruction at `address` is run. Breakpoints
+/// with a condition only stop when it's true, and the first
+/// `ignore_count` hits are passed over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: usize,
+    pub condition: Option<Condition>,
+    pub ignore_count: u64,
+    /// Times the breakpoint has been reached with its condition true
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn new(address: usize) -> Self {
+        Breakpoint {
+            address,
+            condition: None,
+            ignore_count: 0,
+            hits: 0,
+        }
+    }
+
+    /// True if the simulator is at the breakpoint, and its condition is true
+    pub fn applies(&self, sim: &Simulator) -> bool {
+        sim.get_program_counter() == self.address
+            && match &self.condition {
+                Some(condition) => condition.evaluate(sim),
+                None => true,
+            }
+    }
+}
+
+/// Stops a run after a step uses a register or word of memory in the
+/// way given. The first `ignore_count` hits are passed over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub location: Location,
+    pub access: Access,
+    pub ignore_count: u64,
+    /// Times the location has been used in the way watched for
+    pub hits: u64,
+}
+
+impl Watchpoint {
+    pub fn new(location: Location, access: Access) -> Self {
+        Watchpoint {
+            location,
+            access,
+            ignore_count: 0,
+            hits: 0,
+        }
+    }
+}
+
+/// Why run_until stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// Reached the breakpoint with this id, and hasn't run its instruction yet
+    Breakpoint(usize),
+    /// The last step used the location watched by the watchpoint with this
+    /// id, leaving it holding `value`
+    Watchpoint { id: usize, value: i16 },
+    /// Ran as many steps as it was allowed to
+    StepLimit,
+    /// The program halted
+    Halted,
+    /// The program stopped with an error
+    Error(RuntimeError),
+}
+
+/// Every breakpoint and watchpoint set on a simulator, each with an id
+/// that's unique between the two
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StopPoints {
+    next_id: usize,
+    pub breakpoints: BTreeMap<usize, Breakpoint>,
+    pub watchpoints: BTreeMap<usize, Watchpoint>,
+}
+
+impl StopPoints {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        self.breakpoints.insert(self.next_id, breakpoint);
+        self.next_id
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.next_id += 1;
+        self.watchpoints.insert(self.next_id, watchpoint);
+        self.next_id
+    }
+
+    /// Removes the breakpoint or watchpoint with an id, returning
+    /// false if there isn't one
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.breakpoints.remove(&id).is_some() || self.watchpoints.remove(&id).is_some()
+    }
+
+    /// True if a watchpoint needs to know which locations a step reads or writes
+    pub(crate) fn watches_accesses(&self) -> bool {
+        self.watchpoints
+            .values()
+            .any(|watchpoint| watchpoint.access != Access::Change)
+    }
+}
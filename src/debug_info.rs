@@ -0,0 +1,204 @@
+use super::assembler::{Assembly, Diagnostic, SourceLocation};
+use super::simulator::*;
+
+/// First line of the debug section in a .hb file
+pub const DEBUG_HEADER: &str = "# debug 1";
+
+/// Lines that aren't words in a .hb file start with this
+const COMMENT: char = '#';
+
+/// True if a line of a .hb file is a comment or debug info, rather than a word
+pub fn is_debug_line(line: &str) -> bool {
+    line.starts_with(COMMENT)
+}
+
+/// Writes a program's symbols and source map as lines to go after its words
+/// in a .hb file. Each line starts with '#', so readers that don't know about
+/// debug info can skip them. Anything that can have spaces in it is written
+/// last on its line:
+///
+/// ```text
+/// # debug 1
+/// # file <path>
+/// # label <name> <address>
+/// # constant <name> <value>
+/// # line <address> <file index, or -> <line> <column> <text>
+/// # comment <address> <comment>
+/// ```
+///
+/// Files are numbered in the order they're listed. Programs with no symbols
+/// or source map have no debug section
+pub fn debug_section(assembly: &Assembly) -> Vec<String> {
+    if assembly.symbols.is_empty()
+        && assembly.constants.is_empty()
+        && assembly.source_map.is_empty()
+    {
+        return Vec::new();
+    }
+
+    let mut lines = vec![DEBUG_HEADER.to_string()];
+    let mut files: Vec<&str> = Vec::new();
+
+    for (_, location) in assembly.source_map.iter() {
+        if let Some(file) = &location.file {
+            if !files.contains(&file.as_str()) {
+                files.push(file);
+                lines.push(format!("# file {}", file));
+            }
+        }
+    }
+
+    for (name, address) in &assembly.symbols {
+        lines.push(format!("# label {} {}", name, address));
+    }
+
+    for (name, value) in &assembly.constants {
+        lines.push(format!("# constant {} {}", name, value));
+    }
+
+    for (address, location) in assembly.source_map.iter() {
+        let file = match &location.file {
+            Some(file) => files
+                .iter()
+                .position(|name| name == file)
+                .unwrap()
+                .to_string(),
+            None => "-".to_string(),
+        };
+
+        lines.push(format!(
+            "# line {} {} {} {} {}",
+            address, file, location.line, location.column, location.text
+        ));
+
+        if let Some(comment) = &location.comment {
+            lines.push(format!("# comment {} {}", address, comment));
+        }
+    }
+
+    lines
+}
+
+/// Reads the debug section of a .hb file into `assembly`, if it has one.
+/// Lines before the section, and lines with a kind that isn't known,
+/// are skipped. Every line that can't be read is reported
+pub fn read_debug_section(
+    raw_binary: &[String],
+    assembly: &mut Assembly,
+) -> Result<(), Vec<Diagnostic>> {
+    let start = match raw_binary
+        .iter()
+        .position(|line| line.starts_with("# debug "))
+    {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+
+    let header = &raw_binary[start];
+
+    if header != DEBUG_HEADER {
+        let span = 0..header.chars().count();
+        return Err(vec![Diagnostic::new(
+            CompileErr::UnsupportedVersion,
+            start,
+            header,
+            span,
+        )]);
+    }
+
+    let mut files: Vec<String> = Vec::new();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for (index, line) in raw_binary.iter().enumerate().skip(start + 1) {
+        if !is_debug_line(line) {
+            continue;
+        }
+
+        if read_debug_line(line, &mut files, assembly).is_none() {
+            let span = 0..line.chars().count();
+            diagnostics.push(Diagnostic::new(
+                CompileErr::CorruptedBinary,
+                index,
+                line,
+                span,
+            ));
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Reads one line of a debug section, returning None if it's malformed
+fn read_debug_line(line: &str, files: &mut Vec<String>, assembly: &mut Assembly) -> Option<()> {
+    let line = line.trim_start_matches(COMMENT).trim_start();
+    let (kind, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+
+    match kind {
+        "file" => files.push(rest.to_string()),
+        "label" => {
+            let (name, address) = rest.split_once(' ')?;
+            assembly
+                .symbols
+                .insert(name.to_string(), address.parse().ok()?);
+        }
+        "constant" => {
+            let (name, value) = rest.split_once(' ')?;
+            assembly
+                .constants
+                .insert(name.to_string(), value.parse().ok()?);
+        }
+        "line" => {
+            let fields: Vec<&str> = rest.splitn(5, ' ').collect();
+
+            if fields.len() < 4 {
+                return None;
+            }
+
+            let file = match fields[1] {
+                "-" => None,
+                index => Some(files.get(index.parse::<usize>().ok()?)?.clone()),
+            };
+
+            assembly.source_map.insert(
+                fields[0].parse().ok()?,
+                SourceLocation {
+                    file,
+                    line: fields[2].parse().ok()?,
+                    column: fields[3].parse().ok()?,
+                    text: fields.get(4).unwrap_or(&"").to_string(),
+                    comment: None,
+                },
+            );
+        }
+        "comment" => {
+            let (address, comment) = rest.split_once(' ')?;
+            let address: usize = address.parse().ok()?;
+            let location = assembly.source_map.get(address)?.clone();
+
+            assembly.source_map.insert(
+                address,
+                SourceLocation {
+                    comment: Some(comment.to_string()),
+                    ..location
+                },
+            );
+        }
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// Removes the debug section and any other comments from a .hb file, leaving only its words
+pub fn strip_debug_section(raw_binary: &[String]) -> Vec<String> {
+    raw_binary
+        .iter()
+        .filter(|line| !is_debug_line(line))
+        .cloned()
+        .collect()
+}
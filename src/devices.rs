@@ -0,0 +1,290 @@
+use super::simulator::RuntimeErr;
+use colored::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use terminal::Action;
+
+/// Where the numbers for read instructions come from
+pub trait InputSource {
+    /// The next number for the program. Numbers that don't fit in a
+    /// register are fitted by the simulator's overflow policy. Sources
+    /// that have run out return TooManyInputs
+    fn read_number(&mut self) -> Result<i32, RuntimeErr>;
+
+    /// Numbers still to be read, if they're known ahead of time.
+    /// Snapshots keep these, so a restored run reads the same input
+    fn remaining(&self) -> Option<Vec<i16>> {
+        None
+    }
+}
+
+/// Where the numbers from write instructions go
+pub trait OutputSink {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr>;
+}
+
+/// Numbers given ahead of time, read from the front
+impl InputSource for VecDeque<i16> {
+    fn read_number(&mut self) -> Result<i32, RuntimeErr> {
+        self.pop_front()
+            .map(i32::from)
+            .ok_or(RuntimeErr::TooManyInputs)
+    }
+
+    fn remaining(&self) -> Option<Vec<i16>> {
+        Some(self.iter().copied().collect())
+    }
+}
+
+/// Keeps every number written, in order
+impl OutputSink for Vec<i16> {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr> {
+        self.push(number);
+        Ok(())
+    }
+}
+
+/// Lets a device be given to a simulator while keeping a handle
+/// to it, eg: to look at the numbers a Vec has collected
+impl<T: InputSource + ?Sized> InputSource for Rc<RefCell<T>> {
+    fn read_number(&mut self) -> Result<i32, RuntimeErr> {
+        self.borrow_mut().read_number()
+    }
+
+    fn remaining(&self) -> Option<Vec<i16>> {
+        self.borrow().remaining()
+    }
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for Rc<RefCell<T>> {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr> {
+        self.borrow_mut().write_number(number)
+    }
+}
+
+/// Asks the user for each number and prints each one written. In the
+/// debugger, numbers are read and printed in their place on its screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Console {
+    debugger: bool,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console::default()
+    }
+
+    /// A console that draws on the debugger's screen
+    pub fn debugger() -> Self {
+        Console { debugger: true }
+    }
+
+    /// Reads a line from stdin, giving None at the end of input
+    fn read_line(&self) -> Option<String> {
+        let mut line = String::new();
+
+        if self.debugger {
+            let w = terminal::stdout();
+            let _ = w.act(Action::ShowCursor);
+            let _ = w.act(Action::EnableBlinking);
+            let _ = w.act(Action::MoveCursorTo(0, 28));
+            print!("{}", "Enter number:".on_yellow().black());
+            let _ = w.act(Action::MoveCursorTo(14, 28));
+            print!("                                 ");
+            let _ = w.act(Action::MoveCursorTo(14, 28));
+            let read = io::stdin().lock().read_line(&mut line);
+            let _ = w.act(Action::DisableBlinking);
+            let _ = w.act(Action::HideCursor);
+            read.ok()?;
+        } else {
+            println!("{}", "Enter number:".on_yellow().black());
+            io::stdin().read_line(&mut line).ok()?;
+        }
+
+        if line.is_empty() {
+            None
+        } else {
+            Some(line.trim().to_string())
+        }
+    }
+
+    /// Shows a message under the prompt, or clears it
+    fn show_message(&self, message: &str) {
+        if self.debugger {
+            let _ = terminal::stdout().act(Action::MoveCursorTo(16, 29));
+            print!("{:<40}", message);
+        } else if !message.is_empty() {
+            println!("{}", message);
+        }
+    }
+}
+
+impl InputSource for Console {
+    /// Asks until a number is given. "q" halts the program, and
+    /// the end of input is the same as running out of inputs
+    fn read_number(&mut self) -> Result<i32, RuntimeErr> {
+        loop {
+            let line = self.read_line().ok_or(RuntimeErr::TooManyInputs)?;
+
+            if line == "q" {
+                return Err(RuntimeErr::Halt);
+            }
+
+            if let Ok(number) = line.parse::<i32>() {
+                self.show_message("");
+                return Ok(number);
+            }
+
+            self.show_message("Invalid number! Please try again...");
+        }
+    }
+}
+
+impl OutputSink for Console {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr> {
+        if self.debugger {
+            let _ = terminal::stdout().act(Action::MoveCursorTo(50, 8));
+            print!("{:<10}", number);
+        } else {
+            println!("{}\n{}", "HMMM OUT:".on_green().black(), number);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads numbers from a file, pipe or anything else that can be read
+/// by line. Numbers can be split across lines or by spaces, and blank
+/// lines are skipped
+pub struct Reader<R: BufRead> {
+    reader: R,
+    pending: VecDeque<String>,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Reader {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for Reader<R> {
+    fn read_number(&mut self) -> Result<i32, RuntimeErr> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return Err(RuntimeErr::TooManyInputs),
+                Ok(_) => self
+                    .pending
+                    .extend(line.split_whitespace().map(str::to_string)),
+                Err(_) => return Err(RuntimeErr::InvalidInput),
+            }
+        }
+
+        let word = self.pending.pop_front().unwrap_or_default();
+        word.parse().map_err(|_| RuntimeErr::InvalidInput)
+    }
+}
+
+impl<R: BufRead> fmt::Debug for Reader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+/// Writes each number on its own line to a file, pipe or anything
+/// else that can be written to. Use io::sink() to throw them away
+pub struct Writer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Writer { writer }
+    }
+
+    /// Gives back what was being written to
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for Writer<W> {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr> {
+        writeln!(self.writer, "{}", number)
+            .and_then(|_| self.writer.flush())
+            .map_err(|_| RuntimeErr::OutputFailed)
+    }
+}
+
+impl<W: Write> fmt::Debug for Writer<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Writer").finish()
+    }
+}
+
+/// Hands each read or write to a closure. An input closure returns
+/// None when it has no more numbers to give, and can return any
+/// integer that converts to an i32
+pub struct Callback<F> {
+    callback: F,
+}
+
+impl<F> Callback<F> {
+    pub fn new(callback: F) -> Self {
+        Callback { callback }
+    }
+}
+
+impl<F: FnMut() -> Option<T>, T: Into<i32>> InputSource for Callback<F> {
+    fn read_number(&mut self) -> Result<i32, RuntimeErr> {
+        (self.callback)()
+            .map(Into::into)
+            .ok_or(RuntimeErr::TooManyInputs)
+    }
+}
+
+impl<F: FnMut(i16)> OutputSink for Callback<F> {
+    fn write_number(&mut self, number: i16) -> Result<(), RuntimeErr> {
+        (self.callback)(number);
+        Ok(())
+    }
+}
+
+impl<F> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Callback").finish()
+    }
+}
+
+/// A device held by a simulator. Copies of a simulator share their
+/// devices, and are equal when they use the same ones
+pub(crate) struct Device<T: ?Sized>(pub Rc<RefCell<T>>);
+
+impl<T: ?Sized> Clone for Device<T> {
+    fn clone(&self) -> Self {
+        Device(Rc::clone(&self.0))
+    }
+}
+
+impl<T: ?Sized> PartialEq for Device<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: ?Sized> Eq for Device<T> {}
+
+impl<T: ?Sized> fmt::Debug for Device<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Device")
+    }
+}
@@ -0,0 +1,225 @@
+use super::assembler::Assembly;
+use super::formatter::format_lines;
+use super::lint::{reachable_from, Decoded};
+use super::simulator::*;
+use std::collections::BTreeMap;
+
+/// Why an address is given a label. When an address is
+/// used in more than one way, the highest one names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reference {
+    /// Read or written by loadn or storen
+    Data,
+    /// Target of a jump
+    Jump,
+    /// Target of a calln
+    Call,
+}
+
+impl Reference {
+    fn label(&self, address: usize) -> String {
+        match self {
+            Reference::Data => format!("data_{}", address),
+            Reference::Jump => format!("label_{}", address),
+            Reference::Call => format!("sub_{}", address),
+        }
+    }
+}
+
+/// Operands of an instruction as they would be written in source,
+/// read from its binary. Registers are written as "rX", and numbers
+/// in decimal
+fn operands(instruction: &Instruction) -> Vec<String> {
+    let bits = instruction.binary_contents.join("");
+    let read = |position: usize, length: usize| {
+        u16::from_str_radix(&bits[position..position + length], 2).unwrap_or(0)
+    };
+
+    // Data words have no opcode, so their argument starts at bit 0
+    let mut position = if instruction.instruction_type.names[0] == "data" {
+        0
+    } else {
+        4
+    };
+    let mut found = Vec::new();
+
+    for argument in instruction.instruction_type.arguments.chars() {
+        match argument {
+            'r' => found.push(format!("r{}", read(position, 4))),
+            's' => found.push((read(position, 8) as u8 as i8).to_string()),
+            'u' => found.push(read(position, 8).to_string()),
+            'n' => found.push((read(position, 16) as i16).to_string()),
+            _ => {}
+        }
+
+        position += match argument {
+            's' | 'u' => 8,
+            'n' => 16,
+            _ => 4,
+        };
+    }
+
+    found
+}
+
+/// The instruction's human_explanation, with its operands filled in
+fn explain(instruction: &Instruction, operands: &[String]) -> String {
+    let mut operands = operands.iter();
+
+    instruction
+        .instruction_type
+        .human_explanation
+        .split('_')
+        .enumerate()
+        .map(|(index, part)| match index {
+            0 => part.to_string(),
+            _ => format!("{}{}", operands.next().map_or("_", |x| x.as_str()), part),
+        })
+        .collect()
+}
+
+/// Turns a compiled program back into annotated HMMM source. Words that
+/// can be reached from address 0 are written as instructions, and the
+/// rest as .word data. Jump and call targets, and data used by loadn and
+/// storen, are given labels (the program's own, if it came with symbols),
+/// each subroutine entered through calln is marked with the addresses it's
+/// called from, and every instruction has a comment explaining what it does.
+///
+/// The result assembles back into the same binary
+pub fn disassemble(assembly: &Assembly) -> Vec<String> {
+    let instructions = &assembly.instructions;
+    let program: Vec<Decoded> = instructions.iter().map(Decoded::new).collect();
+    let reached = reachable_from(&program, 0);
+    let is_code = |address: usize| reached.contains(&address) && !program[address].is_data();
+
+    // Work out which addresses need a label, and who calls each subroutine
+    let mut references: BTreeMap<usize, Reference> = BTreeMap::new();
+    let mut callers: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+    for &address in reached.iter().filter(|address| is_code(**address)) {
+        let decoded = &program[address];
+
+        let reference = match decoded.name {
+            "calln" => Reference::Call,
+            "loadn" | "storen" => Reference::Data,
+            _ if decoded.target().is_some() => Reference::Jump,
+            _ => continue,
+        };
+        let target = decoded.immediate as usize;
+
+        if target >= program.len() || (reference == Reference::Data && is_code(target)) {
+            continue;
+        }
+
+        let kind = references.entry(target).or_insert(reference);
+        *kind = (*kind).max(reference);
+
+        if reference == Reference::Call {
+            callers.entry(target).or_default().push(address);
+        }
+    }
+
+    // Labels from the program's own symbols are kept, and
+    // everything else that's referenced gets a generated one
+    let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+
+    for (name, address) in &assembly.symbols {
+        labels.entry(*address).or_insert_with(|| name.clone());
+    }
+
+    for (address, reference) in &references {
+        labels
+            .entry(*address)
+            .or_insert_with(|| reference.label(*address));
+    }
+
+    let mut lines = vec![format!("# Disassembled from {} words", instructions.len())];
+
+    let is_blank = |address: usize| {
+        !is_code(address)
+            && instructions[address]
+                .binary_contents
+                .iter()
+                .all(|n| n == "0000")
+    };
+    let mut address = 0;
+
+    while address < instructions.len() {
+        let instruction = &instructions[address];
+
+        if let Some(from) = callers.get(&address) {
+            let from: Vec<String> = from.iter().map(|caller| caller.to_string()).collect();
+            let plural = if from.len() == 1 { "" } else { "es" };
+
+            lines.push(String::new());
+            lines.push(format!(
+                "# Subroutine, called from address{} {}",
+                plural,
+                from.join(", ")
+            ));
+        }
+
+        let label = labels
+            .get(&address)
+            .map(|label| format!("{}: ", label))
+            .unwrap_or_default();
+
+        if is_code(address) {
+            let name = instruction.instruction_type.names[0];
+            let mut written = operands(instruction);
+            let comment = explain(instruction, &written);
+
+            // Refer to addresses by their label, where they have one
+            let refers_to_address =
+                program[address].target().is_some() || matches!(name, "loadn" | "storen");
+
+            let target = program[address].immediate as usize;
+
+            if refers_to_address && target < instructions.len() {
+                if let Some(target) = labels.get(&target) {
+                    if let Some(last) = written.last_mut() {
+                        *last = target.clone();
+                    }
+                }
+            }
+
+            lines.push(format!(
+                "{} {}{} {} # {}",
+                address,
+                label,
+                name,
+                written.join(" "),
+                comment
+            ));
+        } else if is_blank(address) && address + 1 < instructions.len() && is_blank(address + 1) {
+            // Runs of empty memory are written as .space, and only the first word can have a label
+            let run = (address..instructions.len())
+                .take_while(|blank| {
+                    is_blank(*blank) && (*blank == address || !labels.contains_key(blank))
+                })
+                .count();
+
+            lines.push(format!("{} {}.space {}", address, label, run));
+            address += run;
+            continue;
+        } else {
+            let value = u16::from_str_radix(&instruction.binary_contents.join(""), 2).unwrap_or(0);
+            let mut line = format!("{} {}.word {}", address, label, value as i16);
+
+            if !program[address].is_data() {
+                let decoded = std::iter::once(instruction.instruction_type.names[0].to_string())
+                    .chain(operands(instruction))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+
+                line = format!("{} # Never reached, but decodes as: {}", line, decoded);
+            }
+
+            lines.push(line);
+        }
+
+        address += 1;
+    }
+
+    format_lines(&lines, None)
+}
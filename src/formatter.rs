@@ -0,0 +1,264 @@
+use super::assembler::{is_pseudo_instruction, line_addresses, parse_line, Diagnostic, ParsedLine};
+use super::simulator::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Instructions whose last operand is the address of a line
+static JUMP_INSTRUCTIONS: [&str; 6] = ["jumpn", "calln", "jeqzn", "jnezn", "jgtzn", "jltzn"];
+
+/// Comments are lined up at this column, unless the code is too long
+const MIN_COMMENT_COLUMN: usize = 24;
+
+/// Comments past this column are put one space after the code instead
+const MAX_COMMENT_COLUMN: usize = 48;
+
+/// A line split into the parts that get laid out in columns
+struct Layout {
+    line_number: Option<String>,
+    labels: String,
+    code: String,
+    comment: Option<String>,
+}
+
+/// Canonical spelling of a mnemonic: the first name of an instruction,
+/// lowercase pseudo-instructions and directives, and macros as written
+fn canonical_mnemonic(mnemonic: &str) -> String {
+    let lowercase = mnemonic.to_lowercase();
+
+    match InstructionType::from_name(&lowercase) {
+        Some(instruction_type) => instruction_type.names[0].to_string(),
+        None if lowercase.starts_with('.') || is_pseudo_instruction(&lowercase) => lowercase,
+        None => mnemonic.to_string(),
+    }
+}
+
+/// Canonical spelling of an operand, with registers in lowercase
+fn canonical_operand(operand: &str) -> String {
+    let mut chars = operand.chars();
+    let is_register = matches!(chars.next(), Some('r') | Some('R'))
+        && !chars.as_str().is_empty()
+        && chars.all(|c| c.is_ascii_digit());
+
+    if is_register {
+        operand.to_lowercase()
+    } else {
+        operand.to_string()
+    }
+}
+
+/// Everything after the mnemonic, up to the comment. Used for
+/// .include, where the path can contain spaces and commas
+fn raw_operands(line: &str, parsed: &ParsedLine) -> String {
+    match (parsed.operands.first(), parsed.operands.last()) {
+        (Some(first), Some(last)) => line
+            .chars()
+            .skip(first.column)
+            .take(last.column + last.text.chars().count() - first.column)
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+/// Rewrites HMMM source into a canonical layout: canonical instruction
+/// names, operands separated by ", ", and labels, instructions and
+/// comments lined up in columns. Comments and blank lines are kept.
+///
+/// With `renumber`, every instruction is given a line number matching
+/// its address, and jump and call operands that refer to old line
+/// numbers are changed to match. Addresses are worked out by assembling
+/// the program, so a file that doesn't assemble isn't renumbered, and
+/// its diagnostics are returned instead. Included files are looked for
+/// in the current directory
+pub fn format_hmmm(
+    uncompiled_text: &[String],
+    renumber: bool,
+) -> Result<Vec<String>, Vec<Diagnostic>> {
+    format_source(uncompiled_text, None, renumber)
+}
+
+/// Formats a file in the same way as format_hmmm, looking for included
+/// files next to it
+pub fn format_hmmm_from(
+    uncompiled_text: &[String],
+    path: &str,
+    renumber: bool,
+) -> Result<Vec<String>, Vec<Diagnostic>> {
+    format_source(uncompiled_text, Some(Path::new(path)), renumber)
+}
+
+fn format_source(
+    uncompiled_text: &[String],
+    path: Option<&Path>,
+    renumber: bool,
+) -> Result<Vec<String>, Vec<Diagnostic>> {
+    if !renumber {
+        return Ok(format_lines(uncompiled_text, None));
+    }
+
+    let addresses = line_addresses(uncompiled_text, path)?;
+
+    // Only instructions are numbered, and not directives, even those that place words
+    let new_numbers: Vec<Option<usize>> = uncompiled_text
+        .iter()
+        .zip(addresses)
+        .map(|(line, address)| match parse_line(line).mnemonic {
+            Some(mnemonic) if !mnemonic.text.starts_with('.') => address,
+            _ => None,
+        })
+        .collect();
+
+    Ok(format_lines(uncompiled_text, Some(&new_numbers)))
+}
+
+/// Lays out every line, giving instructions the line numbers in
+/// `new_numbers` if there are any
+pub(crate) fn format_lines(
+    uncompiled_text: &[String],
+    new_numbers: Option<&[Option<usize>]>,
+) -> Vec<String> {
+    let parsed_lines: Vec<ParsedLine> = uncompiled_text
+        .iter()
+        .map(|line| parse_line(line))
+        .collect();
+    let renumber = new_numbers.is_some();
+    let new_numbers = new_numbers.unwrap_or_default();
+
+    // What each old line number is being changed to
+    let mut renumbered: HashMap<i128, usize> = HashMap::new();
+
+    for (parsed, new) in parsed_lines.iter().zip(new_numbers) {
+        if let (Some(old), Some(new)) = (&parsed.line_number, new) {
+            if let Some(old) = parse_literal(&old.text) {
+                renumbered.entry(old as i128).or_insert(*new);
+            }
+        }
+    }
+
+    let layouts: Vec<Option<Layout>> = uncompiled_text
+        .iter()
+        .zip(&parsed_lines)
+        .enumerate()
+        .map(|(index, (line, parsed))| {
+            if parsed.line_number.is_none() && parsed.labels.is_empty() && parsed.mnemonic.is_none()
+            {
+                return None;
+            }
+
+            let line_number = match (renumber, new_numbers.get(index)) {
+                (true, Some(Some(number))) => Some(number.to_string()),
+                _ => parsed
+                    .line_number
+                    .as_ref()
+                    .map(|number| number.text.clone()),
+            };
+
+            let labels: Vec<String> = parsed
+                .labels
+                .iter()
+                .map(|label| format!("{}:", label.text))
+                .collect();
+
+            let code = match &parsed.mnemonic {
+                Some(mnemonic) => {
+                    let name = canonical_mnemonic(&mnemonic.text);
+                    let mut operands: Vec<String> = parsed
+                        .operands
+                        .iter()
+                        .map(|operand| canonical_operand(&operand.text))
+                        .collect();
+
+                    if renumber && JUMP_INSTRUCTIONS.contains(&name.as_str()) {
+                        if let Some(target) = operands.last_mut() {
+                            let old = parse_literal(target).map(i128::from);
+
+                            if let Some(new) = old.and_then(|old| renumbered.get(&old)) {
+                                *target = new.to_string();
+                            }
+                        }
+                    }
+
+                    let operands = match name.as_str() {
+                        ".include" => raw_operands(line, parsed),
+                        ".word" | ".fill" => operands.join(", "),
+                        directive if directive.starts_with('.') => operands.join(" "),
+                        _ => operands.join(", "),
+                    };
+
+                    if operands.is_empty() {
+                        name
+                    } else {
+                        format!("{:<6} {}", name, operands)
+                    }
+                }
+                None => String::new(),
+            };
+
+            Some(Layout {
+                line_number,
+                labels: labels.join(" "),
+                code,
+                comment: parsed.comment.clone(),
+            })
+        })
+        .collect();
+
+    let number_width = layouts
+        .iter()
+        .flatten()
+        .filter_map(|layout| layout.line_number.as_ref())
+        .map(|number| number.len() + 1)
+        .max()
+        .map(|width| width.max(4))
+        .unwrap_or(0);
+    let label_width = layouts
+        .iter()
+        .flatten()
+        .map(|layout| layout.labels.len())
+        .max()
+        .map(|width| if width > 0 { width + 1 } else { 0 })
+        .unwrap_or(0);
+
+    let code_lines: Vec<Option<String>> = layouts
+        .iter()
+        .map(|layout| {
+            layout.as_ref().map(|layout| {
+                let number = layout.line_number.as_deref().unwrap_or_default();
+                let code = format!(
+                    "{:<number_width$}{:<label_width$}{}",
+                    number,
+                    layout.labels,
+                    layout.code,
+                    number_width = number_width,
+                    label_width = label_width
+                );
+
+                code.trim_end().to_string()
+            })
+        })
+        .collect();
+
+    let comment_column = code_lines
+        .iter()
+        .flatten()
+        .map(|code| code.len() + 2)
+        .filter(|width| *width <= MAX_COMMENT_COLUMN)
+        .max()
+        .unwrap_or(0)
+        .max(MIN_COMMENT_COLUMN);
+
+    uncompiled_text
+        .iter()
+        .zip(layouts.iter().zip(code_lines))
+        .map(|(line, (layout, code))| match (layout, code) {
+            (Some(layout), Some(code)) => match &layout.comment {
+                Some(comment) if code.len() + 1 < comment_column => {
+                    format!("{:<width$}{}", code, comment, width = comment_column)
+                }
+                Some(comment) => format!("{} {}", code, comment),
+                None => code,
+            },
+            // Comments on their own line and blank lines are kept as they are
+            _ => line.trim().to_string(),
+        })
+        .collect()
+}
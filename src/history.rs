@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+/// A register written by a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub register: u8,
+    pub before: i16,
+    pub after: i16,
+}
+
+/// A word of memory written by a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u8,
+    pub before: u16,
+    pub after: u16,
+    /// Whether the word was data, and had been written to, before the step
+    pub(crate) was_data: bool,
+    pub(crate) was_written: bool,
+}
+
+/// Everything one step changed, so it can be undone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Number of steps run before this one
+    pub number: u64,
+    /// Address of the instruction that was run
+    pub address: usize,
+    pub registers: Vec<RegisterWrite>,
+    pub memory: Vec<MemoryWrite>,
+    /// Number the step read, if it ran a read
+    pub input: Option<i16>,
+    /// Number the step wrote, if it ran a write
+    pub output: Option<i16>,
+    pub(crate) just_updated_pc: bool,
+    pub(crate) current_regs: [u8; 3],
+    /// Length of the counter log before the step
+    pub(crate) counter_log: usize,
+}
+
+impl Step {
+    pub(crate) fn new(
+        number: u64,
+        address: usize,
+        just_updated_pc: bool,
+        current_regs: [u8; 3],
+        counter_log: usize,
+    ) -> Self {
+        Step {
+            number,
+            address,
+            registers: Vec::new(),
+            memory: Vec::new(),
+            input: None,
+            output: None,
+            just_updated_pc,
+            current_regs,
+            counter_log,
+        }
+    }
+
+    /// True if the step changed anything other than the program counter
+    pub fn changed_anything(&self) -> bool {
+        !self.registers.is_empty()
+            || !self.memory.is_empty()
+            || self.input.is_some()
+            || self.output.is_some()
+    }
+}
+
+/// The most recent steps a simulator has run, oldest first. Only the
+/// last `limit` steps are kept, and none are kept if the limit is 0
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    limit: usize,
+    steps: VecDeque<Step>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        History {
+            limit,
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Sets how many steps are kept, forgetting the oldest ones if
+    /// there are already more than that
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+
+        while self.steps.len() > limit {
+            self.steps.pop_front();
+        }
+    }
+
+    pub fn get_limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Every step kept, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Step> {
+        self.steps.iter()
+    }
+
+    /// The most recent step that wrote to a register, along with what it
+    /// wrote. Writes that leave the register's value the same still count
+    pub fn last_register_write(&self, register: u8) -> Option<(&Step, &RegisterWrite)> {
+        self.steps.iter().rev().find_map(|step| {
+            step.registers
+                .iter()
+                .rev()
+                .find(|write| write.register == register)
+                .map(|write| (step, write))
+        })
+    }
+
+    /// The most recent step that wrote to a word of memory, along with what it wrote
+    pub fn last_memory_write(&self, address: u8) -> Option<(&Step, &MemoryWrite)> {
+        self.steps.iter().rev().find_map(|step| {
+            step.memory
+                .iter()
+                .rev()
+                .find(|write| write.address == address)
+                .map(|write| (step, write))
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    pub(crate) fn push(&mut self, step: Step) {
+        if self.limit == 0 {
+            return;
+        }
+
+        if self.steps.len() == self.limit {
+            self.steps.pop_front();
+        }
+
+        self.steps.push_back(step);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Step> {
+        self.steps.pop_back()
+    }
+}
@@ -0,0 +1,442 @@
+use super::assembler::Diagnostic;
+use super::packed::word_to_instruction;
+use super::simulator::*;
+use std::ops::Range;
+
+/// Number of words of memory a HMMM machine has
+const MEMORY_SIZE: usize = 256;
+
+/// Bytes of data in each Intel HEX record
+const HEX_RECORD_BYTES: usize = 16;
+
+/// Words on each line of a Logisim image
+const LOGISIM_LINE_WORDS: usize = 8;
+
+/// Runs of at least this many equal words are written as "count*value"
+const LOGISIM_MIN_RUN: usize = 4;
+
+/// First line of every Logisim image
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+/// Memory image formats used to load a program into a circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Intel HEX, with each word stored big-endian at byte address 2 * address
+    IntelHex,
+    /// Logisim's "v2.0 raw" format, as used by its RAM and ROM components
+    Logisim,
+    /// Hex words for Verilog's $readmemh
+    ReadMemH,
+    /// Binary words for Verilog's $readmemb
+    ReadMemB,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 4] = [
+        ImageFormat::IntelHex,
+        ImageFormat::Logisim,
+        ImageFormat::ReadMemH,
+        ImageFormat::ReadMemB,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::IntelHex => ".hex",
+            ImageFormat::Logisim => ".rom",
+            ImageFormat::ReadMemH => ".memh",
+            ImageFormat::ReadMemB => ".memb",
+        }
+    }
+
+    /// Picks the format from a file's extension
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        ImageFormat::ALL
+            .iter()
+            .find(|format| path.ends_with(format.extension()))
+            .cloned()
+    }
+}
+
+/// Whitespace separated tokens of a line, with the columns they span
+fn tokens(line: &str) -> Vec<(Range<usize>, String)> {
+    let mut found = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (column, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+        match (c.is_whitespace(), &mut current) {
+            (false, Some((_, text))) => text.push(c),
+            (false, None) => current = Some((column, c.to_string())),
+            (true, Some((start, text))) => {
+                found.push((*start..column, text.clone()));
+                current = None;
+            }
+            (true, None) => {}
+        }
+    }
+
+    found
+}
+
+/// Parses a number in the given radix, allowing Verilog's '_' separators
+fn parse_word(text: &str, radix: u32) -> Option<u16> {
+    let digits: String = text.chars().filter(|c| *c != '_').collect();
+
+    if digits.is_empty() {
+        return None;
+    }
+
+    u16::from_str_radix(&digits, radix).ok()
+}
+
+/// Words of memory as they're read in. Addresses that are
+/// never written are left blank
+struct Memory {
+    words: Vec<Option<u16>>,
+}
+
+impl Memory {
+    fn new() -> Self {
+        Memory {
+            words: vec![None; MEMORY_SIZE],
+        }
+    }
+
+    fn write(&mut self, address: usize, word: u16) -> Result<(), CompileErr> {
+        match self.words.get_mut(address) {
+            Some(slot) => {
+                *slot = Some(word);
+                Ok(())
+            }
+            None => Err(CompileErr::ProgramTooLarge),
+        }
+    }
+
+    fn read(&self, address: usize) -> u16 {
+        self.words.get(address).cloned().flatten().unwrap_or(0)
+    }
+
+    /// Decodes every word up to the last one written
+    fn into_instructions(self) -> Vec<Instruction> {
+        let length = self
+            .words
+            .iter()
+            .rposition(|word| word.is_some())
+            .map_or(0, |last| last + 1);
+
+        self.words[..length]
+            .iter()
+            .map(|word| {
+                let word = word.unwrap_or(0);
+                word_to_instruction(word)
+                    .unwrap_or_else(|_| Instruction::new_data(&format!("{:016b}", word)))
+            })
+            .collect()
+    }
+}
+
+/// Creates a memory image of a compiled program
+pub fn export_image(compiled_text: &[Instruction], format: ImageFormat) -> String {
+    let hex: Vec<String> = compiled_text
+        .iter()
+        .map(|instruction| instruction.clone().as_hex())
+        .collect();
+
+    let lines = match format {
+        ImageFormat::IntelHex => export_intel_hex(&hex),
+        ImageFormat::Logisim => export_logisim(&hex),
+        ImageFormat::ReadMemH => hex,
+        ImageFormat::ReadMemB => compiled_text
+            .iter()
+            .map(|instruction| instruction.binary_contents.join("_"))
+            .collect(),
+    };
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+fn intel_hex_record(address: usize, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![
+        data.len() as u8,
+        (address >> 8) as u8,
+        address as u8,
+        record_type,
+    ];
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let digits: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}", digits.join(""))
+}
+
+fn export_intel_hex(hex: &[String]) -> Vec<String> {
+    let bytes: Vec<u8> = hex
+        .iter()
+        .flat_map(|word| {
+            let word = u16::from_str_radix(word, 16).unwrap_or(0);
+            word.to_be_bytes().to_vec()
+        })
+        .collect();
+
+    let mut lines: Vec<String> = bytes
+        .chunks(HEX_RECORD_BYTES)
+        .enumerate()
+        .map(|(index, data)| intel_hex_record(index * HEX_RECORD_BYTES, 0x00, data))
+        .collect();
+
+    lines.push(intel_hex_record(0, 0x01, &[]));
+    lines
+}
+
+fn export_logisim(hex: &[String]) -> Vec<String> {
+    // Logisim writes words without leading zeros, in lowercase
+    let mut values: Vec<String> = Vec::new();
+    let mut index = 0;
+
+    while index < hex.len() {
+        let run = hex[index..]
+            .iter()
+            .take_while(|word| **word == hex[index])
+            .count();
+        let value = format!("{:x}", u16::from_str_radix(&hex[index], 16).unwrap_or(0));
+
+        if run >= LOGISIM_MIN_RUN {
+            values.push(format!("{}*{}", run, value));
+            index += run;
+        } else {
+            values.push(value);
+            index += 1;
+        }
+    }
+
+    let mut lines = vec![LOGISIM_HEADER.to_string()];
+
+    for chunk in values.chunks(LOGISIM_LINE_WORDS) {
+        lines.push(chunk.join(" "));
+    }
+
+    lines
+}
+
+/// Reads a memory image back into a program, so a circuit's memory
+/// can be disassembled. Addresses the image doesn't set are blank,
+/// and the program ends at the last address that is set.
+///
+/// Every line that can't be read is reported, in the same way as
+/// read_compiled_hmmm reports errors
+pub fn import_image(
+    lines: &[String],
+    format: ImageFormat,
+) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+    let mut memory = Memory::new();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    match format {
+        ImageFormat::IntelHex => import_intel_hex(lines, &mut memory, &mut diagnostics),
+        ImageFormat::Logisim => import_logisim(lines, &mut memory, &mut diagnostics),
+        ImageFormat::ReadMemH => import_readmem(lines, 16, &mut memory, &mut diagnostics),
+        ImageFormat::ReadMemB => import_readmem(lines, 2, &mut memory, &mut diagnostics),
+    }
+
+    if diagnostics.is_empty() {
+        Ok(memory.into_instructions())
+    } else {
+        Err(diagnostics)
+    }
+}
+
+fn import_intel_hex(lines: &[String], memory: &mut Memory, diagnostics: &mut Vec<Diagnostic>) {
+    // Upper 16 bits of the address, set by extended address records
+    let mut base = 0;
+
+    for (index, line) in lines.iter().enumerate() {
+        let record = line.trim();
+
+        if record.is_empty() {
+            continue;
+        }
+
+        let span = 0..line.chars().count();
+        let corrupted = |diagnostics: &mut Vec<Diagnostic>| {
+            diagnostics.push(Diagnostic::new(
+                CompileErr::CorruptedBinary,
+                index,
+                line,
+                span.clone(),
+            ));
+        };
+
+        let digits = match record.strip_prefix(':') {
+            Some(digits) if digits.len() % 2 == 0 && digits.is_ascii() => digits,
+            _ => {
+                corrupted(diagnostics);
+                continue;
+            }
+        };
+
+        let bytes: Option<Vec<u8>> = (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+            .collect();
+
+        let bytes = match bytes {
+            Some(bytes)
+                if bytes.len() >= 5
+                    && bytes.len() == bytes[0] as usize + 5
+                    && bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0 =>
+            {
+                bytes
+            }
+            _ => {
+                corrupted(diagnostics);
+                continue;
+            }
+        };
+
+        let address = base + ((bytes[1] as usize) << 8 | bytes[2] as usize);
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            // Data
+            0x00 => {
+                for (offset, byte) in data.iter().enumerate() {
+                    let byte_address = address + offset;
+                    let word_address = byte_address / 2;
+                    let mut word = memory.read(word_address).to_be_bytes();
+                    word[byte_address % 2] = *byte;
+
+                    if let Err(err) = memory.write(word_address, u16::from_be_bytes(word)) {
+                        diagnostics.push(Diagnostic::new(err, index, line, span.clone()));
+                        break;
+                    }
+                }
+            }
+            // End of file
+            0x01 => break,
+            // Extended segment and linear addresses
+            0x02 | 0x04 if data.len() == 2 => {
+                let upper = (data[0] as usize) << 8 | data[1] as usize;
+                base = if bytes[3] == 0x02 {
+                    upper << 4
+                } else {
+                    upper << 16
+                };
+            }
+            // Start addresses don't mean anything to HMMM
+            0x03 | 0x05 => {}
+            _ => corrupted(diagnostics),
+        }
+    }
+}
+
+fn import_logisim(lines: &[String], memory: &mut Memory, diagnostics: &mut Vec<Diagnostic>) {
+    let mut address = 0;
+    let mut seen_header = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+
+        if code.trim().is_empty() {
+            continue;
+        }
+
+        if !seen_header {
+            if code.trim() != LOGISIM_HEADER {
+                let mut diagnostic =
+                    Diagnostic::new(CompileErr::CorruptedBinary, index, line, 0..line.len());
+                diagnostic.suggestion = Some(format!(
+                    "Logisim images must start with \"{}\"",
+                    LOGISIM_HEADER
+                ));
+                diagnostics.push(diagnostic);
+                return;
+            }
+
+            seen_header = true;
+            continue;
+        }
+
+        for (span, token) in tokens(code) {
+            // Repeated words are written as "count*value"
+            let (count, value) = match token.split_once('*') {
+                Some((count, value)) => (count.parse::<usize>().ok(), value),
+                None => (Some(1), token.as_str()),
+            };
+
+            let (count, word) = match (count, parse_word(value, 16)) {
+                (Some(count), Some(word)) => (count, word),
+                _ => {
+                    diagnostics.push(Diagnostic::new(
+                        CompileErr::CorruptedBinary,
+                        index,
+                        line,
+                        span,
+                    ));
+                    continue;
+                }
+            };
+
+            for _ in 0..count {
+                if let Err(err) = memory.write(address, word) {
+                    diagnostics.push(Diagnostic::new(err, index, line, span));
+                    return;
+                }
+
+                address += 1;
+            }
+        }
+    }
+}
+
+fn import_readmem(
+    lines: &[String],
+    radix: u32,
+    memory: &mut Memory,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut address = 0;
+
+    for (index, line) in lines.iter().enumerate() {
+        let code = line.split("//").next().unwrap_or_default();
+
+        for (span, token) in tokens(code) {
+            // "@address" moves to a new address, always given in hex
+            if let Some(new_address) = token.strip_prefix('@') {
+                match usize::from_str_radix(new_address, 16) {
+                    Ok(new_address) => address = new_address,
+                    Err(_) => diagnostics.push(Diagnostic::new(
+                        CompileErr::CorruptedBinary,
+                        index,
+                        line,
+                        span,
+                    )),
+                }
+
+                continue;
+            }
+
+            match parse_word(&token, radix) {
+                Some(word) => {
+                    if let Err(err) = memory.write(address, word) {
+                        diagnostics.push(Diagnostic::new(err, index, line, span));
+                        return;
+                    }
+                }
+                None => diagnostics.push(Diagnostic::new(
+                    CompileErr::CorruptedBinary,
+                    index,
+                    line,
+                    span,
+                )),
+            }
+
+            address += 1;
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use super::assembler::{Assembly, SourceMap};
+use super::simulator::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Instructions whose last operand is a memory address, so a number
+/// there is moved along with the program when it's linked
+pub static ADDRESS_INSTRUCTIONS: [&str; 8] = [
+    "jumpn", "calln", "jeqzn", "jnezn", "jgtzn", "jltzn", "loadn", "storen",
+];
+
+/// Which part of a word holds the address being relocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationField {
+    /// The last 8 bits of an instruction, eg: the target of a jumpn
+    Operand,
+    /// A whole data word, eg: a label used in .word
+    Word,
+}
+
+/// A word that refers to an address, and so has to be
+/// changed when its object is moved by the linker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Address of the word, relative to the start of its object
+    pub address: usize,
+    pub field: RelocationField,
+    /// Symbol imported from another object, or None if the word
+    /// refers to an address in its own object
+    pub symbol: Option<String>,
+}
+
+/// A separately assembled piece of a program. Addresses in it
+/// start from 0, and are moved when it's linked with others
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    /// Name used in linker errors, usually the object's path
+    pub name: String,
+    pub assembly: Assembly,
+    /// Labels other objects can use, listed with .global
+    pub exports: BTreeSet<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    /// Makes an object out of a compiled program that has no relocations,
+    /// such as one read from a .hb file. The address of every jump, call,
+    /// loadn and storen is taken to be in the program, and is moved with it.
+    /// Addresses held in data words can't be told apart from other data,
+    /// so they're left alone
+    pub fn from_instructions(name: &str, instructions: Vec<Instruction>) -> Self {
+        let relocations = instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| {
+                ADDRESS_INSTRUCTIONS.contains(&instruction.instruction_type.names[0])
+            })
+            .map(|(address, _)| Relocation {
+                address,
+                field: RelocationField::Operand,
+                symbol: None,
+            })
+            .collect();
+
+        Object {
+            name: name.to_string(),
+            assembly: Assembly::from_instructions(instructions),
+            exports: BTreeSet::new(),
+            relocations,
+        }
+    }
+
+    /// Symbols this object uses from other objects
+    pub fn imports(&self) -> BTreeSet<&str> {
+        self.relocations
+            .iter()
+            .filter_map(|relocation| relocation.symbol.as_deref())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkErr {
+    UndefinedSymbol,
+    DuplicateSymbol,
+    ProgramTooLarge,
+    AddressOutOfRange,
+    Overlap,
+}
+
+impl LinkErr {
+    pub fn as_code(&self) -> i32 {
+        match self {
+            LinkErr::UndefinedSymbol => 50,
+            LinkErr::DuplicateSymbol => 51,
+            LinkErr::ProgramTooLarge => 52,
+            LinkErr::AddressOutOfRange => 53,
+            LinkErr::Overlap => 54,
+        }
+    }
+}
+
+impl fmt::Display for LinkErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            LinkErr::UndefinedSymbol => "symbol is not exported by any object",
+            LinkErr::DuplicateSymbol => "symbol is exported by more than one object",
+            LinkErr::ProgramTooLarge => "linked program does not fit in 256 words of memory",
+            LinkErr::AddressOutOfRange => "address does not fit in the instruction",
+            LinkErr::Overlap => "two images are placed at the same address",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for LinkErr {}
+
+/// A problem found while linking, along with the object it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkError {
+    pub error: LinkErr,
+    /// Name of the object the problem is in
+    pub object: String,
+    /// What went wrong, in terms of the program
+    pub message: String,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.object, self.error, self.message)
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Changes the address held in a word, keeping data words as data
+fn patch(
+    instruction: &Instruction,
+    field: RelocationField,
+    value: i32,
+) -> Result<Instruction, String> {
+    let word = u16::from_str_radix(&instruction.binary_contents.join(""), 2).unwrap_or(0);
+
+    let patched = match field {
+        RelocationField::Word => value as u16,
+        RelocationField::Operand => {
+            let kind = instruction
+                .instruction_type
+                .arguments
+                .chars()
+                .last()
+                .unwrap_or('u');
+            let range = InstructionType::argument_range(kind).unwrap_or(0..=u8::MAX as i32);
+
+            if !range.contains(&value) {
+                return Err(format!(
+                    "{} needs address {}, which is outside the allowed range of {} to {}",
+                    instruction.instruction_type.names[0],
+                    value,
+                    range.start(),
+                    range.end()
+                ));
+            }
+
+            word & 0xFF00 | (value as u16 & 0x00FF)
+        }
+    };
+
+    let binary = format!("{:016b}", patched);
+
+    if instruction.instruction_type.names[0] == "data" {
+        Ok(Instruction::new_data(&binary))
+    } else {
+        Instruction::new_from_binary(&binary).map_err(|err| err.to_string())
+    }
+}
+
+/// Value currently held in the relocated part of a word
+fn field_value(instruction: &Instruction, field: RelocationField) -> i32 {
+    let word = u16::from_str_radix(&instruction.binary_contents.join(""), 2).unwrap_or(0);
+
+    match field {
+        RelocationField::Word => word as i16 as i32,
+        RelocationField::Operand => match instruction.instruction_type.arguments.chars().last() {
+            Some('s') => (word as u8 as i8) as i32,
+            _ => (word & 0x00FF) as i32,
+        },
+    }
+}
+
+/// Objects after relocation, each with the address it was placed at
+pub(crate) struct Placed {
+    pub words: Vec<(usize, Vec<Instruction>)>,
+    pub source_map: SourceMap,
+    /// Every exported symbol, at its final address
+    pub symbols: BTreeMap<String, usize>,
+}
+
+/// Moves each object to its base address, and fills in the symbols
+/// they import from each other. Doesn't check the objects fit in
+/// memory, or that they don't overlap
+pub(crate) fn place(objects: &[(usize, &Object)]) -> Result<Placed, Vec<LinkError>> {
+    let mut errors: Vec<LinkError> = Vec::new();
+
+    // Every exported symbol, along with the object that exports it
+    let mut exported: BTreeMap<&str, (usize, &str)> = BTreeMap::new();
+
+    for (base, object) in objects {
+        for name in &object.exports {
+            let address = match object.assembly.symbols.get(name) {
+                Some(address) => base + address,
+                None => {
+                    errors.push(LinkError {
+                        error: LinkErr::UndefinedSymbol,
+                        object: object.name.clone(),
+                        message: format!("\"{}\" is exported, but never defined", name),
+                    });
+                    continue;
+                }
+            };
+
+            match exported.get(name.as_str()) {
+                Some((_, first)) => errors.push(LinkError {
+                    error: LinkErr::DuplicateSymbol,
+                    object: object.name.clone(),
+                    message: format!("\"{}\" is already exported by {}", name, first),
+                }),
+                None => {
+                    exported.insert(name, (address, &object.name));
+                }
+            }
+        }
+    }
+
+    let mut placed = Placed {
+        words: Vec::new(),
+        source_map: SourceMap::new(),
+        symbols: BTreeMap::new(),
+    };
+
+    for (base, object) in objects {
+        let mut words = object.assembly.instructions.clone();
+
+        for relocation in &object.relocations {
+            let word = match words.get(relocation.address) {
+                Some(word) => word,
+                None => continue,
+            };
+
+            let value = match &relocation.symbol {
+                None => field_value(word, relocation.field) + *base as i32,
+                Some(name) => match exported.get(name.as_str()) {
+                    Some((address, _)) => *address as i32,
+                    None => {
+                        errors.push(LinkError {
+                            error: LinkErr::UndefinedSymbol,
+                            object: object.name.clone(),
+                            message: format!(
+                                "\"{}\" is used at address {}, but no object exports it",
+                                name, relocation.address
+                            ),
+                        });
+                        continue;
+                    }
+                },
+            };
+
+            match patch(word, relocation.field, value) {
+                Ok(patched) => words[relocation.address] = patched,
+                Err(message) => errors.push(LinkError {
+                    error: LinkErr::AddressOutOfRange,
+                    object: object.name.clone(),
+                    message: format!("at address {}, {}", relocation.address, message),
+                }),
+            }
+        }
+
+        for (address, location) in object.assembly.source_map.iter() {
+            placed.source_map.insert(base + address, location.clone());
+        }
+
+        placed.words.push((*base, words));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    placed.symbols = exported
+        .into_iter()
+        .map(|(name, (address, _))| (name.to_string(), address))
+        .collect();
+
+    Ok(placed)
+}
+
+/// Combines objects into one program. Objects are placed one after
+/// another, in the order given, so the first object's code starts at
+/// address 0. Every word that refers to an address is moved along with
+/// its object, and imported symbols are filled in from the object that
+/// exports them.
+///
+/// The linked program keeps every exported symbol and the source map
+/// of every object. All problems found are returned
+pub fn link(objects: &[Object]) -> Result<Assembly, Vec<LinkError>> {
+    // Place each object after the one before it
+    let mut placements: Vec<(usize, &Object)> = Vec::new();
+    let mut size = 0;
+
+    for object in objects {
+        placements.push((size, object));
+        size += object.assembly.instructions.len();
+    }
+
+    if size > 256 {
+        let sizes: Vec<String> = objects
+            .iter()
+            .map(|object| format!("{} ({})", object.name, object.assembly.instructions.len()))
+            .collect();
+
+        return Err(vec![LinkError {
+            error: LinkErr::ProgramTooLarge,
+            object: objects
+                .last()
+                .map(|object| object.name.clone())
+                .unwrap_or_default(),
+            message: format!("the objects take up {} words: {}", size, sizes.join(", ")),
+        }]);
+    }
+
+    let placed = place(&placements)?;
+    let instructions = placed
+        .words
+        .into_iter()
+        .flat_map(|(_, words)| words)
+        .collect();
+
+    let mut assembly = Assembly::from_instructions(instructions);
+    assembly.source_map = placed.source_map;
+    assembly.symbols = placed.symbols;
+
+    Ok(assembly)
+}
@@ -0,0 +1,341 @@
+use super::assembler::{SourceLocation, SourceMap};
+use super::simulator::*;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// How likely a finding is to be a real bug
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Probably a mistake, but the program can still run correctly
+    Warning,
+    /// Will cause a runtime error or wrong results if reached
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The kinds of mistake the linter looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// An instruction writes to r0, which is always 0
+    WriteToZeroRegister,
+    /// A jump or call goes past the end of the program
+    JumpOutOfProgram,
+    /// A jump or call lands on a data word
+    JumpIntoData,
+    /// Code that no path from address 0 can reach
+    UnreachableCode,
+    /// A path runs off the end of the program or into data without halting
+    MissingHalt,
+    /// A register is read before anything could have been written to it
+    UninitializedRegister,
+}
+
+impl LintKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            LintKind::WriteToZeroRegister => Severity::Warning,
+            LintKind::JumpOutOfProgram => Severity::Error,
+            LintKind::JumpIntoData => Severity::Error,
+            LintKind::UnreachableCode => Severity::Warning,
+            LintKind::MissingHalt => Severity::Error,
+            LintKind::UninitializedRegister => Severity::Warning,
+        }
+    }
+}
+
+/// A single problem found by the linter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: LintKind,
+    pub severity: Severity,
+    /// Address of the instruction the problem is with
+    pub address: usize,
+    pub message: String,
+    /// Source line of the instruction, if a source map was given
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at address {}", self.severity, self.address)?;
+
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The parts of an instruction the linter cares about
+pub(crate) struct Decoded {
+    pub(crate) name: &'static str,
+    pub(crate) reg_x: usize,
+    pub(crate) reg_y: usize,
+    pub(crate) reg_z: usize,
+    /// Last 8 bits, used as an address or number
+    pub(crate) immediate: u8,
+}
+
+impl Decoded {
+    pub(crate) fn new(instruction: &Instruction) -> Self {
+        let nibble =
+            |i: usize| usize::from_str_radix(&instruction.binary_contents[i], 2).unwrap_or(0);
+
+        Decoded {
+            name: instruction.instruction_type.names[0],
+            reg_x: nibble(1),
+            reg_y: nibble(2),
+            reg_z: nibble(3),
+            immediate: (nibble(2) * 16 + nibble(3)) as u8,
+        }
+    }
+
+    pub(crate) fn is_data(&self) -> bool {
+        self.name == "data"
+    }
+
+    /// Address this instruction jumps or calls to, if it has a fixed one
+    pub(crate) fn target(&self) -> Option<usize> {
+        match self.name {
+            "jumpn" | "calln" | "jeqzn" | "jnezn" | "jgtzn" | "jltzn" => {
+                Some(self.immediate as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// True if execution can carry on to the next address
+    pub(crate) fn falls_through(&self) -> bool {
+        !matches!(self.name, "halt" | "jumpn" | "jumpr" | "data")
+    }
+
+    /// Registers this instruction reads
+    pub(crate) fn reads(&self) -> Vec<usize> {
+        match self.name {
+            "write" | "jumpr" | "storen" | "addn" | "jeqzn" | "jnezn" | "jgtzn" | "jltzn" => {
+                vec![self.reg_x]
+            }
+            "storer" | "pushr" => vec![self.reg_x, self.reg_y],
+            "loadr" | "popr" | "copy" => vec![self.reg_y],
+            "neg" => vec![self.reg_z],
+            "add" | "sub" | "mul" | "div" | "mod" => vec![self.reg_y, self.reg_z],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Registers this instruction writes
+    pub(crate) fn writes(&self) -> Vec<usize> {
+        match self.name {
+            "read" | "setn" | "loadn" | "loadr" | "addn" | "copy" | "neg" | "add" | "sub"
+            | "mul" | "div" | "mod" | "calln" => vec![self.reg_x],
+            "popr" => vec![self.reg_x, self.reg_y],
+            "pushr" => vec![self.reg_y],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Every address that can be reached from `start` without following
+/// jumpr, which is assumed to return from a call
+pub(crate) fn reachable_from(program: &[Decoded], start: usize) -> BTreeSet<usize> {
+    let mut reached = BTreeSet::new();
+    let mut to_visit = vec![start];
+
+    while let Some(address) = to_visit.pop() {
+        if address >= program.len() || !reached.insert(address) {
+            continue;
+        }
+
+        let decoded = &program[address];
+
+        if let Some(target) = decoded.target() {
+            to_visit.push(target);
+        }
+
+        if decoded.falls_through() {
+            to_visit.push(address + 1);
+        }
+    }
+
+    reached
+}
+
+/// Looks for common mistakes in a compiled program, without running it.
+/// The source map is optional, and is only used to give each finding a
+/// location. Findings are returned in address order
+pub fn lint(instructions: &[Instruction], source_map: &SourceMap) -> Vec<Finding> {
+    let program: Vec<Decoded> = instructions.iter().map(Decoded::new).collect();
+    let mut findings: Vec<Finding> = Vec::new();
+
+    if program.is_empty() {
+        return findings;
+    }
+
+    let mut report = |kind: LintKind, address: usize, message: String| {
+        findings.push(Finding {
+            kind,
+            severity: kind.severity(),
+            address,
+            message,
+            location: source_map.get(address).cloned(),
+        });
+    };
+
+    let reached = reachable_from(&program, 0);
+
+    for &address in &reached {
+        let decoded = &program[address];
+
+        if decoded.is_data() {
+            continue;
+        }
+
+        if decoded.writes().first() == Some(&0) && decoded.name != "nop" {
+            report(
+                LintKind::WriteToZeroRegister,
+                address,
+                format!(
+                    "{} writes to r0, which is always 0, so the result is lost",
+                    decoded.name
+                ),
+            );
+        }
+
+        if let Some(target) = decoded.target() {
+            if target >= program.len() {
+                report(
+                    LintKind::JumpOutOfProgram,
+                    address,
+                    format!(
+                        "{} goes to address {}, past the end of the program",
+                        decoded.name, target
+                    ),
+                );
+            } else if program[target].is_data() {
+                report(
+                    LintKind::JumpIntoData,
+                    address,
+                    format!("{} goes to address {}, which is data", decoded.name, target),
+                );
+            }
+        }
+
+        if decoded.falls_through() {
+            let next = address + 1;
+
+            if next >= program.len() {
+                report(
+                    LintKind::MissingHalt,
+                    address,
+                    "the program can run past its last instruction without halting".to_string(),
+                );
+            } else if program[next].is_data() {
+                report(
+                    LintKind::MissingHalt,
+                    address,
+                    format!(
+                        "the program can run into the data at address {} without halting",
+                        next
+                    ),
+                );
+            }
+        }
+    }
+
+    // Report each run of unreachable code once
+    let mut run_start: Option<usize> = None;
+
+    for address in 0..=program.len() {
+        let is_unreachable =
+            address < program.len() && !program[address].is_data() && !reached.contains(&address);
+
+        match (is_unreachable, run_start) {
+            (true, None) => run_start = Some(address),
+            (false, Some(start)) => {
+                let message = if start == address - 1 {
+                    format!("the instruction at address {} can never run", start)
+                } else {
+                    format!(
+                        "the instructions at addresses {} to {} can never run",
+                        start,
+                        address - 1
+                    )
+                };
+
+                report(LintKind::UnreachableCode, start, message);
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Find registers that are read before any path could have written them.
+    // Each address gets the set of registers that might have been written
+    // by the time it runs, and the sets are grown until nothing changes
+    let mut written: Vec<Option<u16>> = vec![None; program.len()];
+    let mut to_visit: Vec<(usize, u16)> = vec![(0, 1)];
+
+    while let Some((address, incoming)) = to_visit.pop() {
+        if address >= program.len() || program[address].is_data() {
+            continue;
+        }
+
+        let merged = written[address].unwrap_or(0) | incoming;
+        if written[address] == Some(merged) {
+            continue;
+        }
+        written[address] = Some(merged);
+
+        let decoded = &program[address];
+        let mut outgoing = merged;
+
+        for register in decoded.writes() {
+            outgoing |= 1 << register;
+        }
+
+        if let Some(target) = decoded.target() {
+            to_visit.push((target, outgoing));
+        }
+
+        if decoded.falls_through() {
+            // Anything a called function might write has been written on return
+            if decoded.name == "calln" {
+                for callee in reachable_from(&program, decoded.immediate as usize) {
+                    for register in program[callee].writes() {
+                        outgoing |= 1 << register;
+                    }
+                }
+            }
+
+            to_visit.push((address + 1, outgoing));
+        }
+    }
+
+    let mut reported: BTreeSet<usize> = BTreeSet::new();
+
+    for (address, state) in written.iter().enumerate() {
+        if let Some(state) = state {
+            for register in program[address].reads() {
+                if state & (1 << register) == 0 && reported.insert(register) {
+                    report(
+                        LintKind::UninitializedRegister,
+                        address,
+                        format!("r{} is read before anything is written to it", register),
+                    );
+                }
+            }
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.address);
+    findings
+}
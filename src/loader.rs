@@ -0,0 +1,165 @@
+use super::assembler::Assembly;
+use super::linker::{place, LinkErr, LinkError, Object};
+use super::simulator::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Places programs and data at chosen addresses in memory, to run more than
+/// one program at once, such as a resident monitor and a user program, or a
+/// test driver and the subroutine it tests.
+///
+/// Every address in a program is moved to where the program is placed, and
+/// symbols exported by one object can be used by any other. Data is exported
+/// under its name, so programs can find it wherever it's placed. Anything not
+/// placed is left as blank data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Loader {
+    images: Vec<(usize, Object)>,
+    entry: usize,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader::default()
+    }
+
+    /// Places an object, such as one from assemble_object, at `base`
+    pub fn add_object(&mut self, base: usize, object: Object) {
+        self.images.push((base, object));
+    }
+
+    /// Places a compiled program without relocations, such as one from a
+    /// .hb file, at `base`. See Object::from_instructions for which
+    /// addresses are moved
+    pub fn add_program(&mut self, base: usize, name: &str, instructions: Vec<Instruction>) {
+        self.add_object(base, Object::from_instructions(name, instructions));
+    }
+
+    /// Places data words at `base`, without changing them. Objects can
+    /// refer to the first word by `name`
+    pub fn add_data(&mut self, base: usize, name: &str, data: &[i16]) {
+        let words = data
+            .iter()
+            .map(|value| Instruction::new_data(&format!("{:016b}", value)))
+            .collect();
+
+        let mut assembly = Assembly::from_instructions(words);
+        assembly.symbols = BTreeMap::from([(name.to_string(), 0)]);
+
+        self.add_object(
+            base,
+            Object {
+                name: name.to_string(),
+                assembly,
+                exports: BTreeSet::from([name.to_string()]),
+                relocations: Vec::new(),
+            },
+        );
+    }
+
+    /// Sets the address the program counter starts at, which is 0 by default
+    pub fn set_entry(&mut self, entry: usize) {
+        self.entry = entry;
+    }
+
+    pub fn get_entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Checks every image fits in memory, that no two share an
+    /// address, and that the entry address is in memory
+    fn check_layout(&self) -> Result<(), Vec<LinkError>> {
+        let mut errors = Vec::new();
+
+        if self.entry > 255 {
+            errors.push(LinkError {
+                error: LinkErr::AddressOutOfRange,
+                object: "entry".to_string(),
+                message: format!("entry address {} is past the end of memory", self.entry),
+            });
+        }
+
+        for (index, (base, image)) in self.images.iter().enumerate() {
+            let len = image.assembly.instructions.len();
+            let end = base + len;
+
+            if end > 256 {
+                errors.push(LinkError {
+                    error: LinkErr::ProgramTooLarge,
+                    object: image.name.clone(),
+                    message: format!(
+                        "{} words placed at address {} run past the end of memory",
+                        len, base
+                    ),
+                });
+            }
+
+            for (other_base, other) in &self.images[..index] {
+                let start = (*base).max(*other_base);
+                let other_end = other_base + other.assembly.instructions.len();
+
+                if start < end.min(other_end) {
+                    errors.push(LinkError {
+                        error: LinkErr::Overlap,
+                        object: image.name.clone(),
+                        message: format!(
+                            "addresses {} to {} are also used by {}",
+                            start,
+                            end.min(other_end) - 1,
+                            other.name
+                        ),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Builds the contents of memory, up to the last word placed. The
+    /// result keeps every exported symbol and the source map of every object
+    pub fn image(&self) -> Result<Assembly, Vec<LinkError>> {
+        self.check_layout()?;
+
+        let objects: Vec<(usize, &Object)> = self
+            .images
+            .iter()
+            .map(|(base, object)| (*base, object))
+            .collect();
+        let placed = place(&objects)?;
+
+        let size = placed
+            .words
+            .iter()
+            .map(|(base, words)| base + words.len())
+            .max()
+            .unwrap_or(0);
+        let mut memory = vec![Instruction::new_blank_data(); size];
+
+        for (base, words) in placed.words {
+            for (offset, word) in words.into_iter().enumerate() {
+                memory[base + offset] = word;
+            }
+        }
+
+        let mut assembly = Assembly::from_instructions(memory);
+        assembly.source_map = placed.source_map;
+        assembly.symbols = placed.symbols;
+
+        Ok(assembly)
+    }
+
+    /// Creates a simulator with everything loaded, ready to start at the entry address
+    pub fn simulator(&self) -> Result<Simulator, Vec<LinkError>> {
+        let assembly = self.image()?;
+
+        let mut simulator = Simulator::new(assembly.instructions);
+        simulator.set_source_map(assembly.source_map);
+        simulator.program_counter = self.entry;
+
+        Ok(simulator)
+    }
+}
@@ -1,6 +1,1194 @@
-
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::io::Write;
+use std::path::Path;
 use std::process::*;
+use std::{io, thread, time};
+
+use colored::*;
+use terminal::*;
+
+use hmmm_rs::assembler::{Assembly, Diagnostic, SourceLocation};
+use hmmm_rs::autograder::*;
+use hmmm_rs::breakpoints::{Access, Breakpoint, Condition, Location, Stop, Watchpoint};
+use hmmm_rs::devices::Console;
+use hmmm_rs::image::{self, ImageFormat};
+use hmmm_rs::linker::{self, LinkError, Object};
+use hmmm_rs::lint::{self, Finding, Severity};
+use hmmm_rs::simulator::*;
+use hmmm_rs::*;
+
+/// Number of steps the debugger keeps, to be able to step back through them
+const DEBUGGER_HISTORY: usize = 10_000;
+
+/// Most steps the debugger runs when continuing, so a program stuck
+/// in a loop doesn't hang it
+const DEBUGGER_CONTINUE_LIMIT: u64 = 1_000_000;
+
+/// Function to load a file, exiting with an error message if it can't be read
+fn load_or_exit(path: &str) -> Vec<String> {
+    match load_file(path) {
+        Ok(lines) => lines,
+        Err(err) => {
+            println!("Error: Cannot open file \"{}\": {}", path, err);
+            exit(1);
+        }
+    }
+}
+
+/// Function to pretty-print every compilation error found,
+/// pointing at the offending part of each line
+fn raise_compile_errors(diagnostics: &[Diagnostic]) {
+    println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
+    println!(
+        "{}{}{}",
+        "████".yellow(),
+        "    COMPILATION UNSUCCESSFUL    ".red().bold(),
+        "████".yellow()
+    );
+    println!("{}\n", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+
+    for diagnostic in diagnostics {
+        let location = match &diagnostic.file {
+            Some(file) => format!(" ERROR IN {} ON LINE {}:", file, diagnostic.line),
+            None => format!(" ERROR ON LINE {}:", diagnostic.line),
+        };
+
+        println!("{} {:?}", location.on_red().white().bold(), diagnostic.error);
+
+        // Underline the offending token
+        let underline = format!(
+            "{}{}",
+            " ".repeat(diagnostic.span.start),
+            "^".repeat(diagnostic.span.len().max(1))
+        );
+
+        println!("{:>5} █ {}", diagnostic.line, diagnostic.source.white());
+        println!("      █ {}", underline.red().bold());
+
+        if let Some(expected) = diagnostic.expected {
+            println!(
+                "      █ {} {}",
+                "Expected:".bold(),
+                InstructionType::describe_argument(expected)
+            );
+        }
+
+        if let Some(suggestion) = &diagnostic.suggestion {
+            println!("      █ {} {}", "Suggestion:".bold(), suggestion);
+        }
+
+        println!();
+    }
+
+    println!("{} error(s) found. Exiting...", diagnostics.len());
+}
+
+/// Function to pretty-print a runtime error and exit
+/// the program gracefully
+fn raise_runtime_error(sim: &Simulator, error: &RuntimeError) {
+    // Easy way to display information: show the debug screen!
+    let _debug_result = print_debug_screen(sim);
+
+    let w = terminal::stdout();
+    let _ = w.act(Action::MoveCursorTo(0, 29));
+    println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
+    println!(
+        "{}{}{}",
+        "████".yellow(),
+        "    SIMULATION UNSUCCESSFUL     ".red().bold(),
+        "████".yellow()
+    );
+    println!("{}\n", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+
+    println!(
+        "{} {:?} ({})",
+        format!("{} {}:", " ERROR EXECUTING ADDRESS", error.address)
+            .on_red()
+            .white()
+            .bold(),
+        error.kind,
+        error.kind
+    );
+    println!(
+        "{} {} {}\n",
+        " MEMORY ADDRESS CONTENTS:".on_red().white().bold(),
+        error.instruction.instruction_type.names[0],
+        error.instruction.text_contents
+    );
+
+    if let Some(location) = &error.location {
+        print_source_location(location);
+    }
+}
+
+/// Function to print a source line, pointing at where
+/// in the line the instruction is
+fn print_source_location(location: &SourceLocation) {
+    let heading = match &location.file {
+        Some(file) => format!(" SOURCE ({}):", file),
+        None => " SOURCE:".to_string(),
+    };
+
+    println!("{}", heading.on_red().white().bold());
+    println!("{:>5} █ {}", location.line, location.text.white());
+    println!("      █ {}^\n", " ".repeat(location.column));
+}
+
+/// Function to print the current state of the simulator
+/// (registers, memory, etc.) to the screen without flickering
+/// (i.e. no flicker when the screen is updated)
+fn print_debug_screen(sim: &Simulator) -> terminal::error::Result<()> {
+    let mut debug_screen_lines: Vec<String> = Vec::new();
+
+    debug_screen_lines.push(format!(
+        "{}{}{}",
+        "█▀▀▀▀▀▀▀▀▀▀█",
+        "  REGISTER CONTENTS  ".bold().on_blue(),
+        "█▀▀▀▀▀▀▀▀▀▀█\n",
+    ));
+
+    for row in 0..4 {
+        debug_screen_lines.push(format!(
+            "█    R{: <2}   █    R{: <2}   █    R{: <2}   █    R{: <2}   █\n",
+            row * 4,
+            (row * 4) + 1,
+            (row * 4) + 2,
+            (row * 4) + 3
+        ));
+
+        debug_screen_lines.push(format!(
+            "█ {:8} █ {:8} █ {:8} █ {:8} █\n",
+            &sim.get_register(row * 4).unwrap_or(0),
+            &sim.get_register((row * 4) + 1).unwrap_or(0),
+            &sim.get_register((row * 4) + 2).unwrap_or(0),
+            &sim.get_register((row * 4) + 3).unwrap_or(0),
+        ));
+    }
+    debug_screen_lines.push("█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄\n".to_string());
+    debug_screen_lines.push("█    █   0  █   1  █   2  █   3  █   4  █   5  █   6  █   7  █   8  █   9  █   A  █   B  █   C  █   D  █   E  █   F  █\n".to_string());
+    let address_chars = vec![
+        '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
+    ];
+
+    let current_pc = &sim.get_program_counter();
+
+    for (i, address_rows) in address_chars.iter().enumerate() {
+        let mut to_print = format!("█  {} █", address_rows);
+
+        for (j, _address_columns) in address_chars.iter().enumerate() {
+            let memory_index = (i * 16) + j;
+
+            // We can safely unwrap here because we know that the memory
+            // is fully populated. Any errors will be caught at compile time,
+            // or worst case, with a RuntimeErr.
+            let current_instruction = sim.get_memory(memory_index).unwrap();
+
+            let instruction_text;
+            if current_pc == &memory_index {
+                instruction_text = current_instruction.as_hex().on_green();
+            } else if current_instruction.instruction_type.names[0] == "data" {
+                if current_instruction.binary_contents == vec!["0000", "0000", "0000", "0000"] {
+                    instruction_text = current_instruction.as_hex().on_black();
+                } else {
+                    instruction_text = current_instruction.as_hex().on_yellow().black();
+                }
+            } else {
+                instruction_text = current_instruction.as_hex().on_purple();
+            }
+
+            to_print = format!("{} {} █", to_print, instruction_text);
+        }
+
+        debug_screen_lines.push(format!("{}\n", to_print));
+    }
+
+    debug_screen_lines.push("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀\n".to_string());
+
+    // Create terminal object to print out the debug screen
+    let mut w = terminal::stdout();
+    // Make sure the cursor is at the top of the screen
+    w.act(Action::MoveCursorTo(0, 0))?;
+    // Print line by line to avoid having to strobe the screen
+    for line in debug_screen_lines {
+        print!("{}", line);
+    }
+
+    // Line by line printing done, now to print out program counter,
+    // IR, human-readable output, and HMMM output.
+
+    // Print program counter
+    w.act(Action::MoveCursorTo(50, 1)).unwrap();
+    let to_print = format!("{}", " PROGRAM COUNTER: ".on_red().white().bold());
+    print!("{}", to_print);
+    w.act(Action::MoveCursorTo(50, 2)).unwrap();
+    let to_print = format!("{:<10}", sim.get_program_counter());
+    print!("{}", to_print);
+
+    // Print IR
+    w.act(Action::MoveCursorTo(50, 4)).unwrap();
+    let to_print = format!("{}", " INSTRUCTION REGISTER: ".on_red().white().bold());
+    print!("{}", to_print);
+    if let Some(memory_ir) = sim.fetch(sim.get_program_counter()) {
+        w.act(Action::MoveCursorTo(50, 5)).unwrap();
+        let to_print = format!(
+            "{:<15}",
+            format!(
+                "{} {}",
+                memory_ir.instruction_type.names[0], memory_ir.text_contents
+            )
+        );
+        print!("{}", to_print);
+
+        // Print human-readable output
+        w.act(Action::MoveCursorTo(75, 1)).unwrap();
+        let to_print = format!("{}", " HUMAN-READABLE CODE: ".on_green().white().bold());
+        print!("{}", to_print);
+
+        w.act(Action::MoveCursorTo(75, 2)).unwrap();
+
+        let mut to_print = String::from(memory_ir.instruction_type.human_explanation);
+
+        for (i, c) in memory_ir.instruction_type.arguments.chars().enumerate() {
+            let result: String = match c {
+                'r' => {
+                    format!(
+                        "{}",
+                        u8::from_str_radix(memory_ir.binary_contents[i + 1].as_str(), 2).unwrap()
+                    )
+                }
+                's' => {
+                    let converted = signed_binary_conversion(
+                        memory_ir.binary_contents[i + 1..i + 3].join("").as_str(),
+                    )
+                    .unwrap();
+
+                    format!("{}", converted)
+                }
+                'u' => {
+                    let converted = u8::from_str_radix(
+                        memory_ir.binary_contents[i + 1..i + 3].join("").as_str(),
+                        2,
+                    )
+                    .unwrap();
+                    format!("{}", converted)
+                }
+                'n' => {
+                    let converted =
+                        i32::from_str_radix(memory_ir.binary_contents.join("").as_str(), 2)
+                            .unwrap();
+                    format!("{}", converted)
+                }
+                _ => String::from(""),
+            };
+            if !result.is_empty() {
+                to_print = to_print.replacen("_", result.as_str(), 1);
+            }
+        }
+        if to_print.len() > 45 {
+            print!(
+                "{:<45}",
+                to_print.drain(..40).collect::<String>().trim().bold()
+            );
+            w.act(Action::MoveCursorTo(75, 3)).unwrap();
+            print!("{:<45}", to_print.trim().bold());
+        } else {
+            print!("{:<45}", to_print.bold());
+            w.act(Action::MoveCursorTo(75, 3)).unwrap();
+            print!("{:<45}", "");
+        }
+    }
+
+    // Print the source line the instruction came from, clearing
+    // the previous one if there's no source for this address
+    let location = sim.get_source_location(sim.get_program_counter());
+    let (heading, code, comment) = match location {
+        Some(location) => (
+            format!(" SOURCE LINE {}: ", location.line),
+            location.text.split('#').next().unwrap_or_default().trim(),
+            location.comment.as_deref().unwrap_or_default(),
+        ),
+        None => (String::new(), "", ""),
+    };
+
+    w.act(Action::MoveCursorTo(75, 4)).unwrap();
+    print!("{:<45}", "");
+    w.act(Action::MoveCursorTo(75, 4)).unwrap();
+    print!("{}", heading.on_blue().white().bold());
+    w.act(Action::MoveCursorTo(75, 5)).unwrap();
+    print!("{:<45}", code.chars().take(45).collect::<String>().bold());
+    w.act(Action::MoveCursorTo(75, 6)).unwrap();
+    print!("{:<45}", comment.chars().take(45).collect::<String>().dimmed());
+
+    // Print HMMM output
+    w.act(Action::MoveCursorTo(50, 7)).unwrap();
+    let to_print = format!("{}", " HMMM OUT: ".on_green().white().bold());
+    print!("{}", to_print);
+
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Reads a command at the debugger's prompt, when stepping with --speed 0.
+/// Returns false if the simulator should step forward, or true once any
+/// other command has been carried out
+fn debugger_command(sim: &mut Simulator) -> terminal::error::Result<bool> {
+    let w = terminal::stdout();
+
+    w.act(Action::MoveCursorTo(0, 30))?;
+    print!("{:<100}", "");
+    w.act(Action::MoveCursorTo(0, 30))?;
+    print!(
+        "{} ",
+        " Enter: step  c: continue  b: back  r: back to breakpoint  p N [if r3 < 0]: breakpoint  x r3: watch  w r3: last write "
+            .on_blue()
+            .white()
+    );
+    w.act(Action::ShowCursor)?;
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+    w.act(Action::HideCursor)?;
+
+    // Keep stepping if there's nothing left to read
+    if !matches!(read, Ok(read) if read > 0) {
+        return Ok(false);
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let message = match words.as_slice() {
+        [] => return Ok(false),
+        ["c"] => match sim.run_until(DEBUGGER_CONTINUE_LIMIT) {
+            Stop::Breakpoint(id) => {
+                let breakpoint = &sim.get_stops().breakpoints[&id];
+                format!("Stopped at breakpoint {}, hit {} times", breakpoint.address, breakpoint.hits)
+            }
+            Stop::Watchpoint { id, value } => {
+                let watchpoint = &sim.get_stops().watchpoints[&id];
+                format!("{} changed to {}", watchpoint.location, value)
+            }
+            Stop::StepLimit => format!("Still running after {} steps", DEBUGGER_CONTINUE_LIMIT),
+            // Stepping again shows the halt or error in the usual way
+            Stop::Halted | Stop::Error(_) => return Ok(false),
+        },
+        ["b"] => match sim.step_back() {
+            Some(_) => String::new(),
+            None => "No earlier steps to go back to".to_string(),
+        },
+        ["r"] => match sim.reverse_continue() {
+            0 => "No earlier steps to go back to".to_string(),
+            undone => format!("Went back {} steps", undone),
+        },
+        ["p", address, rest @ ..] => match (address.parse::<usize>(), rest) {
+            (Ok(address), []) => {
+                let existing: Vec<usize> = sim.get_stops().breakpoints.iter()
+                    .filter(|(_, breakpoint)| breakpoint.address == address)
+                    .map(|(id, _)| *id)
+                    .collect();
+
+                if existing.is_empty() {
+                    sim.add_breakpoint(Breakpoint::new(address));
+                    format!("Added breakpoint at {}", address)
+                } else {
+                    for id in existing {
+                        sim.remove_stop(id);
+                    }
+                    format!("Removed breakpoint at {}", address)
+                }
+            }
+            (Ok(address), ["if", ..]) => match rest[1..].join(" ").parse::<Condition>() {
+                Ok(condition) => {
+                    let message = format!("Added breakpoint at {} if {}", address, condition);
+                    let mut breakpoint = Breakpoint::new(address);
+                    breakpoint.condition = Some(condition);
+                    sim.add_breakpoint(breakpoint);
+                    message
+                }
+                Err(err) => format!("Invalid condition: {}", err),
+            },
+            _ => format!("Expected \"p <address>\" or \"p <address> if <condition>\", not \"{}\"", line.trim()),
+        },
+        ["x", location] => match location.parse::<Location>() {
+            Ok(location) => {
+                let existing = sim.get_stops().watchpoints.iter()
+                    .find(|(_, watchpoint)| watchpoint.location == location)
+                    .map(|(id, _)| *id);
+
+                match existing {
+                    Some(id) => {
+                        sim.remove_stop(id);
+                        format!("Stopped watching {}", location)
+                    }
+                    None => {
+                        sim.add_watchpoint(Watchpoint::new(location, Access::Change));
+                        format!("Watching {} for changes", location)
+                    }
+                }
+            }
+            Err(err) => format!("Invalid location: {}", err),
+        },
+        ["w", register] => match register.trim_start_matches('r').parse::<u8>() {
+            Ok(register) => match sim.get_history().last_register_write(register) {
+                Some((step, write)) => format!(
+                    "r{} was last written at step {} by address {}, from {} to {}",
+                    register, step.number, step.address, write.before, write.after
+                ),
+                None => format!(
+                    "r{} hasn't been written in the last {} steps",
+                    register,
+                    sim.get_history().len()
+                ),
+            },
+            Err(_) => format!("\"{}\" is not a register", register),
+        },
+        _ => format!("Unknown command \"{}\"", line.trim()),
+    };
+
+    w.act(Action::MoveCursorTo(0, 31))?;
+    print!("{:<100}", message);
+
+    Ok(true)
+}
+
+/// Function to print every lint finding, with the source line if known
+fn print_lint_findings(findings: &[Finding]) {
+    if findings.is_empty() {
+        println!("{}", " NO PROBLEMS FOUND ".on_green().white().bold());
+        return;
+    }
+
+    for finding in findings {
+        let heading = format!(" {} AT ADDRESS {}:", finding.severity.to_string().to_uppercase(), finding.address);
+        let heading = match finding.severity {
+            Severity::Error => heading.on_red(),
+            Severity::Warning => heading.on_yellow(),
+        };
+
+        println!("{} {:?}", heading.white().bold(), finding.kind);
+
+        if let Some(location) = &finding.location {
+            println!("{:>5} █ {}", location.line, location.text.white());
+        }
+
+        println!("      █ {}\n", finding.message);
+    }
+
+    let errors = findings.iter().filter(|finding| finding.severity == Severity::Error).count();
+    println!("{} error(s) and {} warning(s) found.", errors, findings.len() - errors);
+}
+
+/// Function to print how every file did on every test case
+fn print_autograder_progress(autograder: &AutoGrader) {
+    for (test_case, results) in autograder.test_cases.iter().zip(autograder.results.iter()) {
+        println!(
+            "{} [{}]",
+            "Grading Testcase".bold().blue(),
+            test_case.as_string().bold()
+        );
+
+        for (file_name, grade_result) in autograder.file_names.iter().zip(results.iter()) {
+            let grade_result_string = if grade_result.get_exit_code() != 0 {
+                format!("{} [{}]", "FAILED".bold().red(), grade_result.get_exit_name())
+            } else if !grade_result.test_case_matches() {
+                format!("{} [Outputs don't match]", "FAILED".bold().red())
+            } else {
+                format!("{} [{}]", "PASSED".bold().green(), grade_result.get_exit_name())
+            };
+
+            println!(
+                "- {} {:45} {} {}",
+                "Graded".bold().green(),
+                file_name,
+                ":".bold(),
+                grade_result_string,
+            );
+        }
+    }
+}
+
+/// Function to print a summary table of every graded file
+fn print_autograder_results(autograder: &AutoGrader) {
+    let top_line =
+        "█▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀▀▀▀█▀▀▀▀▀▀▀▀▀▀▀█";
+    let bottom_line =
+        "█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄█";
+
+    println!("\n{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
+    println!(
+        "{}{}{}",
+        "████".yellow(),
+        "       GRADING SUCCESSFUL       ".green().bold(),
+        "████".yellow()
+    );
+    println!("{}", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+    println!();
+    println!("{}", top_line);
+    println!(
+        "█ {}                                  █ {} █ {} █ {} █ {} █",
+        "Name of File".bold(),
+        "# Error Cases".bold(),
+        "# Fail Cases".bold(),
+        "# Pass Cases".bold(),
+        "Pass/Fail".bold()
+    );
+    println!("{}", bottom_line);
+    println!();
+    println!("{}", top_line);
+    for i in 0..autograder.file_names.len() {
+        let grade_cases_all = autograder
+            .results
+            .iter()
+            .map(|result| result[i].clone())
+            .collect::<Vec<GradeCase>>();
+
+        // Cases that failed with a runtime error
+        let cases_errored: Vec<&GradeCase> = grade_cases_all
+            .iter()
+            .filter(|x| x.get_exit_code() != 0)
+            .collect();
+
+        // Cases that did not match expected test case
+        let cases_failed: Vec<&GradeCase> = grade_cases_all
+            .iter()
+            .filter(|x| x.get_exit_code() == 0 && !x.test_case_matches())
+            .collect();
+
+        // Cases that passed
+        let cases_passed: Vec<&GradeCase> = grade_cases_all
+            .iter()
+            .filter(|x| x.passes())
+            .collect();
+
+        let pass_fail_emoji = if cases_passed.len() == autograder.results.len() {
+            "P".to_string().bold().green()
+        } else {
+            "F".to_string().bold().red()
+        };
+        let mut output_string = format!(
+            "█ {:45} █ {:13} █ {:12} █ {:12} █ {}  {:6} █",
+            autograder.file_names[i],
+            cases_errored.len(),
+            cases_failed.len(),
+            cases_passed.len(),
+            pass_fail_emoji,
+            format!("{}/{}", cases_passed.len(), autograder.results.len(),),
+        );
+
+        // Increase readability by making every other line a different color
+        if i % 2 == 0 {
+            // "bright black" is just grey
+            output_string = format!("{}", output_string.on_bright_black());
+        }
+
+        output_string = format!("{}", output_string.bold());
+
+        println!("{}", output_string);
+    }
+    println!("{}", bottom_line);
+}
+
+/// Function to write a program to a file, in the format given by its extension.
+/// Binaries keep the program's symbols and source map unless `strip` is set
+fn write_output(output_file: &str, assembly: &Assembly, strip: bool) -> std::io::Result<()> {
+    let compiled_text = assembly.instructions.clone();
+
+    if output_file.ends_with(UNCOMPILED) {
+        write_uncompiled_hmmm(output_file, compiled_text)
+    } else if output_file.ends_with(LISTING) {
+        write_listing(output_file, assembly)
+    } else if output_file.ends_with(COMPILED) && strip {
+        write_compiled_hmmm(output_file, compiled_text)
+    } else if output_file.ends_with(COMPILED) {
+        write_compiled_assembly(output_file, assembly)
+    } else if output_file.ends_with(PACKED) && strip {
+        write_packed_hmmm(output_file, &Assembly::from_instructions(compiled_text))
+    } else if output_file.ends_with(PACKED) {
+        write_packed_hmmm(output_file, assembly)
+    } else if let Some(format) = ImageFormat::from_path(output_file) {
+        write_file(output_file, &image::export_image(&compiled_text, format))
+    } else {
+        println!("No output type specified, writing as binary...");
+        // If no ending, just tack on a .hb extension and write out as binary
+        write_compiled_hmmm(format!("{}.hb", output_file).as_str(), compiled_text)
+    }
+}
+
+/// Function to print every problem found while linking
+fn raise_link_errors(errors: &[LinkError]) {
+    println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
+    println!(
+        "{}{}{}",
+        "████".yellow(),
+        "       LINKING UNSUCCESSFUL     ".red().bold(),
+        "████".yellow()
+    );
+    println!("{}\n", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+
+    for error in errors {
+        println!(
+            "{} {:?}",
+            format!(" ERROR IN {}:", error.object).on_red().white().bold(),
+            error.error
+        );
+        println!("      █ {}\n", error.message);
+    }
+}
+
+/// Function to link objects into one program, for the link subcommand.
+/// Objects can be given as .ho files, or as .hmmm files to be assembled first
+fn link_objects(matches: &ArgMatches) {
+    let mut objects: Vec<Object> = Vec::new();
+
+    for path in matches.values_of("objects").unwrap() {
+        if path.ends_with(UNCOMPILED) {
+            match assembler::assemble_object(&load_or_exit(path), path) {
+                Ok(object) => objects.push(object),
+                Err(diagnostics) => {
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            }
+        } else if path.ends_with(OBJECT) {
+            match read_object(path) {
+                Ok(Ok(object)) => objects.push(object),
+                Ok(Err(err)) => {
+                    println!("Error: Cannot read object \"{}\": {}", path, err);
+                    exit(err.as_code())
+                }
+                Err(err) => {
+                    println!("Error: Cannot open file \"{}\": {}", path, err);
+                    exit(1);
+                }
+            }
+        } else {
+            println!("Error: Only {} and {} files can be linked", OBJECT, UNCOMPILED);
+            exit(1);
+        }
+    }
+
+    let assembly = match linker::link(&objects) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            raise_link_errors(&errors);
+            exit(errors[0].error.as_code())
+        }
+    };
+
+    let output_file = matches.value_of("output").unwrap();
+
+    match write_output(output_file, &assembly, matches.is_present("strip")) {
+        Ok(()) => println!(
+            "{} {} ({} words)",
+            "Linked:".bold().on_green(),
+            output_file.bold(),
+            assembly.instructions.len()
+        ),
+        Err(err) => {
+            println!("Error: Cannot write file \"{}\": {}", output_file, err);
+            exit(1);
+        }
+    }
+}
+
+fn run() -> terminal::error::Result<()> {
+    // Create the terminal object just to have an easy way
+    // to clear it
+    let terminal = terminal::stdout();
+    terminal.act(Action::ClearTerminal(Clear::All))?;
+
+    // Setup command line matches
+    let matches = App::new("HMMM Compiler")
+        .version("1.0")
+        .author("Ethan Vazquez <edv121@outlook.com>")
+        .about("A compiler, decompiler, debugger, and simulator for Harvey Mudd Miniature Machine (HMMM)")
+        .arg(Arg::with_name("input")
+                 .short("i")
+                 .long("input")
+                 .takes_value(true)
+                 .help("Input .hmmm, .hb or .hbin file, a .hex, .rom, .memh or .memb memory image, or a .hsnap snapshot to carry on running"))
+        .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .takes_value(true)
+                 .help("Output location of either .hmmm, .hb, .hbin, .ho (object) or .lst file, or a .hex (Intel HEX), .rom (Logisim), .memh ($readmemh) or .memb ($readmemb) memory image"))
+        .arg(Arg::with_name("debug")
+                 .short("d")
+                 .long("debug")
+                 .takes_value(false)
+                 .help("Use debug mode for stepping through simulator"))
+        .arg(Arg::with_name("no-run")
+                 .short("n")
+                 .long("no-run")
+                 .takes_value(false)
+                 .help("Do not simulate (run) the program on compilation"))
+        .arg(Arg::with_name("speed")
+                 .short("s")
+                 .long("speed")
+                 .takes_value(true)
+                 .help("Sets the multiplier (speed) of debug mode (eg: .5 is half speed, 2 is double)"))
+        .arg(Arg::with_name("listing")
+                 .short("L")
+                 .long("listing")
+                 .takes_value(false)
+                 .help("Also write a .lst listing of the compiled program, next to the --output file (or the input file if there is none)"))
+        .arg(Arg::with_name("fmt")
+                 .short("f")
+                 .long("fmt")
+                 .takes_value(false)
+                 .help("Rewrite a .hmmm file in a canonical layout instead of running it. Writes to --output if given, otherwise the input file is overwritten"))
+        .arg(Arg::with_name("renumber")
+                 .short("r")
+                 .long("renumber")
+                 .takes_value(false)
+                 .help("With --fmt, number every instruction by its address, and update jumps and calls to match"))
+        .arg(Arg::with_name("disassemble")
+                 .short("D")
+                 .long("disassemble")
+                 .takes_value(false)
+                 .help("Write the program as annotated .hmmm source instead of running it, with labels for jump and call targets and a comment on every instruction. Writes to --output if given, otherwise prints it"))
+        .arg(Arg::with_name("strict")
+                 .long("strict")
+                 .takes_value(false)
+                 .help("Stop with an error when the program runs a word it wrote as data, or reads an instruction as data, instead of treating memory as plain numbers"))
+        .arg(Arg::with_name("overflow")
+                 .long("overflow")
+                 .value_name("POLICY")
+                 .takes_value(true)
+                 .possible_values(&["trap", "wrap", "saturate"])
+                 .help("What happens when arithmetic, or a number given to read, doesn't fit in a register: stop with an error (trap, the default), keep the low 16 bits (wrap), or clamp to -32768 or 32767 (saturate)"))
+        .arg(Arg::with_name("snapshot")
+                 .long("snapshot")
+                 .value_name("FILE")
+                 .takes_value(true)
+                 .help("Save the simulator's state to a .hsnap file when the program stops, whether it halts or fails. Give the snapshot to --input to carry on from where it stopped, or to look at it in the debugger"))
+        .arg(Arg::with_name("strip")
+                 .long("strip")
+                 .takes_value(false)
+                 .help("Leave the symbols and source map out of a .hb or .hbin --output file. Without this, they're kept so the debugger and runtime errors can show source lines when running the binary"))
+        .arg(Arg::with_name("lint")
+                 .short("l")
+                 .long("lint")
+                 .takes_value(false)
+                 .help("Check the program for common mistakes instead of running it. Exits with 40 if there are only warnings, or 41 if there are errors"))
+        .arg(Arg::with_name("autograder")
+                 .short("a")
+                 .long("autograder")
+                 .takes_value(true)
+                 .help("Toggles the AutoGrader functionality, expecting a test string to be given. If enabled, expects a directory path instead of a file path for --input and --output. --debug, --no-run, and --speed are ignored in this mode."))         
+        .subcommand(SubCommand::with_name("link")
+                 .about("Links .ho objects (or .hmmm files, assembled as objects) into one program")
+                 .arg(Arg::with_name("objects")
+                          .required(true)
+                          .multiple(true)
+                          .help("Objects to link, placed in memory in the order given"))
+                 .arg(Arg::with_name("output")
+                          .short("o")
+                          .long("output")
+                          .takes_value(true)
+                          .required(true)
+                          .help("Output location of the linked program, in any format --output accepts"))
+                 .arg(Arg::with_name("strip")
+                          .long("strip")
+                          .takes_value(false)
+                          .help("Leave the symbols and source map out of the linked program")))
+        .get_matches();
+
+    if let Some(link_matches) = matches.subcommand_matches("link") {
+        link_objects(link_matches);
+        exit(0);
+    }
+
+    if matches.value_of("input").is_none() {
+        println!("Error: Please specify a file to compile/run!");
+        exit(1);
+    } else {
+        // Print out startup message
+        println!(
+            "{} ████    ████  ████    ████",
+            "██    ██  ████    ████ ".yellow()
+        );
+        println!(
+            "{} ██ ██  ██ ██  ██ ██  ██ ██",
+            "██    ██  ██ ██  ██ ██ ".yellow()
+        );
+        println!(
+            "{} ██  ████  ██  ██  ████  ██",
+            "████████  ██  ████  ██ ".yellow()
+        );
+        println!(
+            "{} ██   ██   ██  ██   ██   ██",
+            "██    ██  ██   ██   ██ ".yellow()
+        );
+        println!(
+            "{} ██        ██  ██        ██",
+            "██    ██  ██        ██ ".yellow()
+        );
+        println!(
+            "{}",
+            " HARVEY       MUDD       MINIATURE      MACHINE   "
+                .black()
+                .dimmed()
+                .italic()
+                .bold()
+                .on_white()
+        );
+
+        println!();
+
+        let file_path: &str = matches.value_of("input").unwrap().trim_start_matches(".\\");
+
+        if matches.value_of("autograder").is_some() {
+            println!("{}\n", "AutoGrader Mode Enabled".bold().on_green());
+            let path = file_path.trim_matches(&['\\', '/'] as &[_]);
+            let mut autograder =
+                match AutoGrader::new_from_cmd(path, matches.value_of("autograder").unwrap()) {
+                    Ok(autograder) => autograder,
+                    Err(err) => {
+                        println!("{} {}", "AutoGrader Failed:".bold().on_red(), err);
+                        exit(1);
+                    }
+                };
+            autograder.grade_all();
+            print_autograder_progress(&autograder);
+            print_autograder_results(&autograder);
+            let export_result = autograder.export_results(path);
+
+            match export_result {
+                Ok(export_path) => println!("\n{} {}\n", "AutoGrader Export Successful:".bold().on_green(), export_path.bold()),
+                Err(_) => println!("\n{}\n", "AutoGrader Export Failed".bold().on_red()),
+            }
+            exit(0);
+        }
+
+        if matches.is_present("fmt") {
+            if !file_path.ends_with(UNCOMPILED) {
+                println!("Error: Only {} files can be formatted", UNCOMPILED);
+                exit(1);
+            }
+
+            let formatted = match formatter::format_hmmm_from(&load_or_exit(file_path), file_path, matches.is_present("renumber")) {
+                Ok(formatted) => formatted,
+                Err(diagnostics) => {
+                    println!("Error: Only programs that compile can be renumbered");
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            };
+            let output_file = matches.value_of("output").unwrap_or(file_path);
+
+            match write_file(output_file, &formatted.join("\n")) {
+                Ok(()) => println!("{} {}", "Formatted:".bold().on_green(), output_file.bold()),
+                Err(err) => {
+                    println!("Error: Cannot write file \"{}\": {}", output_file, err);
+                    exit(1);
+                }
+            }
+            exit(0);
+        }
+
+        // Objects are only assembled here, and have to be linked before they can run
+        if file_path.ends_with(UNCOMPILED) && matches!(matches.value_of("output"), Some(output) if output.ends_with(OBJECT)) {
+            let output_file = matches.value_of("output").unwrap();
+
+            match assembler::assemble_object(&load_or_exit(file_path), file_path) {
+                Ok(object) => match write_object(output_file, &object) {
+                    Ok(()) => println!("{} {}", "Assembled object:".bold().on_green(), output_file.bold()),
+                    Err(err) => {
+                        println!("Error: Cannot write file \"{}\": {}", output_file, err);
+                        exit(1);
+                    }
+                },
+                Err(diagnostics) => {
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            }
+            exit(0);
+        }
+
+        // Setup the compiled program, along with where it came
+        // from if the source is available
+        let assembly: Assembly;
+        // A simulator restored from a snapshot, to carry on running
+        let mut resumed: Option<Simulator> = None;
+
+        // Check to see what type of file is being loaded
+        if file_path.ends_with(UNCOMPILED) {
+            // If it's uncompiled, load it
+            let uncompiled_text = load_or_exit(file_path);
+
+            // Then, compile it into Instruction structs
+            match assembler::assemble_from(&uncompiled_text, file_path) {
+                Ok(assembled) => assembly = assembled,
+                Err(diagnostics) => {
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            }
+        } else if file_path.ends_with(COMPILED) {
+            // If it's already compiled, load it
+            let raw_binary = load_or_exit(file_path);
+
+            // Then, interpret it into Instruction structs, along
+            // with its debug section if it has one
+            match read_compiled_assembly(raw_binary) {
+                Ok(compiled) => assembly = compiled,
+                Err(diagnostics) => {
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            }
+        } else if file_path.ends_with(PACKED) {
+            // Packed binaries carry their own symbols and source map
+            match read_packed_hmmm(file_path) {
+                Ok(Ok(unpacked)) => assembly = unpacked,
+                Ok(Err(err)) => {
+                    println!("Error: Cannot read packed binary \"{}\": {}", file_path, err);
+                    exit(err.as_code())
+                }
+                Err(err) => {
+                    println!("Error: Cannot open file \"{}\": {}", file_path, err);
+                    exit(1);
+                }
+            }
+        } else if file_path.ends_with(SNAPSHOT) {
+            // Snapshots are shown as memory stood when they were saved
+            match read_snapshot(file_path) {
+                Ok(Ok(sim)) => {
+                    let size = (0..256).rev().find(|address| sim.get_word(*address) != Some(0)).map_or(0, |address| address + 1);
+                    let mut memory = Assembly::from_instructions((0..size).filter_map(|address| sim.get_memory(address)).collect());
+                    memory.source_map = sim.source_map.clone();
+
+                    assembly = memory;
+                    resumed = Some(sim);
+                }
+                Ok(Err(err)) => {
+                    println!("Error: Cannot read snapshot \"{}\": {}", file_path, err);
+                    exit(err.as_code())
+                }
+                Err(err) => {
+                    println!("Error: Cannot open file \"{}\": {}", file_path, err);
+                    exit(1);
+                }
+            }
+        } else if let Some(format) = ImageFormat::from_path(file_path) {
+            // Memory images are read back in the same way as a .hb file
+            let image_text = load_or_exit(file_path);
+
+            match image::import_image(&image_text, format) {
+                Ok(instructions) => assembly = Assembly::from_instructions(instructions),
+                Err(diagnostics) => {
+                    raise_compile_errors(&diagnostics);
+                    exit(diagnostics[0].error.as_code())
+                }
+            }
+        } else {
+            println!(
+                "Error: Unknown filetype! Expected a {}, {}, {} or {} file, or a memory image",
+                UNCOMPILED, COMPILED, PACKED, SNAPSHOT
+            );
+            exit(1);
+        }
+
+        let compiled_text = assembly.instructions.clone();
+
+        if matches.is_present("disassemble") {
+            let disassembled = disassembler::disassemble(&assembly).join("\n");
+
+            match matches.value_of("output") {
+                Some(output_file) => match write_file(output_file, &disassembled) {
+                    Ok(()) => println!("{} {}", "Disassembled:".bold().on_green(), output_file.bold()),
+                    Err(err) => {
+                        println!("Error: Cannot write file \"{}\": {}", output_file, err);
+                        exit(1);
+                    }
+                },
+                None => println!("{}", disassembled),
+            }
+            exit(0);
+        }
+
+        if matches.is_present("lint") {
+            let findings = lint::lint(&compiled_text, &assembly.source_map);
+            print_lint_findings(&findings);
+
+            match findings.iter().map(|finding| finding.severity).max() {
+                Some(Severity::Error) => exit(41),
+                Some(Severity::Warning) => exit(40),
+                None => exit(0),
+            }
+        }
+        // If compiles without error, print out a success
+        // message and the first 9 lines, with the last being
+        // printed also if there are > 9 lines
+        println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
+        println!(
+            "{}{}{}",
+            "████".yellow(),
+            "     COMPILATION SUCCESSFUL     ".green().bold(),
+            "████".yellow()
+        );
+        println!("{}", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+        println!("\n");
+        println!("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀");
+        println!("█ Line █ Command █ Arguments           █");
+        println!("▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄");
+
+        for (index, line) in compiled_text.iter().enumerate() {
+            if index > 9 {
+                // Print seperator to show the jump in line number
+                println!("........................................");
+                let last = compiled_text.last().unwrap();
+                println!(
+                    "█ {:4} █ {:7} █ {:19} █  >>    {}",
+                    compiled_text.len() - 1,
+                    last.instruction_type.names[0],
+                    last.text_contents,
+                    last.binary_contents.join(" ")
+                );
+                break;
+            }
+            println!(
+                "█ {:4} █ {:7} █ {:19} █  >>    {}",
+                index,
+                line.instruction_type.names[0],
+                line.text_contents,
+                line.binary_contents.join(" ")
+            );
+        }
+
+        println!("█▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█\n\n");
+
+        // Output file if given path
+        if matches.value_of("output").is_some() {
+            let output_file = matches.value_of("output").unwrap();
+
+            if write_output(output_file, &assembly, matches.is_present("strip")).is_err() {
+                println!("Error writing output file! Continuing...");
+            }
+        }
+
+        // Write a listing next to the output (or input) file if asked for
+        if matches.is_present("listing") {
+            let base = matches.value_of("output").unwrap_or(file_path);
+            let listing_file = Path::new(base).with_extension(LISTING.trim_start_matches('.'));
+
+            if write_listing(&listing_file.to_string_lossy(), &assembly).is_err() {
+                println!("Error writing listing file! Continuing...");
+            }
+        }
+
+        // Run simulation if --no-run flag is not present
+        if !matches.is_present("no-run") {
+            // Create it as new struct from compiled HMMM, unless
+            // carrying on from a snapshot
+            let mut simulator = match resumed {
+                Some(simulator) => simulator,
+                None => {
+                    let mut simulator = Simulator::new(compiled_text);
+                    simulator.set_source_map(assembly.source_map.clone());
+                    simulator
+                }
+            };
+
+            if matches.is_present("strict") {
+                simulator.set_strict(true);
+            }
+
+            if let Some(overflow) = matches.value_of("overflow") {
+                simulator.set_overflow_policy(overflow.parse().unwrap_or_default());
+            }
+            let debug_multiplier = matches
+                .value_of("speed")
+                .unwrap_or("1")
+                .parse::<f64>()
+                .unwrap_or(1.0);
+
+            if matches.is_present("debug") {
+                println!("{}", "ENTERING DEBUGGING MODE...".on_red());
+                simulator.set_debug(true);
+                simulator.set_input_source(Console::debugger());
+                simulator.set_output_sink(Console::debugger());
+                simulator.set_history_limit(DEBUGGER_HISTORY);
+                thread::sleep(time::Duration::from_millis(
+                    200_u64,
+                ));
+                terminal.act(Action::ClearTerminal(Clear::All))?;
+                terminal.act(Action::DisableBlinking)?;
+                terminal.act(Action::HideCursor)?;
+            }
+
+            loop {
+                if simulator.is_debug() {
+                    print_debug_screen(&simulator)?;
+                    if debug_multiplier == 0. {
+                        // Commands that don't step forward redraw the screen and ask again
+                        while debugger_command(&mut simulator)? {
+                            print_debug_screen(&simulator)?;
+                        }
+                    } else {
+                        thread::sleep(time::Duration::from_millis(
+                            (500. / debug_multiplier) as u64,
+                        ));
+                    }
+                }
+                // Attempt to run a step in the simulator
+                let result = &simulator.step();
+                // If it's an error, raise it
+                if result.is_err() {
+                    // Don't trap the user without a cursor,
+                    // make sure to show it on exit
+                    // Hopefully the program doesn't hard crash because if it does,
+                    // the cursor might not be visible
+                    terminal.act(Action::ShowCursor)?;
+                    let result_err = result.as_ref().unwrap_err();
+                    // If the error is Halt, exit quietly, as that is the
+                    // program successfully finishing
+                    if result_err.kind == RuntimeErr::Halt {
+                        if simulator.is_debug() {
+                            terminal.act(Action::MoveCursorTo(0, 31))?;
+                        }
+
+                        println!(
+                            "{}",
+                            "Program has reached end, exiting...".black().on_green()
+                        );
+                        save_snapshot(&matches, &simulator);
+
+                        exit(0);
+                    } else {
+                        // If not, raise that error!
+                        terminal.act(Action::ClearTerminal(Clear::All))?;
+                        // Prints out the debug screen as well as the the error
+                        raise_runtime_error(&simulator, result_err);
+                        let exit_code = &result_err.as_code();
+
+                        // Move the terminal prompt to the bottom of the screen
+                        for _ in 0..16 {
+                            println!("\n");
+                        }
+                        save_snapshot(&matches, &simulator);
+                        exit(*exit_code);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Saves the simulator's state to the --snapshot file, if one was given
+fn save_snapshot(matches: &ArgMatches, simulator: &Simulator) {
+    if let Some(path) = matches.value_of("snapshot") {
+        match write_snapshot(path, simulator) {
+            Ok(()) => println!("{} {}", "Snapshot saved:".bold().on_green(), path.bold()),
+            Err(err) => println!("Error: Cannot write snapshot \"{}\": {}", path, err),
+        }
+    }
+}
+
 fn main() {
-    hmmm_rs::main().unwrap();
+    run().unwrap();
     exit(0);
-}
\ No newline at end of file
+}
@@ -0,0 +1,489 @@
+use super::assembler::{Assembly, SourceLocation, SourceMap};
+use super::linker::{Object, Relocation, RelocationField};
+use super::simulator::*;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// First bytes of every packed file
+pub const MAGIC: &[u8; 4] = b"HMMM";
+
+/// First bytes of every packed object, which has to be linked before it's run
+pub const OBJECT_MAGIC: &[u8; 4] = b"HMMO";
+
+/// Version of the packed format written by pack. Files from older
+/// versions can still be unpacked, and newer ones are refused with
+/// UnsupportedVersion, as they may use the words or sections differently
+pub const VERSION: u8 = 1;
+
+/// True if a file of `version` can be read by a reader of version `current`.
+/// Versions start at 1 and only go up, so 0 was never written
+pub(crate) fn is_supported_version(version: u8, current: u8) -> bool {
+    (1..=current).contains(&version)
+}
+
+/// Section holding labels and constants
+const SYMBOLS_SECTION: &[u8; 4] = b"SYMS";
+
+/// Section holding the source map
+const SOURCE_MAP_SECTION: &[u8; 4] = b"SMAP";
+
+/// Section holding the labels an object exports
+const EXPORTS_SECTION: &[u8; 4] = b"EXPT";
+
+/// Section holding an object's relocations
+const RELOCATIONS_SECTION: &[u8; 4] = b"RELO";
+
+/// Marks a source location that has no file
+const NO_FILE: u16 = u16::MAX;
+
+/// Symbol kinds in the symbols section
+const LABEL: u8 = 0;
+const CONSTANT: u8 = 1;
+
+/// Reads values out of a packed file, failing with
+/// CorruptedBinary if the file ends too early
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    pub(crate) fn take(&mut self, count: usize) -> Result<&'a [u8], CompileErr> {
+        let end = self
+            .position
+            .checked_add(count)
+            .ok_or(CompileErr::CorruptedBinary)?;
+        let taken = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(CompileErr::CorruptedBinary)?;
+
+        self.position = end;
+        Ok(taken)
+    }
+
+    /// Number of bytes left to read
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.position)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CompileErr> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, CompileErr> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, CompileErr> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, CompileErr> {
+        let length = self.u16()? as usize;
+        let bytes = self.take(length)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| CompileErr::CorruptedBinary)
+    }
+}
+
+pub(crate) fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(crate) fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Strings are written as a 16-bit length followed by UTF-8,
+/// cut short if they're longer than that
+pub(crate) fn push_string(bytes: &mut Vec<u8>, text: &str) {
+    let mut end = text.len().min(u16::MAX as usize);
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    push_u16(bytes, end as u16);
+    bytes.extend_from_slice(&text.as_bytes()[..end]);
+}
+
+pub(crate) fn push_section(bytes: &mut Vec<u8>, tag: &[u8; 4], contents: Vec<u8>) {
+    bytes.extend_from_slice(tag);
+    push_u32(bytes, contents.len() as u32);
+    bytes.extend(contents);
+}
+
+/// Encodes a word of memory as a 16-bit number
+pub fn instruction_to_word(instruction: &Instruction) -> u16 {
+    u16::from_str_radix(&instruction.binary_contents.join(""), 2).unwrap_or(0)
+}
+
+/// Decodes a 16-bit number into a word of memory
+pub fn word_to_instruction(word: u16) -> Result<Instruction, CompileErr> {
+    Instruction::new_from_binary(&format!("{:016b}", word))
+}
+
+fn pack_symbols(assembly: &Assembly) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_u16(
+        &mut bytes,
+        (assembly.symbols.len() + assembly.constants.len()) as u16,
+    );
+
+    for (name, address) in &assembly.symbols {
+        bytes.push(LABEL);
+        push_string(&mut bytes, name);
+        push_u32(&mut bytes, *address as u32);
+    }
+
+    for (name, value) in &assembly.constants {
+        bytes.push(CONSTANT);
+        push_string(&mut bytes, name);
+        push_u32(&mut bytes, *value as u32);
+    }
+
+    bytes
+}
+
+pub(crate) fn pack_source_map(source_map: &SourceMap) -> Vec<u8> {
+    // File names are stored once, and referred to by their index
+    let mut files: Vec<&str> = Vec::new();
+
+    for (_, location) in source_map.iter() {
+        if let Some(file) = &location.file {
+            if !files.contains(&file.as_str()) {
+                files.push(file);
+            }
+        }
+    }
+
+    let mut bytes = Vec::new();
+    push_u16(&mut bytes, files.len() as u16);
+
+    for file in &files {
+        push_string(&mut bytes, file);
+    }
+
+    push_u16(&mut bytes, source_map.iter().count() as u16);
+
+    for (address, location) in source_map.iter() {
+        let file = match &location.file {
+            Some(file) => files.iter().position(|name| name == file).unwrap() as u16,
+            None => NO_FILE,
+        };
+
+        push_u16(&mut bytes, address as u16);
+        push_u16(&mut bytes, file);
+        push_u32(&mut bytes, location.line as u32);
+        push_u32(&mut bytes, location.column as u32);
+        push_string(&mut bytes, &location.text);
+
+        match &location.comment {
+            Some(comment) => {
+                bytes.push(1);
+                push_string(&mut bytes, comment);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    bytes
+}
+
+fn pack_exports(exports: &BTreeSet<String>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_u16(&mut bytes, exports.len() as u16);
+
+    for name in exports {
+        push_string(&mut bytes, name);
+    }
+
+    bytes
+}
+
+fn pack_relocations(relocations: &[Relocation]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_u16(&mut bytes, relocations.len() as u16);
+
+    for relocation in relocations {
+        push_u16(&mut bytes, relocation.address as u16);
+        bytes.push(match relocation.field {
+            RelocationField::Operand => 0,
+            RelocationField::Word => 1,
+        });
+
+        match &relocation.symbol {
+            Some(symbol) => {
+                bytes.push(1);
+                push_string(&mut bytes, symbol);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    bytes
+}
+
+/// Packs a program into the binary format: the magic bytes "HMMM",
+/// a version byte, the number of words as a 16-bit number, and then
+/// every word as 16-bit big-endian. The symbol table and source map
+/// can be added as optional sections after the words.
+///
+/// Each section is a 4 byte tag, a 32-bit length, and its contents,
+/// so readers can skip sections they don't understand
+pub fn pack(assembly: &Assembly, with_symbols: bool, with_source_map: bool) -> Vec<u8> {
+    pack_with_magic(MAGIC, assembly, with_symbols, with_source_map)
+}
+
+/// Packs an object in the same format as pack, starting with "HMMO"
+/// instead, and always with its symbols, exports and relocations
+pub fn pack_object(object: &Object) -> Vec<u8> {
+    let mut bytes = pack_with_magic(OBJECT_MAGIC, &object.assembly, true, true);
+
+    push_section(&mut bytes, EXPORTS_SECTION, pack_exports(&object.exports));
+    push_section(
+        &mut bytes,
+        RELOCATIONS_SECTION,
+        pack_relocations(&object.relocations),
+    );
+
+    bytes
+}
+
+fn pack_with_magic(
+    magic: &[u8; 4],
+    assembly: &Assembly,
+    with_symbols: bool,
+    with_source_map: bool,
+) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    bytes.push(VERSION);
+    push_u16(&mut bytes, assembly.instructions.len() as u16);
+
+    for instruction in &assembly.instructions {
+        push_u16(&mut bytes, instruction_to_word(instruction));
+    }
+
+    if with_symbols && !(assembly.symbols.is_empty() && assembly.constants.is_empty()) {
+        push_section(&mut bytes, SYMBOLS_SECTION, pack_symbols(assembly));
+    }
+
+    if with_source_map && !assembly.source_map.is_empty() {
+        push_section(
+            &mut bytes,
+            SOURCE_MAP_SECTION,
+            pack_source_map(&assembly.source_map),
+        );
+    }
+
+    bytes
+}
+
+fn unpack_symbols(reader: &mut Reader, assembly: &mut Assembly) -> Result<(), CompileErr> {
+    for _ in 0..reader.u16()? {
+        let kind = reader.u8()?;
+        let name = reader.string()?;
+        let value = reader.u32()?;
+
+        match kind {
+            LABEL => {
+                assembly.symbols.insert(name, value as usize);
+            }
+            CONSTANT => {
+                assembly.constants.insert(name, value as i32);
+            }
+            _ => return Err(CompileErr::CorruptedBinary),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn unpack_source_map(reader: &mut Reader) -> Result<SourceMap, CompileErr> {
+    let mut files = Vec::new();
+
+    for _ in 0..reader.u16()? {
+        files.push(reader.string()?);
+    }
+
+    let mut source_map = SourceMap::new();
+
+    for _ in 0..reader.u16()? {
+        let address = reader.u16()? as usize;
+        let file = match reader.u16()? {
+            NO_FILE => None,
+            index => Some(
+                files
+                    .get(index as usize)
+                    .ok_or(CompileErr::CorruptedBinary)?
+                    .clone(),
+            ),
+        };
+        let line = reader.u32()? as usize;
+        let column = reader.u32()? as usize;
+        let text = reader.string()?;
+        let comment = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.string()?),
+        };
+
+        source_map.insert(
+            address,
+            SourceLocation {
+                file,
+                line,
+                column,
+                text,
+                comment,
+            },
+        );
+    }
+
+    Ok(source_map)
+}
+
+fn unpack_exports(reader: &mut Reader) -> Result<BTreeSet<String>, CompileErr> {
+    let mut exports = BTreeSet::new();
+
+    for _ in 0..reader.u16()? {
+        exports.insert(reader.string()?);
+    }
+
+    Ok(exports)
+}
+
+fn unpack_relocations(reader: &mut Reader) -> Result<Vec<Relocation>, CompileErr> {
+    let mut relocations = Vec::new();
+
+    for _ in 0..reader.u16()? {
+        let address = reader.u16()? as usize;
+        let field = match reader.u8()? {
+            0 => RelocationField::Operand,
+            1 => RelocationField::Word,
+            _ => return Err(CompileErr::CorruptedBinary),
+        };
+        let symbol = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.string()?),
+        };
+
+        relocations.push(Relocation {
+            address,
+            field,
+            symbol,
+        });
+    }
+
+    Ok(relocations)
+}
+
+/// True if the bytes start like a packed file
+pub fn is_packed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// True if the bytes start like a packed object
+pub fn is_packed_object(bytes: &[u8]) -> bool {
+    bytes.starts_with(OBJECT_MAGIC)
+}
+
+/// Reads a program packed with pack. Sections that aren't
+/// understood are skipped, so newer files can still be read
+pub fn unpack(bytes: &[u8]) -> Result<Assembly, CompileErr> {
+    unpack_with_magic(MAGIC, bytes, |_, _| Ok(()))
+}
+
+/// Reads an object packed with pack_object. Its name is left empty
+pub fn unpack_object(bytes: &[u8]) -> Result<Object, CompileErr> {
+    let mut exports = BTreeSet::new();
+    let mut relocations = Vec::new();
+
+    let assembly = unpack_with_magic(OBJECT_MAGIC, bytes, |tag, section| {
+        match tag {
+            EXPORTS_SECTION => exports = unpack_exports(section)?,
+            RELOCATIONS_SECTION => relocations = unpack_relocations(section)?,
+            _ => {}
+        }
+
+        Ok(())
+    })?;
+
+    Ok(Object {
+        name: String::new(),
+        assembly,
+        exports,
+        relocations,
+    })
+}
+
+/// Reads the words and common sections of a packed file, passing
+/// any other section to `other_section`
+fn unpack_with_magic<F>(
+    magic: &[u8; 4],
+    bytes: &[u8],
+    mut other_section: F,
+) -> Result<Assembly, CompileErr>
+where
+    F: FnMut(&[u8; 4], &mut Reader) -> Result<(), CompileErr>,
+{
+    let mut reader = Reader::new(bytes);
+
+    if reader.take(magic.len())? != magic {
+        return Err(CompileErr::CorruptedBinary);
+    }
+
+    if !is_supported_version(reader.u8()?, VERSION) {
+        return Err(CompileErr::UnsupportedVersion);
+    }
+
+    let word_count = reader.u16()? as usize;
+
+    if word_count > 256 {
+        return Err(CompileErr::ProgramTooLarge);
+    }
+
+    let mut instructions = Vec::with_capacity(word_count);
+
+    for _ in 0..word_count {
+        instructions.push(word_to_instruction(reader.u16()?)?);
+    }
+
+    let mut assembly = Assembly::from_instructions(instructions);
+
+    read_sections(&mut reader, |tag, section| match tag {
+        SYMBOLS_SECTION => unpack_symbols(section, &mut assembly),
+        SOURCE_MAP_SECTION => {
+            assembly.source_map = unpack_source_map(section)?;
+            Ok(())
+        }
+        _ => other_section(tag, section),
+    })?;
+
+    Ok(assembly)
+}
+
+/// Passes each section left in `reader` to `section`, along with its tag
+pub(crate) fn read_sections<F>(reader: &mut Reader, mut section: F) -> Result<(), CompileErr>
+where
+    F: FnMut(&[u8; 4], &mut Reader) -> Result<(), CompileErr>,
+{
+    while !reader.is_empty() {
+        let tag = <[u8; 4]>::try_from(reader.take(4)?).unwrap();
+        let length = reader.u32()? as usize;
+
+        section(&tag, &mut Reader::new(reader.take(length)?))?;
+    }
+
+    Ok(())
+}
@@ -1,8 +1,16 @@
 use super::*;
+use super::assembler::{Diagnostic, SourceLocation, SourceMap};
+use super::breakpoints::{Access, Breakpoint, Location, Stop, StopPoints, Watchpoint};
+use super::devices::{Console, Device, InputSource, OutputSink, Writer};
+use super::history::{History, MemoryWrite, RegisterWrite, Step};
 use lazy_static::lazy_static;
-use std::io;
-use std::io::stdin;
-use std::io::BufRead;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::ops::RangeInclusive;
 
 lazy_static! {
     static ref INSTRUCTION_LOOKUP: Vec<InstructionType> = vec![
@@ -58,14 +66,14 @@ lazy_static! {
         InstructionType::new(
             vec!["loadr", "loadi", "load"],
             "0100 0000 0000 0000",
-            "1111 0000 0000 0000",
+            "1111 0000 0000 1111",
             "rr",
             "Load register _ with memory data indexed by register _"
         ),
         InstructionType::new(
             vec!["storer", "storei", "store"],
             "0100 0000 0000 0001",
-            "1111 0000 0000 0000",
+            "1111 0000 0000 1111",
             "rr",
             "Store register _ in memory indexed by register _"
         ),
@@ -248,6 +256,193 @@ impl InstructionType {
             human_explanation,
         }
     }
+
+    /// Human-readable description of an argument type from `arguments`
+    pub fn describe_argument(argument_type: char) -> &'static str {
+        match argument_type {
+            'r' => "register (r0 to r15)",
+            's' => "signed number (-128 to 127)",
+            'u' => "unsigned number (0 to 255)",
+            'n' => "16-bit number (-32768 to 65535)",
+            _ => "nothing",
+        }
+    }
+
+    /// Range of values a numeric argument type can hold
+    pub fn argument_range(argument_type: char) -> Option<RangeInclusive<i32>> {
+        match argument_type {
+            's' => Some(i8::MIN as i32..=i8::MAX as i32),
+            'u' => Some(0..=u8::MAX as i32),
+            'n' => Some(i16::MIN as i32..=u16::MAX as i32),
+            _ => None,
+        }
+    }
+
+    /// Every name and alias of every instruction
+    pub fn all_names() -> Vec<&'static str> {
+        INSTRUCTION_LOOKUP
+            .iter()
+            .flat_map(|instruction| instruction.names.iter().copied())
+            .collect()
+    }
+
+    /// Looks up an instruction type by its name or any of its aliases
+    pub fn from_name(name: &str) -> Option<InstructionType> {
+        INSTRUCTION_LOOKUP
+            .iter()
+            .find(|instruction| instruction.names.contains(&name))
+            .cloned()
+    }
+
+    /// True if the bits of `word` fixed by the mask string
+    /// are the same as in the match string
+    pub fn matches_word(&self, word: u16) -> bool {
+        let mask = pattern_to_word(self.mask_string);
+        word & mask == pattern_to_word(self.match_string) & mask
+    }
+
+    /// Number of bits the mask string fixes
+    pub fn fixed_bits(&self) -> u32 {
+        pattern_to_word(self.mask_string).count_ones()
+    }
+
+    /// Finds the instruction a word of memory decodes to, or None if it's data.
+    ///
+    /// Some instructions are special cases of others, and share their
+    /// encoding: nop is add r0 r0 r0, copy rX rY is add rX rY r0, neg rX rY
+    /// is sub rX r0 rY, and jumpn N is calln r0 N. When more than one
+    /// instruction matches, the one with the most fixed bits wins, so the
+    /// special case is always chosen, matching the original HMMM. No two
+    /// instructions that match the same word fix the same number of bits,
+    /// so every word decodes to exactly one instruction or to data
+    pub fn decode(word: u16) -> Option<InstructionType> {
+        INSTRUCTION_LOOKUP
+            .iter()
+            .filter(|instruction| instruction.names[0] != "data" && instruction.matches_word(word))
+            .max_by_key(|instruction| instruction.fixed_bits())
+            .cloned()
+    }
+}
+
+/// Every instruction the simulator can run, so a word only has to be
+/// decoded once rather than on every step
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Opcode {
+    Halt,
+    Read,
+    Write,
+    Jumpr,
+    Setn,
+    Loadn,
+    Storen,
+    Loadr,
+    Storer,
+    Popr,
+    Pushr,
+    Addn,
+    Nop,
+    Copy,
+    Add,
+    Neg,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Jumpn,
+    Calln,
+    Jeqzn,
+    Jnezn,
+    Jgtzn,
+    Jltzn,
+    /// A word that doesn't decode as any instruction
+    Data,
+}
+
+impl Opcode {
+    /// Decodes a word in the same way as InstructionType::decode,
+    /// straight from its bits
+    pub fn decode(word: u16) -> Opcode {
+        let reg_x = (word >> 8) & 0xF;
+        let reg_y = (word >> 4) & 0xF;
+        let reg_z = word & 0xF;
+
+        match word >> 12 {
+            0x0 if word == 0 => Opcode::Halt,
+            0x0 if reg_y == 0 => match reg_z {
+                1 => Opcode::Read,
+                2 => Opcode::Write,
+                3 => Opcode::Jumpr,
+                _ => Opcode::Data,
+            },
+            0x0 => Opcode::Data,
+            0x1 => Opcode::Setn,
+            0x2 => Opcode::Loadn,
+            0x3 => Opcode::Storen,
+            0x4 => match reg_z {
+                0 => Opcode::Loadr,
+                1 => Opcode::Storer,
+                2 => Opcode::Popr,
+                3 => Opcode::Pushr,
+                _ => Opcode::Data,
+            },
+            0x5 => Opcode::Addn,
+            0x6 if word == 0x6000 => Opcode::Nop,
+            0x6 if reg_z == 0 => Opcode::Copy,
+            0x6 => Opcode::Add,
+            0x7 if reg_y == 0 => Opcode::Neg,
+            0x7 => Opcode::Sub,
+            0x8 => Opcode::Mul,
+            0x9 => Opcode::Div,
+            0xA => Opcode::Mod,
+            0xB if reg_x == 0 => Opcode::Jumpn,
+            0xB => Opcode::Calln,
+            0xC => Opcode::Jeqzn,
+            0xD => Opcode::Jnezn,
+            0xE => Opcode::Jgtzn,
+            _ => Opcode::Jltzn,
+        }
+    }
+
+    /// Main name of the instruction, as used by InstructionType
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::Halt => "halt",
+            Opcode::Read => "read",
+            Opcode::Write => "write",
+            Opcode::Jumpr => "jumpr",
+            Opcode::Setn => "setn",
+            Opcode::Loadn => "loadn",
+            Opcode::Storen => "storen",
+            Opcode::Loadr => "loadr",
+            Opcode::Storer => "storer",
+            Opcode::Popr => "popr",
+            Opcode::Pushr => "pushr",
+            Opcode::Addn => "addn",
+            Opcode::Nop => "nop",
+            Opcode::Copy => "copy",
+            Opcode::Add => "add",
+            Opcode::Neg => "neg",
+            Opcode::Sub => "sub",
+            Opcode::Mul => "mul",
+            Opcode::Div => "div",
+            Opcode::Mod => "mod",
+            Opcode::Jumpn => "jumpn",
+            Opcode::Calln => "calln",
+            Opcode::Jeqzn => "jeqzn",
+            Opcode::Jnezn => "jnezn",
+            Opcode::Jgtzn => "jgtzn",
+            Opcode::Jltzn => "jltzn",
+            Opcode::Data => "data",
+        }
+    }
+}
+
+/// Reads a match or mask string such as "1111 0000 0000 1111" as a word
+fn pattern_to_word(pattern: &str) -> u16 {
+    pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .fold(0, |word, c| word << 1 | (c == '1') as u16)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -263,6 +458,17 @@ pub enum CompileErr {
     CorruptedBinary,
     LineNumberNotPresent,
     InvalidLineNumber,
+    UndefinedLabel,
+    DuplicateLabel,
+    InvalidLabel,
+    ProgramTooLarge,
+    UnknownDirective,
+    InvalidOrigin,
+    IncludeNotFound,
+    IncludeCycle,
+    InvalidMacro,
+    MacroRecursion,
+    UnsupportedVersion,
 }
 
 impl CompileErr {
@@ -279,10 +485,54 @@ impl CompileErr {
             CompileErr::CorruptedBinary => 18,
             CompileErr::LineNumberNotPresent => 19,
             CompileErr::InvalidLineNumber => 20,
+            CompileErr::UndefinedLabel => 21,
+            CompileErr::DuplicateLabel => 22,
+            CompileErr::InvalidLabel => 23,
+            CompileErr::ProgramTooLarge => 24,
+            CompileErr::UnknownDirective => 25,
+            CompileErr::InvalidOrigin => 26,
+            CompileErr::IncludeNotFound => 27,
+            CompileErr::IncludeCycle => 28,
+            CompileErr::InvalidMacro => 29,
+            CompileErr::MacroRecursion => 30,
+            CompileErr::UnsupportedVersion => 31,
         }
     }
 }
 
+impl fmt::Display for CompileErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            CompileErr::InstructionDoesNotExist => "instruction does not exist",
+            CompileErr::InvalidArgumentType => "argument is the wrong type",
+            CompileErr::InvalidRegister => "invalid register",
+            CompileErr::TooManyArguments => "too many arguments",
+            CompileErr::TooFewArguments => "too few arguments",
+            CompileErr::InvalidSignedNumber => "invalid signed number",
+            CompileErr::InvalidUnsignedNumber => "invalid unsigned number",
+            CompileErr::InvalidNumber => "invalid number",
+            CompileErr::CorruptedBinary => "corrupted binary",
+            CompileErr::LineNumberNotPresent => "line number not present",
+            CompileErr::InvalidLineNumber => "invalid line number",
+            CompileErr::UndefinedLabel => "label is not defined",
+            CompileErr::DuplicateLabel => "label is already defined",
+            CompileErr::InvalidLabel => "invalid label name",
+            CompileErr::ProgramTooLarge => "program does not fit in 256 words of memory",
+            CompileErr::UnknownDirective => "directive does not exist",
+            CompileErr::InvalidOrigin => "address is already in use",
+            CompileErr::IncludeNotFound => "included file cannot be found",
+            CompileErr::IncludeCycle => "file includes itself",
+            CompileErr::InvalidMacro => "macro definition is invalid",
+            CompileErr::MacroRecursion => "macro expands into itself",
+            CompileErr::UnsupportedVersion => "binary format version is not supported",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for CompileErr {}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Instruction {
     pub instruction_type: InstructionType,
@@ -295,14 +545,7 @@ impl Instruction {
         // Split on both "," and " "
         let contents_list: Vec<&str> = line_contents.split(' ').collect();
 
-        let mut instruction_type: Option<InstructionType> = None;
-
-        for instruction in INSTRUCTION_LOOKUP.clone() {
-            if instruction.names.contains(&contents_list[0]) {
-                instruction_type = Some(instruction);
-                break;
-            }
-        }
+        let instruction_type = InstructionType::from_name(contents_list[0]);
 
         // First, check to make sure the instruction type exists in the lookup table
         if instruction_type.is_none() {
@@ -361,66 +604,21 @@ impl Instruction {
         // Third, check if instructions match the source instruction types
         let mut arg_to_get = 0;
         for current_instruction_type in instruction_chars {
-            let arg = instruction_args[arg_to_get];
-            let slot_to_fill = filled_slots.iter().position(|a| !(*a)).unwrap();
-            let mut binary_string = String::from("");
-            filled_slots[slot_to_fill] = true;
-
-            if current_instruction_type == 'r' {
-                if arg.to_lowercase().starts_with('r') {
-                    let register_number = arg[1..].parse::<u8>();
-
-                    if register_number.is_err() {
-                        return Err(CompileErr::InvalidRegister);
-                    }
-
-                    binary_string = format!("{:04b}", register_number.unwrap());
-                } else {
-                    return Err(CompileErr::InvalidArgumentType);
-                }
-            } else if current_instruction_type == 's' {
-                let number = arg.parse::<i8>();
-
-                if number.is_err() {
-                    return Err(CompileErr::InvalidSignedNumber);
-                }
-
-                binary_string = format!("{:08b}", number.unwrap());
-            } else if current_instruction_type == 'u' {
-                let number = arg.parse::<u8>();
-                if number.is_err() {
-                    return Err(CompileErr::InvalidUnsignedNumber);
-                }
-
-                binary_string = format!("{:08b}", number.unwrap());
-            } else if current_instruction_type == 'n' {
-                let number_dec = arg.parse::<i32>();
-                let number_hex = i32::from_str_radix(arg, 16);
-
-                if number_hex.is_ok() {
-                    binary_string = format!("{:016b}", number_hex.unwrap());
-                } else if number_dec.is_ok() {
-                    binary_string = format!("{:016b}", number_dec.unwrap());
-                } else {
-                    return Err(CompileErr::InvalidNumber);
-                }
-            } else if current_instruction_type == 'z' {
-                binary_string = "0000".to_string();
-                arg_to_get -= 1;
-                filled_slots[slot_to_fill] = false;
+            // 'z' is just padding that's already 0 in the match string
+            if current_instruction_type == 'z' {
+                continue;
             }
 
+            let slot_to_fill = filled_slots.iter().position(|a| !(*a)).unwrap();
+            let binary_string =
+                Instruction::encode_argument(current_instruction_type, instruction_args[arg_to_get])?;
+
             arg_to_get += 1;
 
-            if binary_string.len() == 4 {
-                binary_contents[slot_to_fill] = binary_string;
-            } else if binary_string.len() == 8 {
-                binary_contents[slot_to_fill] = String::from(binary_string.get(0..4).unwrap());
-                binary_contents[slot_to_fill + 1] = String::from(binary_string.get(4..8).unwrap());
-            } else {
-                binary_contents[slot_to_fill] = String::from(binary_string.get(0..4).unwrap());
-                binary_contents[slot_to_fill + 1] = String::from(binary_string.get(4..8).unwrap());
-                binary_contents[slot_to_fill + 2] = String::from(binary_string.get(8..12).unwrap());
+            // Each 4 bits of the argument fills the next slot
+            for (i, chunk) in binary_string.as_bytes().chunks(4).enumerate() {
+                filled_slots[slot_to_fill + i] = true;
+                binary_contents[slot_to_fill + i] = String::from_utf8_lossy(chunk).to_string();
             }
         }
 
@@ -431,51 +629,65 @@ impl Instruction {
         })
     }
 
-    pub fn new_from_binary(line_contents: &str) -> Result<Instruction, CompileErr> {
-        let binary_contents: Vec<String> = line_contents
-            .clone()
-            .split(' ')
-            .map(String::from)
-            .collect();
-
-        let mut instruction_type: Option<InstructionType> = None;
-
-        let line_split: Vec<String> = line_contents.split(' ').map(String::from).collect();
-
-        for instruction in INSTRUCTION_LOOKUP.clone().into_iter() {
-            let mut matches_instruction: bool = true;
-
-            let matcher: Vec<String> = instruction
-                .match_string
-                .split(' ')
-                .map(String::from)
-                .collect();
-
-            let mask: Vec<bool> = instruction
-                .mask_string
-                .split(' ')
-                .map(|a| {
-                    a != "0000"
-                })
-                .collect();
+    /// Converts a single argument into its binary representation,
+    /// based on its type from InstructionType::arguments
+    pub fn encode_argument(argument_type: char, arg: &str) -> Result<String, CompileErr> {
+        match argument_type {
+            'r' => {
+                if !arg.to_lowercase().starts_with('r') {
+                    return Err(CompileErr::InvalidArgumentType);
+                }
 
-            for i in 0..4 {
-                if mask[i] && matcher[i] != line_split[i] {
-                    matches_instruction = false;
+                match arg[1..].parse::<u8>() {
+                    Ok(register_number) if register_number < 16 => {
+                        Ok(format!("{:04b}", register_number))
+                    }
+                    _ => Err(CompileErr::InvalidRegister),
                 }
             }
-
-            if matches_instruction {
-                instruction_type = Some(instruction);
-                break;
+            's' | 'u' | 'n' => {
+                let (error, width) = match argument_type {
+                    's' => (CompileErr::InvalidSignedNumber, 8),
+                    'u' => (CompileErr::InvalidUnsignedNumber, 8),
+                    _ => (CompileErr::InvalidNumber, 16),
+                };
+                let range = InstructionType::argument_range(argument_type).unwrap();
+
+                match parse_literal(arg) {
+                    Some(number) if range.contains(&number) => {
+                        // Cutting the two's complement form down to size
+                        // works for both signed and unsigned numbers
+                        let bits = format!("{:032b}", number);
+                        Ok(bits[32 - width..].to_string())
+                    }
+                    _ => Err(error),
+                }
             }
+            _ => Err(CompileErr::InvalidArgumentType),
         }
+    }
 
-        if instruction_type.is_none() {
-            return Err(CompileErr::InstructionDoesNotExist);
+    /// Decodes a line of 16 binary digits, either written as
+    /// four groups of four (eg: "0001 0010 0000 0101") or all together
+    pub fn new_from_binary(line_contents: &str) -> Result<Instruction, CompileErr> {
+        let digits: String = line_contents.split_whitespace().collect();
+
+        if digits.len() != 16 || !digits.chars().all(|c| c == '0' || c == '1') {
+            return Err(CompileErr::CorruptedBinary);
         }
 
-        let instruction_type = instruction_type.unwrap();
+        let word = u16::from_str_radix(&digits, 2).unwrap();
+
+        let instruction_type = match InstructionType::decode(word) {
+            Some(instruction_type) => instruction_type,
+            None => return Ok(Instruction::new_data(&digits)),
+        };
+
+        let binary_contents: Vec<String> = split_binary_to_chunks(digits)
+            .split(' ')
+            .map(String::from)
+            .collect();
+
         let mut text_contents = String::from("");
 
         let mut instruction_args: Vec<String> = Vec::new();
@@ -497,7 +709,7 @@ impl Instruction {
                 );
                 instruction_args.push(format!(
                     "{}",
-                    i8::from_str_radix(combined_binary.as_str(), 2).unwrap()
+                    u8::from_str_radix(combined_binary.as_str(), 2).unwrap() as i8
                 ));
                 slots_filled += 2;
             } else if arg_type == 'u' {
@@ -511,23 +723,12 @@ impl Instruction {
                     u8::from_str_radix(combined_binary.as_str(), 2).unwrap()
                 ));
                 slots_filled += 2;
-            } else if arg_type == 'n' {
-                let combined_binary = binary_contents.join("");
-
-                instruction_args.push(format!(
-                    "{}",
-                    i32::from_str_radix(combined_binary.as_str(), 2).unwrap()
-                ));
-                slots_filled += 4;
+            } else if arg_type == 'z' {
+                slots_filled += 1;
             }
         }
         if !instruction_args.is_empty() {
-            text_contents = instruction_args[0].clone();
-        }
-        if instruction_args.len() > 1 {
-            for i in 1..(instruction_args.len()) {
-                text_contents = format!("{}, {}", text_contents, instruction_args[i]);
-            }
+            text_contents = instruction_args.join(", ");
         }
 
         Ok(Instruction {
@@ -552,7 +753,9 @@ impl Instruction {
                 data[8..12].to_string(),
                 data[12..16].to_string(),
             ],
-            text_contents: "".to_string(),
+            text_contents: u16::from_str_radix(data, 2)
+                .map(|value| (value as i16).to_string())
+                .unwrap_or_default(),
         }
     }
 
@@ -565,12 +768,9 @@ impl Instruction {
 
         for i in 0..4 {
             hex_string = format!(
-                "{}{}",
+                "{}{:X}",
                 hex_string,
-                format!(
-                    "{:X}",
-                    u8::from_str_radix(self.binary_contents[i].as_str(), 2).unwrap()
-                )
+                u8::from_str_radix(self.binary_contents[i].as_str(), 2).unwrap()
             );
         }
 
@@ -594,6 +794,8 @@ pub enum RuntimeErr {
     RegisterOutOfBounds,
     MaximumIterationsReached,
     TooManyInputs,
+    InvalidInput,
+    OutputFailed,
 }
 
 impl RuntimeErr {
@@ -613,129 +815,266 @@ impl RuntimeErr {
             RuntimeErr::RegisterOutOfBounds => 110,
             RuntimeErr::MaximumIterationsReached => 111,
             RuntimeErr::TooManyInputs => 112,
+            RuntimeErr::InvalidInput => 113,
+            RuntimeErr::OutputFailed => 114,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            RuntimeErr::InvalidRegisterLocation => "register does not exist",
+            RuntimeErr::MemoryLocationNotData => "memory location does not contain data",
+            RuntimeErr::InvalidMemoryData => "memory contains invalid data",
+            RuntimeErr::InvalidMemoryLocation => "memory address out of range",
+            RuntimeErr::InvalidData => "invalid data",
+            RuntimeErr::InvalidSignedNumber => "invalid signed number",
+            RuntimeErr::Halt => "program halted",
+            RuntimeErr::InvalidProgramCounter => "program counter out of range",
+            RuntimeErr::InstructionIsData => "tried to execute data",
+            RuntimeErr::InvalidInstructionType => "invalid instruction type",
+            RuntimeErr::DivideByZero => "divide by zero",
+            RuntimeErr::RegisterOutOfBounds => "result does not fit in a register",
+            RuntimeErr::MaximumIterationsReached => "maximum number of iterations reached",
+            RuntimeErr::TooManyInputs => "program asked for more inputs than were given",
+            RuntimeErr::InvalidInput => "input is not a number that fits in a register",
+            RuntimeErr::OutputFailed => "output could not be written",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for RuntimeErr {}
+
+/// A RuntimeErr along with where in the program it happened
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErr,
+    /// Program counter when the error happened
+    pub address: usize,
+    /// Instruction at that address
+    pub instruction: Box<Instruction>,
+    /// Source line the instruction was compiled from, if the
+    /// simulator has a source map
+    pub location: Option<Box<SourceLocation>>,
+}
+
+impl RuntimeError {
+    pub fn as_code(&self) -> i32 {
+        self.kind.as_code()
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at address {} ({} {})",
+            self.kind,
+            self.address,
+            self.instruction.instruction_type.names[0],
+            self.instruction.text_contents
+        )?;
+
+        if let Some(location) = &self.location {
+            write!(f, " on {}: {}", location, location.text.trim())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// What happens when a result doesn't fit in a register, whether it's from
+/// add, sub, mul, div, mod, addn or neg, or a number given to read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Stop with RegisterOutOfBounds, or InvalidInput for a read
+    #[default]
+    Trap,
+    /// Keep the low 16 bits, as two's complement hardware would
+    Wrap,
+    /// Clamp to -32768 or 32767
+    Saturate,
+}
+
+impl OverflowPolicy {
+    /// Fits a result into a register, or gives None if it traps
+    pub fn apply(&self, result: i32) -> Option<i16> {
+        match self {
+            OverflowPolicy::Trap => i16::try_from(result).ok(),
+            OverflowPolicy::Wrap => Some(result as i16),
+            OverflowPolicy::Saturate => Some(result.clamp(i16::MIN as i32, i16::MAX as i32) as i16),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OverflowPolicy::Trap => "trap",
+            OverflowPolicy::Wrap => "wrap",
+            OverflowPolicy::Saturate => "saturate",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_lowercase().as_str() {
+            "trap" => Ok(OverflowPolicy::Trap),
+            "wrap" => Ok(OverflowPolicy::Wrap),
+            "saturate" => Ok(OverflowPolicy::Saturate),
+            _ => Err(()),
         }
     }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Simulator {
-    pub memory: Vec<Instruction>,
+    /// Every word of memory, as the number it holds. Use write_mem to
+    /// change a word, so the decoded copy of it is kept up to date
+    pub(crate) memory: [u16; 256],
+    /// Each word decoded ahead of time, so it's only done when it changes
+    opcodes: [Opcode; 256],
+    /// Words that were written as data, rather than as instructions
+    pub(crate) data_words: [bool; 256],
+    /// Words the program has written to since it was loaded
+    pub(crate) written: [bool; 256],
+    /// The program as it was loaded, used to show words that haven't
+    /// been written to in the same way the assembler wrote them
+    pub(crate) program: Vec<Instruction>,
     pub registers: Vec<i16>,
     pub program_counter: usize,
     pub counter_log: Vec<usize>,
     pub just_updated_pc: bool,
     pub debug: bool,
-    pub current_regs: Vec<u8>,
-    pub headless: bool,
-    pub inputs: Vec<i16>,
+    pub current_regs: [u8; 3],
+    /// Traps on running a word written as data, or reading an
+    /// instruction as data, instead of decoding it as it's used
+    pub strict: bool,
+    /// What happens when arithmetic, or a number given to read, doesn't fit in a register
+    pub overflow: OverflowPolicy,
+    /// Where read instructions get numbers from
+    input: Device<dyn InputSource>,
+    /// Where write instructions send numbers to
+    output: Device<dyn OutputSink>,
+    /// Every number written so far, wherever it was sent
     pub outputs: Vec<i16>,
+    /// Where each address came from in the source, if known
+    pub source_map: SourceMap,
+    /// Number of steps run so far
+    pub(crate) steps: u64,
+    /// Steps that can be undone with step_back
+    history: History,
+    /// What the step being run has changed so far, when history is kept
+    recording: Option<Step>,
+    /// Inputs given back by step_back, read again before the input source.
+    /// The next one to read is last
+    replay: Vec<i16>,
+    /// Breakpoints and watchpoints that run_until and reverse_continue stop at
+    stops: StopPoints,
+    /// Registers and memory the step being run has used so far, when a
+    /// watchpoint needs to know
+    accesses: Option<Vec<(Location, Access)>>,
 }
 
 impl Simulator {
+    /// Creates a simulator with the program loaded at address 0.
+    /// The rest of memory is filled with blank data, and anything
+    /// past the 256th word is ignored
     pub fn new(compiled_text: Vec<Instruction>) -> Self {
-        let data_left = 256 - compiled_text.len();
-        let mut memory: Vec<Instruction> = compiled_text;
-        let data = Instruction::new_blank_data();
+        let mut program: Vec<Instruction> = compiled_text;
+        program.truncate(256);
 
-        for _ in 0..data_left {
-            memory.push(data.clone());
-        }
+        let mut memory = [0; 256];
+        let mut data_words = [true; 256];
 
-        let mut registers: Vec<i16> = Vec::new();
-        for _ in 0..16 {
-            registers.push(0_i16);
+        for (address, instruction) in program.iter().enumerate() {
+            memory[address] = u16::from_str_radix(&instruction.binary_contents.join(""), 2).unwrap_or(0);
+            data_words[address] = instruction.instruction_type.names[0] == "data";
         }
-        Simulator {
+
+        let registers: Vec<i16> = vec![0; 16];
+        let mut sim = Simulator {
             memory,
+            opcodes: [Opcode::Halt; 256],
+            data_words,
+            written: [false; 256],
+            program,
             registers,
             program_counter: 0,
             counter_log: Vec::new(),
             just_updated_pc: false,
             debug: false,
-            current_regs: vec![0, 0, 0],
-            headless: false,
-            inputs: Vec::new(),
+            current_regs: [0, 0, 0],
+            strict: false,
+            overflow: OverflowPolicy::default(),
+            input: Device(Rc::new(RefCell::new(Console::new()))),
+            output: Device(Rc::new(RefCell::new(Console::new()))),
             outputs: Vec::new(),
+            source_map: SourceMap::new(),
+            steps: 0,
+            history: History::default(),
+            recording: None,
+            replay: Vec::new(),
+            stops: StopPoints::default(),
+            accesses: None,
+        };
+
+        for address in 0..256 {
+            sim.predecode(address);
         }
+
+        sim
     }
 
+    /// Decodes a word of memory ahead of time, from its bits, so a
+    /// loaded instruction runs the same as one written by the program
+    pub(crate) fn predecode(&mut self, address: usize) {
+        self.opcodes[address] = Opcode::decode(self.memory[address]);
+    }
+
+    /// Creates a simulator that doesn't use the terminal. It has no
+    /// inputs until set_inputs is called, and outputs are only kept
+    /// for get_outputs
     pub fn new_headless(compiled_text: Vec<Instruction>) -> Self {
         let mut sim = Simulator::new(compiled_text);
-        sim.headless = true;
+        sim.set_headless();
         sim
     }
 
     /// Function to compile a vec of HMMM instructions into
     /// a Vec of Instruction structs
-    pub fn compile_hmmm(
-        uncompiled_text: Vec<String>,
-        is_headless: bool,
-    ) -> Result<Vec<Instruction>, CompileErr> {
-        let mut line_counter = 0;
-        let mut compiled_text: Vec<Instruction> = Vec::new();
-
-        for (index, line) in uncompiled_text.iter().enumerate() {
-            if !(line.trim().starts_with('#')) && line.len() > 2 {
-                let mut line_parts: Vec<String> = line
-                    .split(&[',', ' ', '\t'][..])
-                    .map(String::from)
-                    .collect();
-                let line_number = line_parts.get(0).unwrap().trim().parse::<i128>();
-                let comment_part = line_parts.iter().position(|a| a.starts_with('#'));
-
-                if comment_part.is_some() {
-                    line_parts.drain(comment_part.unwrap()..);
-                }
+    pub fn compile_hmmm(uncompiled_text: Vec<String>) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+        let assembly = assembler::assemble(&uncompiled_text)?;
 
-                let line_parts: Vec<String> = String::from(line_parts.join(" ").trim())
-                    .split_whitespace()
-                    .map(String::from)
-                    .collect();
-
-                let cleaned_line = line_parts[1..].join(" ").to_lowercase();
-                if line_number.is_err() {
-                    if !is_headless {
-                        raise_compile_error(
-                            index,
-                            CompileErr::LineNumberNotPresent,
-                            line,
-                            line_parts,
-                        );
-                    }
-                    return Err(CompileErr::LineNumberNotPresent);
-                } else if line_number.unwrap() != line_counter {
-                    if !is_headless {
-                        raise_compile_error(
-                            index,
-                            CompileErr::InvalidLineNumber,
-                            line,
-                            line_parts,
-                        );
-                    }
-                    return Err(CompileErr::InvalidLineNumber);
-                } else {
-                    let next_instruction = Instruction::new_from_text(cleaned_line.as_str());
-                    if next_instruction.is_err() {
-                        let err = next_instruction.unwrap_err();
-                        if !is_headless {
-                            raise_compile_error(index, err.clone(), line, line_parts);
-                        }
-                        return Err(err);
-                    } else {
-                        compiled_text.push(next_instruction.unwrap());
-                        line_counter += 1;
-                    }
-                }
-            }
-        }
-
-        Ok(compiled_text)
+        Ok(assembly.instructions)
     }
 
     pub fn write_reg(&mut self, register: u8, data: i16) -> Result<(), RuntimeErr> {
         if register > 15 {
             return Err(RuntimeErr::InvalidRegisterLocation);
         } else if register > 0 {
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push((Location::Register(register), Access::Write));
+            }
+
+            if let Some(step) = &mut self.recording {
+                step.registers.push(RegisterWrite {
+                    register,
+                    before: self.registers[register as usize],
+                    after: data,
+                });
+            }
+
             self.registers[register as usize] = data;
         }
 
@@ -748,32 +1087,51 @@ impl Simulator {
         } else if register > 15 {
             Err(RuntimeErr::InvalidRegisterLocation)
         } else {
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push((Location::Register(register), Access::Read));
+            }
+
             Ok(self.registers[register as usize])
         }
     }
 
     pub fn write_mem(&mut self, memory: u8, data: i16) -> Result<(), RuntimeErr> {
-        let data_binary = format!("{:016b}", data);
+        let address = memory as usize;
+
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push((Location::Memory(memory), Access::Write));
+        }
 
-        let data = Instruction::new_data(data_binary.as_str());
+        if let Some(step) = &mut self.recording {
+            step.memory.push(MemoryWrite {
+                address: memory,
+                before: self.memory[address],
+                after: data as u16,
+                was_data: self.data_words[address],
+                was_written: self.written[address],
+            });
+        }
 
-        self.memory[memory as usize] = data;
+        self.memory[address] = data as u16;
+        self.data_words[address] = true;
+        self.written[address] = true;
+        self.predecode(address);
         Ok(())
     }
 
+    /// Reads a word of memory as a number. Instructions are read as the
+    /// number they're encoded as, unless in strict mode
     pub fn read_mem(&mut self, memory: u8) -> Result<i16, RuntimeErr> {
-        let data = self.memory[memory as usize].clone();
-        if data.instruction_type.names[0] != "data" {
+        let address = memory as usize;
+
+        if self.strict && !self.data_words[address] {
             Err(RuntimeErr::MemoryLocationNotData)
         } else {
-            let binary = data.binary_contents.join("");
-            let num = i16::from_str_radix(binary.as_str(), 2);
-
-            if num.is_err() {
-                Err(RuntimeErr::InvalidMemoryData)
-            } else {
-                Ok(num.unwrap())
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push((Location::Memory(memory), Access::Read));
             }
+
+            Ok(self.memory[address] as i16)
         }
     }
 
@@ -800,27 +1158,92 @@ impl Simulator {
         self.debug
     }
 
-    // Get headless state
-    pub fn is_headless(&self) -> bool {
-        self.headless
+    /// Sets the state of strict mode
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Get strict state
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Sets what happens when a result doesn't fit in a register
+    pub fn set_overflow_policy(&mut self, overflow: OverflowPolicy) {
+        self.overflow = overflow;
+    }
+
+    // Get overflow policy
+    pub fn get_overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// Stops using the terminal, in the same way as new_headless
+    pub fn set_headless(&mut self) {
+        self.set_inputs(Vec::new());
+        self.set_output_sink(Writer::new(std::io::sink()));
+    }
+
+    /// Sets where read instructions get numbers from
+    pub fn set_input_source(&mut self, input: impl InputSource + 'static) {
+        self.replay.clear();
+        self.input = Device(Rc::new(RefCell::new(input)));
+    }
+
+    /// Sets where write instructions send numbers to. Numbers written are
+    /// also kept for get_outputs, wherever they're sent
+    pub fn set_output_sink(&mut self, output: impl OutputSink + 'static) {
+        self.output = Device(Rc::new(RefCell::new(output)));
     }
 
     // Add to output
-    pub fn add_output(&mut self, output: i16) {
+    pub fn add_output(&mut self, output: i16) -> Result<(), RuntimeErr> {
+        if let Some(step) = &mut self.recording {
+            step.output = Some(output);
+        }
+
         self.outputs.push(output);
+        self.output.0.borrow_mut().write_number(output)
     }
 
-    // Set inputs
+    /// Gives the program these numbers as input, in order
     pub fn set_inputs(&mut self, inputs: Vec<i16>) {
-        self.inputs = inputs;
+        self.set_input_source(VecDeque::from(inputs));
+    }
+
+    /// Sets the source map used to show where runtime errors happened
+    pub fn set_source_map(&mut self, source_map: SourceMap) {
+        self.source_map = source_map;
+    }
+
+    /// Source line the word at an address was compiled from, if known
+    pub fn get_source_location(&self, address: usize) -> Option<&SourceLocation> {
+        self.source_map.get(address)
+    }
+
+    /// Inputs the program hasn't read yet, if the input source knows them ahead of time
+    pub fn get_remaining_inputs(&self) -> Option<Vec<i16>> {
+        let remaining = self.input.0.borrow().remaining()?;
+
+        Some(self.replay.iter().rev().copied().chain(remaining).collect())
     }
 
-    // Get the next input, and pop it
-    pub fn get_next_input(&mut self) -> Option<i16> {
-        if self.inputs.is_empty() {
-            return None;
+    // Get the next input from the input source, fitted into a
+    // register by the overflow policy
+    pub fn get_next_input(&mut self) -> Result<i16, RuntimeErr> {
+        let number = match self.replay.pop() {
+            Some(number) => number,
+            None => {
+                let number = self.input.0.borrow_mut().read_number()?;
+                self.overflow.apply(number).ok_or(RuntimeErr::InvalidInput)?
+            }
+        };
+
+        if let Some(step) = &mut self.recording {
+            step.input = Some(number);
         }
-        Some(self.inputs.remove(0))
+
+        Ok(number)
     }
 
     // Return output vec
@@ -830,24 +1253,240 @@ impl Simulator {
 
     /// Function to both execute instruction on program counter
     /// and increment program counter
-    pub fn step(&mut self) -> Result<(), RuntimeErr> {
-        // Run memory at program counter
-        let execution_result = self.execute_next();
+    ///
+    /// Errors are returned along with the address and instruction
+    /// that caused them
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        let address = self.get_program_counter();
+
+        if self.history.get_limit() > 0 {
+            self.recording = Some(Step::new(
+                self.steps,
+                address,
+                self.just_updated_pc,
+                self.current_regs,
+                self.counter_log.len(),
+            ));
+        }
+
+        let result = self.step_inner();
+
+        // Steps that failed part way through are kept if they changed
+        // anything, so they can still be undone
+        if let Some(step) = self.recording.take() {
+            if result.is_ok() || step.changed_anything() {
+                self.history.push(step);
+            }
+        }
+
+        if result.is_ok() {
+            self.steps += 1;
+        }
+
+        result.map_err(|kind| RuntimeError {
+            kind,
+            address,
+            instruction: Box::new(
+                self.fetch(address)
+                    .unwrap_or_else(Instruction::new_blank_data),
+            ),
+            location: self.get_source_location(address).cloned().map(Box::new),
+        })
+    }
+
+    /// Undoes the last step kept in the history, returning what it changed.
+    /// Numbers it read are given back, to be read again by the next read,
+    /// and numbers it wrote are taken off get_outputs, though they can't
+    /// be taken back from the output sink
+    pub fn step_back(&mut self) -> Option<Step> {
+        let step = self.history.pop()?;
+
+        for write in step.memory.iter().rev() {
+            let address = write.address as usize;
+
+            self.memory[address] = write.before;
+            self.data_words[address] = write.was_data;
+            self.written[address] = write.was_written;
+            self.predecode(address);
+        }
+
+        for write in step.registers.iter().rev() {
+            self.registers[write.register as usize] = write.before;
+        }
+
+        if let Some(input) = step.input {
+            self.replay.push(input);
+        }
+
+        if step.output.is_some() {
+            self.outputs.pop();
+        }
+
+        self.counter_log.truncate(step.counter_log);
+        self.program_counter = step.address;
+        self.just_updated_pc = step.just_updated_pc;
+        self.current_regs = step.current_regs;
+        self.steps = step.number;
+
+        Some(step)
+    }
+
+    /// Steps back until the program counter is at a breakpoint, or there's
+    /// no more history. Always steps back at least once, and returns how
+    /// many steps were undone
+    pub fn reverse_continue(&mut self) -> usize {
+        let mut undone = 0;
+
+        while self.step_back().is_some() {
+            undone += 1;
+
+            if self.stops.breakpoints.values().any(|breakpoint| breakpoint.applies(self)) {
+                break;
+            }
+        }
+
+        undone
+    }
+
+    /// Runs until a breakpoint or watchpoint stops it, the program halts
+    /// or fails, or `max_steps` steps have been run, and says which it was.
+    /// Breakpoints are checked after every step, including the last one,
+    /// but not before the first, so a run can carry on from the breakpoint
+    /// it last stopped at
+    pub fn run_until(&mut self, max_steps: u64) -> Stop {
+        for _ in 0..max_steps {
+            // Values of everything watched for a change, before the step
+            let before: Vec<i16> = self
+                .stops
+                .watchpoints
+                .values()
+                .map(|watchpoint| watchpoint.location.value(self))
+                .collect();
+
+            if self.stops.watches_accesses() {
+                self.accesses = Some(Vec::new());
+            }
+
+            let result = self.step();
+            let accesses = self.accesses.take().unwrap_or_default();
+
+            match result {
+                Err(err) if err.kind == RuntimeErr::Halt => return Stop::Halted,
+                Err(err) => return Stop::Error(err),
+                Ok(()) => {}
+            }
+
+            if let Some(stop) = self.hit_watchpoint(&before, &accesses) {
+                return stop;
+            }
+
+            if let Some(id) = self.hit_breakpoint() {
+                return Stop::Breakpoint(id);
+            }
+        }
+
+        Stop::StepLimit
+    }
+
+    /// Counts a hit on every breakpoint that applies, returning the id
+    /// of the first one that's passed its ignore count
+    fn hit_breakpoint(&mut self) -> Option<usize> {
+        let applying: Vec<usize> = self
+            .stops
+            .breakpoints
+            .iter()
+            .filter(|(_, breakpoint)| breakpoint.applies(self))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut stopped = None;
+
+        for id in applying {
+            let breakpoint = self.stops.breakpoints.get_mut(&id).unwrap();
+            breakpoint.hits += 1;
+
+            if breakpoint.hits > breakpoint.ignore_count && stopped.is_none() {
+                stopped = Some(id);
+            }
+        }
+
+        stopped
+    }
 
-        // If the execution resulted in an error, return it
-        if execution_result.is_err() {
-            return Err(execution_result.unwrap_err());
+    /// Counts a hit on every watchpoint the last step set off, given the
+    /// value of each watched location before the step and everything the
+    /// step used, and stops at the first one that's passed its ignore count
+    fn hit_watchpoint(&mut self, before: &[i16], accesses: &[(Location, Access)]) -> Option<Stop> {
+        let triggered: Vec<usize> = self
+            .stops
+            .watchpoints
+            .iter()
+            .zip(before)
+            .filter(|((_, watchpoint), before)| match watchpoint.access {
+                Access::Change => watchpoint.location.value(self) != **before,
+                access => accesses.contains(&(watchpoint.location, access)),
+            })
+            .map(|((id, _), _)| *id)
+            .collect();
+        let mut stopped = None;
+
+        for id in triggered {
+            let watchpoint = self.stops.watchpoints.get_mut(&id).unwrap();
+            watchpoint.hits += 1;
+
+            if watchpoint.hits > watchpoint.ignore_count && stopped.is_none() {
+                let location = watchpoint.location;
+                stopped = Some(Stop::Watchpoint { id, value: location.value(self) });
+            }
         }
 
+        stopped
+    }
+
+    /// Sets how many steps are kept for step_back. None are kept by
+    /// default, so running isn't slowed down unless it's asked for
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+
+    /// Steps that can be undone, such as to find when a register was last written
+    pub fn get_history(&self) -> &History {
+        &self.history
+    }
+
+    /// Number of steps run, less any undone with step_back
+    pub fn get_step_count(&self) -> u64 {
+        self.steps
+    }
+
+    /// Adds a breakpoint, returning its id
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.stops.add_breakpoint(breakpoint)
+    }
+
+    /// Adds a watchpoint, returning its id
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.stops.add_watchpoint(watchpoint)
+    }
+
+    /// Removes the breakpoint or watchpoint with an id, returning false if there isn't one
+    pub fn remove_stop(&mut self, id: usize) -> bool {
+        self.stops.remove(id)
+    }
+
+    /// Every breakpoint and watchpoint, along with how many times each has been hit
+    pub fn get_stops(&self) -> &StopPoints {
+        &self.stops
+    }
+
+    fn step_inner(&mut self) -> Result<(), RuntimeErr> {
+        // Run memory at program counter
+        self.execute_next()?;
+
         // Otherwise, increase the program counter by one if instruction
         // didn't already do thats
         if !self.just_updated_pc {
-            let update_program_counter = self.update_pc(self.program_counter + 1);
-
             // If there's an error (went past the final memory address), return it
-            if update_program_counter.is_err() {
-                return Err(update_program_counter.unwrap_err());
-            }
+            self.update_pc(self.program_counter + 1)?;
         }
 
         Ok(())
@@ -868,41 +1507,44 @@ impl Simulator {
 
         // Make sure to rest just_updated_pc to false
         self.just_updated_pc = false;
-        // Clone the current instruction from memory
-        let instruction_to_run = self.get_memory(self.get_program_counter()).unwrap();
-        // Get the name of the instruction for quick reference
-        let instruction_name = instruction_to_run.instruction_type.names[0];
-
-        self.current_regs = self.quick_access_regs(instruction_to_run);
-
-        let result: Result<(), RuntimeErr> = match instruction_name {
-            "data" => self.perform_data(),
-            "halt" => self.perform_halt(),
-            "nop" => self.perform_nop(),
-            "read" => self.perform_read(),
-            "write" => self.perform_write(),
-            "setn" => self.perform_setn(),
-            "loadr" => self.perform_loadr(),
-            "storer" => self.perform_storer(),
-            "popr" => self.perform_popr(),
-            "pushr" => self.perform_pushr(),
-            "loadn" => self.perform_loadn(),
-            "storen" => self.perform_storen(),
-            "addn" => self.perform_addn(),
-            "copy" => self.perform_copy(),
-            "neg" => self.perform_neg(),
-            "add" | "sub" | "mul" | "div" | "mod" => self.perform_arithmetic(instruction_name),
-            "jumpr" => self.perform_jumpr(),
-            "jumpn" => self.perform_jumpn(),
-            "jeqzn" => self.perform_jeqzn(),
-            "jnezn" => self.perform_jnezn(),
-            "jgtzn" => self.perform_jgtzn(),
-            "jltzn" => self.perform_jltzn(),
-            "calln" => self.perform_calln(),
-            _ => Err(RuntimeErr::InvalidInstructionType),
+
+        // Words written as data can't be run in strict mode. Otherwise,
+        // the word was decoded when it was loaded or last written
+        let opcode = if self.strict && self.data_words[pc] {
+            Opcode::Data
+        } else {
+            self.opcodes[pc]
         };
 
-        result
+        self.current_regs = Simulator::word_regs(self.memory[pc]);
+
+        match opcode {
+            Opcode::Data => self.perform_data(),
+            Opcode::Halt => self.perform_halt(),
+            Opcode::Nop => self.perform_nop(),
+            Opcode::Read => self.perform_read(),
+            Opcode::Write => self.perform_write(),
+            Opcode::Setn => self.perform_setn(),
+            Opcode::Loadr => self.perform_loadr(),
+            Opcode::Storer => self.perform_storer(),
+            Opcode::Popr => self.perform_popr(),
+            Opcode::Pushr => self.perform_pushr(),
+            Opcode::Loadn => self.perform_loadn(),
+            Opcode::Storen => self.perform_storen(),
+            Opcode::Addn => self.perform_addn(),
+            Opcode::Copy => self.perform_copy(),
+            Opcode::Neg => self.perform_neg(),
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => {
+                self.perform_arithmetic(opcode)
+            }
+            Opcode::Jumpr => self.perform_jumpr(),
+            Opcode::Jumpn => self.perform_jumpn(),
+            Opcode::Jeqzn => self.perform_jeqzn(),
+            Opcode::Jnezn => self.perform_jnezn(),
+            Opcode::Jgtzn => self.perform_jgtzn(),
+            Opcode::Jltzn => self.perform_jltzn(),
+            Opcode::Calln => self.perform_calln(),
+        }
     }
 
     /// Returns the current program counter as usize
@@ -912,50 +1554,72 @@ impl Simulator {
 
     /// Returns the register value at the given register index
     pub fn get_register(&self, address: usize) -> Option<i16> {
-        let option = self.registers.get(address);
+        self.registers.get(address).copied()
+    }
+    /// Returns the Instruction struct at memory[address] as Option.
+    /// Words the program hasn't written to are given as they were loaded
+    pub fn get_memory(&self, address: usize) -> Option<Instruction> {
+        let word = *self.memory.get(address)?;
 
-        if option.is_none() {
-            None
-        } else {
-            Some(*option.unwrap())
+        match self.program.get(address) {
+            Some(instruction) if !self.written[address] => Some(instruction.clone()),
+            _ => Some(Instruction::new_data(&format!("{:016b}", word))),
         }
     }
-    /// Returns the Instruction struct at memory[address] as Option
-    pub fn get_memory(&self, address: usize) -> Option<Instruction> {
-        let option = self.memory.get(address);
-        if option.is_none() {
-            None
+
+    /// Returns the number held in memory[address] as Option
+    pub fn get_word(&self, address: usize) -> Option<u16> {
+        self.memory.get(address).copied()
+    }
+    /// Returns the instruction the word at memory[address] holds right now.
+    /// Memory is only numbers, so a word written as data (eg: by storen)
+    /// is decoded in the same way as one written by the assembler, and
+    /// programs can change their own code. In strict mode, words written
+    /// as data stay data, and can't be run
+    pub fn fetch(&self, address: usize) -> Option<Instruction> {
+        let word = *self.memory.get(address)?;
+
+        if self.strict || !self.data_words[address] {
+            self.get_memory(address)
         } else {
-            Some(option.unwrap().clone())
+            Instruction::new_from_binary(&format!("{:016b}", word)).ok()
         }
     }
+
     /// Returns current counter log of program counter
     pub fn get_counter_log(&self) -> Vec<usize> {
         self.counter_log.clone()
     }
 
     /// Returns the current instruction register values    
-    pub fn quick_access_regs(&self, instruction_to_run: Instruction) -> Vec<u8> {
-        // All binary of length 4 can be coerced into u8, and having all three
-        // arguments available as numbers can be useful for instructions
-        let reg_x = u8::from_str_radix(instruction_to_run.binary_contents[1].as_str(), 2).unwrap();
-        let reg_y = u8::from_str_radix(instruction_to_run.binary_contents[2].as_str(), 2).unwrap();
-        let reg_z = u8::from_str_radix(instruction_to_run.binary_contents[3].as_str(), 2).unwrap();
+    pub fn quick_access_regs(&self, instruction_to_run: Instruction) -> [u8; 3] {
+        let word = u16::from_str_radix(&instruction_to_run.binary_contents.join(""), 2).unwrap_or(0);
+
+        Simulator::word_regs(word)
+    }
 
-        return vec![reg_x, reg_y, reg_z];
+    /// The last three groups of 4 bits in a word. Having all three
+    /// arguments available as numbers can be useful for instructions
+    fn word_regs(word: u16) -> [u8; 3] {
+        [
+            (word >> 8 & 0xF) as u8,
+            (word >> 4 & 0xF) as u8,
+            (word & 0xF) as u8,
+        ]
     }
 
     // Get last data as i8
     pub fn get_ending_data(&self) -> Result<i8, RuntimeErr> {
-        let _instruction_to_run = self.get_memory(self.get_program_counter()).unwrap();
+        let word = self
+            .get_word(self.get_program_counter())
+            .ok_or(RuntimeErr::InvalidProgramCounter)?;
+
+        Ok(word as u8 as i8)
+    }
 
-        return signed_binary_conversion(
-            self.get_memory(self.get_program_counter())
-                .unwrap()
-                .binary_contents[2..]
-                .join("")
-                .as_str(),
-        );
+    // Get last data as an unsigned memory address
+    pub fn get_ending_address(&self) -> Result<usize, RuntimeErr> {
+        Ok(self.get_ending_data()? as u8 as usize)
     }
 
     // Below are the functions for each instruction
@@ -973,73 +1637,13 @@ impl Simulator {
     }
 
     pub fn perform_read(&mut self) -> Result<(), RuntimeErr> {
-        if self.is_headless() {
-            let next_number = self.get_next_input();
-            if next_number.is_none() {
-                Err(RuntimeErr::TooManyInputs)
-            } else {
-                self.write_reg(self.current_regs[0], next_number.unwrap())
-            }
-        } else {
-            loop {
-                let mut line = String::new();
-                if self.is_debug() {
-                    let w = terminal::stdout();
-                    let _ = w.act(Action::ShowCursor);
-                    let _ = w.act(Action::EnableBlinking);
-                    let _ = w.act(Action::MoveCursorTo(0, 28)).unwrap();
-                    print!("{}", "Enter number:".on_yellow().black());
-                    let _ = w.act(Action::MoveCursorTo(14, 28)).unwrap();
-                    print!("                                 ");
-                    let _ = w.act(Action::MoveCursorTo(14, 28)).unwrap();
-                    stdin().lock().read_line(&mut line).unwrap();
-                    let _ = w.act(Action::DisableBlinking);
-                    let _ = w.act(Action::HideCursor);
-                } else {
-                    println!("{}", "Enter number:".on_yellow().black());
-                    io::stdin().read_line(&mut line).unwrap();
-                }
-                line = line.trim().to_string();
-                if line == "q" {
-                    return Err(RuntimeErr::Halt);
-                }
-                let number = line.parse::<i16>();
-                if number.is_ok() {
-                    if self.is_debug() {
-                        let w = terminal::stdout();
-                        w.act(Action::MoveCursorTo(16, 29)).unwrap();
-                        print!("                                        ");
-                    }
-                    return self.write_reg(self.current_regs[0], number.unwrap());
-                }
-                if self.is_debug() {
-                    let w = terminal::stdout();
-                    w.act(Action::MoveCursorTo(16, 29)).unwrap();
-                    print!("Invalid number! Please try again...");
-                } else {
-                    println!("Invalid number! Please try again...");
-                }
-            }
-        }
+        let number = self.get_next_input()?;
+        self.write_reg(self.current_regs[0], number)
     }
 
     pub fn perform_write(&mut self) -> Result<(), RuntimeErr> {
-        if self.is_headless() {
-            let read_num = self.read_reg(self.current_regs[0])?;
-            self.add_output(read_num);
-        } else if self.is_debug() {
-            let w = terminal::stdout();
-            w.act(Action::MoveCursorTo(50, 8)).unwrap();
-            let to_print = format!("{:<10}", self.read_reg(self.current_regs[0])?);
-            print!("{}", to_print);
-        } else {
-            println!(
-                "{}\n{}",
-                "HMMM OUT:".on_green().black(),
-                self.read_reg(self.current_regs[0])?
-            );
-        }
-        Ok(())
+        let number = self.read_reg(self.current_regs[0])?;
+        self.add_output(number)
     }
 
     pub fn perform_setn(&mut self) -> Result<(), RuntimeErr> {
@@ -1047,40 +1651,25 @@ impl Simulator {
     }
 
     pub fn perform_loadr(&mut self) -> Result<(), RuntimeErr> {
-        let index = self.read_reg(self.current_regs[1]);
-
-        if index.is_err() {
-            return Err(index.unwrap_err());
-        }
-
-        let index = index.unwrap();
+        let index = self.read_reg(self.current_regs[1])?;
 
         if !(0..=255).contains(&index) {
             return Err(RuntimeErr::InvalidMemoryLocation);
         }
 
-        let data = self.read_mem(index as u8);
-
-        if data.is_err() {
-            return Err(data.unwrap_err());
-        }
+        let data = self.read_mem(index as u8)?;
 
-        self.write_reg(self.current_regs[0], data.unwrap())
+        self.write_reg(self.current_regs[0], data)
     }
 
     pub fn perform_storer(&mut self) -> Result<(), RuntimeErr> {
-        let index = self.read_reg(self.current_regs[1]);
-        if index.is_err() {
-            return Err(index.unwrap_err());
-        }
-
-        let index = index.unwrap();
+        let index = self.read_reg(self.current_regs[1])?;
 
         if !(0..=255).contains(&index) {
             return Err(RuntimeErr::InvalidMemoryLocation);
         }
 
-        let data = self.read_reg(self.current_regs[1] as u8)?;
+        let data = self.read_reg(self.current_regs[0])?;
 
         self.write_mem(index as u8, data)
     }
@@ -1088,17 +1677,17 @@ impl Simulator {
     pub fn perform_popr(&mut self) -> Result<(), RuntimeErr> {
         let reg_y_data = self.read_reg(self.current_regs[1])?;
 
-        let reg_y_data = reg_y_data;
-
-        if !(0..=255).contains(&reg_y_data) {
+        // The stack pointer is decremented before reading, so it
+        // has to point one past a valid memory address
+        if !(1..=256).contains(&reg_y_data) {
             return Err(RuntimeErr::InvalidMemoryLocation);
         }
 
-        let _change_reg = self.write_reg(self.current_regs[1], reg_y_data - 1)?;
-
-        let reg_y_data = reg_y_data as u8;
+        // Read before writing either register, so a read that traps
+        // leaves the stack pointer as it was
+        let mem_data = self.read_mem((reg_y_data - 1) as u8)?;
 
-        let mem_data = self.read_mem(reg_y_data - 1)?;
+        self.write_reg(self.current_regs[1], reg_y_data - 1)?;
 
         self.write_reg(self.current_regs[0], mem_data)
     }
@@ -1137,7 +1726,7 @@ impl Simulator {
 
         let reg_x_data = self.read_reg(self.current_regs[0])?;
 
-        self.write_reg(self.current_regs[0], reg_x_data + ending_data as i16)
+        self.write_overflowing(self.current_regs[0], reg_x_data as i32 + ending_data as i32)
     }
 
     pub fn perform_copy(&mut self) -> Result<(), RuntimeErr> {
@@ -1147,35 +1736,39 @@ impl Simulator {
     }
 
     pub fn perform_neg(&mut self) -> Result<(), RuntimeErr> {
-        let reg_y_data = self.read_reg(self.current_regs[1])?;
+        // neg is encoded as sub rX r0 rY, so rY is in the last slot
+        let reg_y_data = self.read_reg(self.current_regs[2])?;
 
-        self.write_reg(self.current_regs[0], -reg_y_data)
+        self.write_overflowing(self.current_regs[0], -(reg_y_data as i32))
     }
 
-    pub fn perform_arithmetic(&mut self, name: &str) -> Result<(), RuntimeErr> {
+    pub fn perform_arithmetic(&mut self, opcode: Opcode) -> Result<(), RuntimeErr> {
         let reg_z_data = self.read_reg(self.current_regs[2])?;
 
         let reg_y_data = self.read_reg(self.current_regs[1])?;
 
-        if reg_z_data == 0 && name == "div" {
+        if reg_z_data == 0 && (opcode == Opcode::Div || opcode == Opcode::Mod) {
             return Err(RuntimeErr::DivideByZero);
         }
-        // Coerce to a higher level data type
-        // so that we can detect an out of bounds error
-        let result: i32 = match name {
-            "add" => reg_y_data as i32 + reg_z_data as i32,
-            "sub" => reg_y_data as i32 - reg_z_data as i32,
-            "mul" => reg_y_data as i32 * reg_z_data as i32,
-            "div" => reg_y_data as i32 / reg_z_data as i32,
-            "mod" => reg_y_data as i32 % reg_z_data as i32,
+        // Coerce to a higher level data type so that every result
+        // is exact, even -32768 / -1, before it's fitted into a register
+        let result: i32 = match opcode {
+            Opcode::Add => reg_y_data as i32 + reg_z_data as i32,
+            Opcode::Sub => reg_y_data as i32 - reg_z_data as i32,
+            Opcode::Mul => reg_y_data as i32 * reg_z_data as i32,
+            Opcode::Div => reg_y_data as i32 / reg_z_data as i32,
+            Opcode::Mod => reg_y_data as i32 % reg_z_data as i32,
             _ => 0,
         };
 
-        if result > i16::MAX as i32 || result < i16::MIN as i32 {
-            return Err(RuntimeErr::RegisterOutOfBounds);
-        }
+        self.write_overflowing(self.current_regs[0], result)
+    }
 
-        self.write_reg(self.current_regs[0], result as i16)
+    /// Writes the result of arithmetic to a register, as the overflow policy says
+    fn write_overflowing(&mut self, register: u8, result: i32) -> Result<(), RuntimeErr> {
+        let data = self.overflow.apply(result).ok_or(RuntimeErr::RegisterOutOfBounds)?;
+
+        self.write_reg(register, data)
     }
 
     pub fn perform_jumpr(&mut self) -> Result<(), RuntimeErr> {
@@ -1190,14 +1783,14 @@ impl Simulator {
 
     pub fn perform_jumpn(&mut self) -> Result<(), RuntimeErr> {
         self.just_updated_pc = true;
-        self.update_pc(self.get_ending_data()? as usize)
+        self.update_pc(self.get_ending_address()?)
     }
 
     pub fn perform_jeqzn(&mut self) -> Result<(), RuntimeErr> {
         let reg_x_data = self.read_reg(self.current_regs[0])?;
         if reg_x_data == 0 {
             self.just_updated_pc = true;
-            self.update_pc(self.get_ending_data()? as usize)
+            self.update_pc(self.get_ending_address()?)
         } else {
             Ok(())
         }
@@ -1207,7 +1800,7 @@ impl Simulator {
         let reg_x_data = self.read_reg(self.current_regs[0])?;
         if reg_x_data != 0 {
             self.just_updated_pc = true;
-            self.update_pc(self.get_ending_data()? as usize)
+            self.update_pc(self.get_ending_address()?)
         } else {
             Ok(())
         }
@@ -1217,7 +1810,7 @@ impl Simulator {
         let reg_x_data = self.read_reg(self.current_regs[0])?;
         if reg_x_data > 0 {
             self.just_updated_pc = true;
-            self.update_pc(self.get_ending_data()? as usize)
+            self.update_pc(self.get_ending_address()?)
         } else {
             Ok(())
         }
@@ -1227,58 +1820,84 @@ impl Simulator {
         let reg_x_data = self.read_reg(self.current_regs[0])?;
         if reg_x_data < 0 {
             self.just_updated_pc = true;
-            self.update_pc(self.get_ending_data()? as usize)
+            self.update_pc(self.get_ending_address()?)
         } else {
             Ok(())
         }
     }
 
     pub fn perform_calln(&mut self) -> Result<(), RuntimeErr> {
-        let _update_rg = self.write_reg(
+        self.write_reg(
             self.current_regs[0],
             (self.get_program_counter() + 1) as i16,
         )?;
 
         self.just_updated_pc = true;
 
-        self.update_pc(self.get_ending_data()? as usize)
+        self.update_pc(self.get_ending_address()?)
     }
 }
 
-pub fn signed_binary_conversion(binary: &str) -> Result<i8, RuntimeErr> {
-    let is_negative: bool = { binary.starts_with('1') };
-    let mut binary_mut: String = binary.to_owned();
-    if is_negative {
-        binary_mut = binary.chars().rev().collect();
-        let mut found_1 = false;
-        let mut temp_string: String = "".to_string();
-
-        for i in binary_mut.chars() {
-            if found_1 {
-                if i == '1' {
-                    temp_string += "0";
-                } else {
-                    temp_string += "0";
-                }
-            } else if i == '1' && !found_1 {
-                found_1 = true;
-                temp_string += "1";
-            }
-        }
+/// Parses a numeric literal, as used by every operand and directive:
+/// decimal (10), hex (0x1F), binary (0b1010) or a character ('A'),
+/// any of which can be negative (-0x10)
+pub fn parse_literal(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        parse_digits(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        parse_digits(binary, 2)
+    } else if digits.len() >= 3 && digits.starts_with('\'') && digits.ends_with('\'') {
+        parse_char_literal(&digits[1..digits.len() - 1]).map(|c| c as i64)
+    } else {
+        parse_digits(digits, 10)
+    };
 
-        binary_mut = temp_string.chars().rev().collect();
-    }
+    let value = if negative { -value? } else { value? };
 
-    let decoded_signed = i8::from_str_radix(binary_mut.as_str(), 2);
+    i32::try_from(value).ok()
+}
+
+/// Parses digits in a radix. Unlike from_str_radix, this doesn't allow
+/// a sign, so the only sign a literal can have is the one before its prefix
+fn parse_digits(digits: &str, radix: u32) -> Option<i64> {
+    if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+        i64::from_str_radix(digits, radix).ok()
+    } else {
+        None
+    }
+}
 
-    if decoded_signed.is_err() {
-        return Err(RuntimeErr::InvalidSignedNumber);
+/// Parses the inside of a character literal, which is either a single
+/// ASCII character or one of the escapes \n, \t, \0, \\ and \'
+fn parse_char_literal(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+
+    let c = match (chars.next()?, chars.next()) {
+        ('\\', Some('n')) => '\n',
+        ('\\', Some('t')) => '\t',
+        ('\\', Some('0')) => '\0',
+        ('\\', Some('\\')) => '\\',
+        ('\\', Some('\'')) => '\'',
+        (c, None) if c != '\\' => c,
+        _ => return None,
+    };
+
+    match chars.next() {
+        None if c.is_ascii() => Some(c),
+        _ => None,
     }
+}
 
-    if is_negative {
-        Ok(0 - decoded_signed.unwrap())
-    } else {
-        Ok(decoded_signed.unwrap())
+/// Converts 8 bits of two's complement binary into a signed number
+pub fn signed_binary_conversion(binary: &str) -> Result<i8, RuntimeErr> {
+    match u8::from_str_radix(binary, 2) {
+        Ok(unsigned) => Ok(unsigned as i8),
+        Err(_) => Err(RuntimeErr::InvalidSignedNumber),
     }
 }
 
@@ -0,0 +1,385 @@
+use super::packed::{
+    is_supported_version, pack_source_map, push_section, push_string, push_u16, push_u32,
+    read_sections, unpack_source_map, Reader,
+};
+use super::simulator::*;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// First bytes of every snapshot
+pub const MAGIC: &[u8; 4] = b"HMMS";
+
+/// Version of the snapshot format written by save. As with packed
+/// binaries, snapshots from older versions can still be restored, and
+/// newer ones (or version 0, which was never written) are refused with
+/// UnsupportedVersion.
+///
+/// Version 2 added the overflow policy to the CPU flags, which older
+/// readers would ignore and restore as trapping
+pub const VERSION: u8 = 2;
+
+/// Section holding every word of memory
+const MEMORY_SECTION: &[u8; 4] = b"MEMW";
+
+/// Section holding the program as it was loaded
+const PROGRAM_SECTION: &[u8; 4] = b"PROG";
+
+/// Section holding the registers and program counter
+const CPU_SECTION: &[u8; 4] = b"CPU ";
+
+/// Section holding the counter log
+const COUNTER_LOG_SECTION: &[u8; 4] = b"CLOG";
+
+/// Section holding inputs that haven't been read yet
+const INPUTS_SECTION: &[u8; 4] = b"INPT";
+
+/// Section holding the numbers written so far
+const OUTPUTS_SECTION: &[u8; 4] = b"OUTP";
+
+/// Section holding the number of steps run
+const STEPS_SECTION: &[u8; 4] = b"STEP";
+
+/// Section holding the source map
+const SOURCE_MAP_SECTION: &[u8; 4] = b"SMAP";
+
+/// Flags kept with each word of memory
+const DATA_WORD: u8 = 1;
+const WRITTEN: u8 = 2;
+
+/// Flags kept with the registers
+const JUST_UPDATED_PC: u8 = 1;
+const STRICT: u8 = 2;
+const WRAP: u8 = 4;
+const SATURATE: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotErr {
+    NotASnapshot,
+    Corrupted,
+    UnsupportedVersion,
+}
+
+impl SnapshotErr {
+    pub fn as_code(&self) -> i32 {
+        match self {
+            SnapshotErr::NotASnapshot => 60,
+            SnapshotErr::Corrupted => 61,
+            SnapshotErr::UnsupportedVersion => 62,
+        }
+    }
+}
+
+impl fmt::Display for SnapshotErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            SnapshotErr::NotASnapshot => "file is not a snapshot",
+            SnapshotErr::Corrupted => {
+                "snapshot is damaged, or holds a state no simulator could be in"
+            }
+            SnapshotErr::UnsupportedVersion => "snapshot format version is not supported",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+impl std::error::Error for SnapshotErr {}
+
+/// Sections are read in the same way as a packed binary, which
+/// only fails if the bytes run out or don't make sense
+impl From<CompileErr> for SnapshotErr {
+    fn from(_: CompileErr) -> Self {
+        SnapshotErr::Corrupted
+    }
+}
+
+fn push_numbers(bytes: &mut Vec<u8>, numbers: impl ExactSizeIterator<Item = u16>) {
+    push_u32(bytes, numbers.len() as u32);
+
+    for number in numbers {
+        push_u16(bytes, number);
+    }
+}
+
+fn read_numbers(reader: &mut Reader) -> Result<Vec<u16>, SnapshotErr> {
+    Ok((0..reader.u32()?)
+        .map(|_| reader.u16())
+        .collect::<Result<_, _>>()?)
+}
+
+fn pack_memory(sim: &Simulator) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for address in 0..256 {
+        push_u16(&mut bytes, sim.memory[address]);
+
+        let mut flags = 0;
+        if sim.data_words[address] {
+            flags |= DATA_WORD;
+        }
+        if sim.written[address] {
+            flags |= WRITTEN;
+        }
+        bytes.push(flags);
+    }
+
+    bytes
+}
+
+fn pack_program(program: &[Instruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_u16(&mut bytes, program.len() as u16);
+
+    for instruction in program {
+        push_string(&mut bytes, instruction.instruction_type.names[0]);
+        push_string(&mut bytes, &instruction.text_contents);
+        push_string(&mut bytes, &instruction.binary_contents.join(""));
+    }
+
+    bytes
+}
+
+fn pack_cpu(sim: &Simulator) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.push(sim.registers.len() as u8);
+
+    for register in &sim.registers {
+        push_u16(&mut bytes, *register as u16);
+    }
+
+    push_u16(&mut bytes, sim.program_counter as u16);
+    bytes.extend_from_slice(&sim.current_regs);
+
+    let mut flags = 0;
+    if sim.just_updated_pc {
+        flags |= JUST_UPDATED_PC;
+    }
+    if sim.strict {
+        flags |= STRICT;
+    }
+    match sim.overflow {
+        OverflowPolicy::Trap => {}
+        OverflowPolicy::Wrap => flags |= WRAP,
+        OverflowPolicy::Saturate => flags |= SATURATE,
+    }
+    bytes.push(flags);
+
+    bytes
+}
+
+/// Saves everything needed to carry on running a simulator later:
+/// memory, registers, the program counter, strict mode and the
+/// overflow policy, the counter log, inputs
+/// that haven't been read yet, outputs written so far and the number
+/// of steps run, along with the source map. The history kept for
+/// step_back isn't saved.
+///
+/// A snapshot is the magic bytes "HMMS" and a version byte, followed by
+/// sections in the same layout as a packed binary, so newer versions can
+/// add sections without breaking older readers. Inputs are only kept if
+/// the input source knows them ahead of time, such as those from set_inputs.
+///
+/// Input sources and output sinks can't be saved, so a restored simulator
+/// writes to the terminal, and reads the saved inputs, or the terminal if
+/// none were saved. Give it its devices again with set_input_source and
+/// set_output_sink
+pub fn save(sim: &Simulator) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+
+    push_section(&mut bytes, MEMORY_SECTION, pack_memory(sim));
+    push_section(&mut bytes, PROGRAM_SECTION, pack_program(&sim.program));
+    push_section(&mut bytes, CPU_SECTION, pack_cpu(sim));
+
+    let mut counter_log = Vec::new();
+    push_numbers(
+        &mut counter_log,
+        sim.counter_log.iter().map(|address| *address as u16),
+    );
+    push_section(&mut bytes, COUNTER_LOG_SECTION, counter_log);
+
+    if let Some(inputs) = sim.get_remaining_inputs() {
+        let mut section = Vec::new();
+        push_numbers(&mut section, inputs.iter().map(|input| *input as u16));
+        push_section(&mut bytes, INPUTS_SECTION, section);
+    }
+
+    let mut outputs = Vec::new();
+    push_numbers(
+        &mut outputs,
+        sim.outputs.iter().map(|output| *output as u16),
+    );
+    push_section(&mut bytes, OUTPUTS_SECTION, outputs);
+    push_section(&mut bytes, STEPS_SECTION, sim.steps.to_be_bytes().to_vec());
+
+    if !sim.source_map.is_empty() {
+        push_section(
+            &mut bytes,
+            SOURCE_MAP_SECTION,
+            pack_source_map(&sim.source_map),
+        );
+    }
+
+    bytes
+}
+
+fn unpack_program(reader: &mut Reader) -> Result<Vec<Instruction>, SnapshotErr> {
+    let mut program = Vec::new();
+
+    for _ in 0..reader.u16()? {
+        let instruction_type =
+            InstructionType::from_name(&reader.string()?).ok_or(SnapshotErr::Corrupted)?;
+        let text_contents = reader.string()?;
+        let binary = reader.string()?;
+
+        if binary.len() != 16 || !binary.chars().all(|bit| bit == '0' || bit == '1') {
+            return Err(SnapshotErr::Corrupted);
+        }
+
+        program.push(Instruction {
+            instruction_type,
+            text_contents,
+            binary_contents: (0..4)
+                .map(|nibble| binary[nibble * 4..nibble * 4 + 4].to_string())
+                .collect(),
+        });
+    }
+
+    if program.len() > 256 {
+        return Err(SnapshotErr::Corrupted);
+    }
+
+    Ok(program)
+}
+
+fn unpack_memory(reader: &mut Reader, sim: &mut Simulator) -> Result<(), SnapshotErr> {
+    for address in 0..256 {
+        sim.memory[address] = reader.u16()?;
+
+        let flags = reader.u8()?;
+        sim.data_words[address] = flags & DATA_WORD != 0;
+        sim.written[address] = flags & WRITTEN != 0;
+    }
+
+    Ok(())
+}
+
+fn unpack_cpu(reader: &mut Reader, sim: &mut Simulator) -> Result<(), SnapshotErr> {
+    let count = reader.u8()?;
+    sim.registers = (0..count)
+        .map(|_| reader.u16().map(|register| register as i16))
+        .collect::<Result<_, _>>()?;
+
+    if sim.registers.len() != 16 {
+        return Err(SnapshotErr::Corrupted);
+    }
+
+    sim.program_counter = reader.u16()? as usize;
+    sim.current_regs = [reader.u8()?, reader.u8()?, reader.u8()?];
+
+    // A running simulator never gets into these states, so they can
+    // only come from a damaged snapshot
+    if sim.program_counter > 255 || sim.current_regs.iter().any(|register| *register > 15) {
+        return Err(SnapshotErr::Corrupted);
+    }
+
+    let flags = reader.u8()?;
+    sim.just_updated_pc = flags & JUST_UPDATED_PC != 0;
+    sim.strict = flags & STRICT != 0;
+    sim.overflow = if flags & WRAP != 0 {
+        OverflowPolicy::Wrap
+    } else if flags & SATURATE != 0 {
+        OverflowPolicy::Saturate
+    } else {
+        OverflowPolicy::Trap
+    };
+
+    Ok(())
+}
+
+/// Restores a simulator saved with save, ready to carry on from where it
+/// was. Its outputs go to the terminal, and its inputs come from the
+/// snapshot if it has them, or the terminal if it doesn't, whatever devices
+/// it had when it was saved. Sections that aren't understood are skipped
+pub fn restore(bytes: &[u8]) -> Result<Simulator, SnapshotErr> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(SnapshotErr::NotASnapshot);
+    }
+
+    let mut reader = Reader::new(&bytes[MAGIC.len()..]);
+
+    if !is_supported_version(reader.u8()?, VERSION) {
+        return Err(SnapshotErr::UnsupportedVersion);
+    }
+
+    let mut sections = Vec::new();
+
+    read_sections(&mut reader, |tag, section| {
+        sections.push((*tag, section.take(section.remaining())?.to_vec()));
+        Ok(())
+    })?;
+
+    // The program comes first, as it's needed to create the simulator
+    let mut program = Vec::new();
+
+    for (tag, bytes) in &sections {
+        if tag == PROGRAM_SECTION {
+            program = unpack_program(&mut Reader::new(bytes))?;
+        }
+    }
+
+    let mut sim = Simulator::new(program);
+    let mut found_memory = false;
+    let mut found_cpu = false;
+
+    for (tag, bytes) in sections {
+        let section = &mut Reader::new(&bytes);
+
+        match &tag {
+            MEMORY_SECTION => {
+                unpack_memory(section, &mut sim)?;
+                found_memory = true;
+            }
+            CPU_SECTION => {
+                unpack_cpu(section, &mut sim)?;
+                found_cpu = true;
+            }
+            COUNTER_LOG_SECTION => {
+                sim.counter_log = read_numbers(section)?
+                    .into_iter()
+                    .map(|address| address as usize)
+                    .collect();
+
+                if sim.counter_log.iter().any(|address| *address > 255) {
+                    return Err(SnapshotErr::Corrupted);
+                }
+            }
+            INPUTS_SECTION => {
+                let inputs = read_numbers(section)?;
+                sim.set_inputs(inputs.into_iter().map(|input| input as i16).collect());
+            }
+            OUTPUTS_SECTION => {
+                sim.outputs = read_numbers(section)?
+                    .into_iter()
+                    .map(|output| output as i16)
+                    .collect();
+            }
+            STEPS_SECTION => {
+                let bytes = section.take(8)?;
+                sim.steps = u64::from_be_bytes(<[u8; 8]>::try_from(bytes).unwrap());
+            }
+            SOURCE_MAP_SECTION => sim.source_map = unpack_source_map(section)?,
+            _ => {}
+        }
+    }
+
+    if !found_memory || !found_cpu {
+        return Err(SnapshotErr::Corrupted);
+    }
+
+    for address in 0..256 {
+        sim.predecode(address);
+    }
+
+    Ok(sim)
+}
@@ -1,12 +1,13 @@
 
 use hmmm_rs::{load_file, write_compiled_hmmm, read_compiled_hmmm};
 use hmmm_rs::simulator::*;
+use hmmm_rs::breakpoints::*;
 
 pub fn create_dummy_simulator() -> Simulator {
     // Load file
     let initial_file = load_file("tests/test.hmmm").unwrap();
     // Compile
-    let compile_result = Simulator::compile_hmmm(initial_file, true).unwrap();
+    let compile_result = Simulator::compile_hmmm(initial_file).unwrap();
     // Create simulator object
     Simulator::new(compile_result)
 }
@@ -16,15 +17,18 @@ fn compile_uncompile_binary_test() {
     // Load file
     let initial_file = load_file("tests/test.hmmm").unwrap();
     // Compile
-    let initial_compiled_file = Simulator::compile_hmmm(initial_file, true).unwrap();
+    let initial_compiled_file = Simulator::compile_hmmm(initial_file).unwrap();
     // Get the binary contents
     let binary_1: Vec<Vec<String>> = initial_compiled_file.iter().map(|x| x.binary_contents.clone()).collect();
     // Write compiled file
-    write_compiled_hmmm("tests/test.hb", initial_compiled_file).unwrap();
+    let output = std::env::temp_dir().join("hmmm_rs_compile_uncompile_test.hb");
+    let output = output.to_str().unwrap();
+    write_compiled_hmmm(output, initial_compiled_file).unwrap();
     // Load compiled file
-    let compiled_file = load_file("tests/test.hb").unwrap();
+    let compiled_file = load_file(output).unwrap();
+    std::fs::remove_file(output).unwrap();
     // Uncompile
-    let uncompiled_file = read_compiled_hmmm(compiled_file);
+    let uncompiled_file = read_compiled_hmmm(compiled_file).unwrap();
     // Get the binary contents
     let binary_2: Vec<Vec<String>> = uncompiled_file.iter().map(|x| x.binary_contents.clone()).collect();
 
@@ -74,3 +78,1717 @@ fn perform_halt_test() {
     // Perform halt
     assert_eq!(sim.perform_halt(), Err(RuntimeErr::Halt));
 }
+
+/// Runs a headless program until it halts, giving what it wrote
+fn run_headless(lines: &[&str]) -> Result<Vec<i16>, RuntimeErr> {
+    let program = lines.iter().map(|line| line.to_string()).collect();
+    let mut sim = Simulator::new_headless(Simulator::compile_hmmm(program).unwrap());
+
+    loop {
+        match sim.step() {
+            Ok(()) => {}
+            Err(err) if err.kind == RuntimeErr::Halt => return Ok(sim.get_outputs()),
+            Err(err) => return Err(err.kind),
+        }
+    }
+}
+
+#[test]
+fn perform_storer_test() {
+    // storer rX rY stores rX at the address held in rY
+    let program = [
+        "setn r1 42",
+        "setn r2 100",
+        "storer r1 r2",
+        "loadn r3 100",
+        "write r3",
+        "halt",
+    ];
+
+    assert_eq!(run_headless(&program), Ok(vec![42]));
+}
+
+#[test]
+fn perform_popr_test() {
+    // A push to the last address leaves the stack pointer at 256
+    let program = [
+        "setn r14 127",
+        "addn r14 127",
+        "addn r14 1",
+        "setn r1 9",
+        "pushr r1 r14",
+        "popr r2 r14",
+        "write r2",
+        "write r14",
+        "halt",
+    ];
+
+    assert_eq!(run_headless(&program), Ok(vec![9, 255]));
+
+    // Popping an empty stack has nothing to read
+    assert_eq!(
+        run_headless(&["popr r1 r14", "halt"]),
+        Err(RuntimeErr::InvalidMemoryLocation)
+    );
+}
+
+#[test]
+fn negative_immediate_test() {
+    // setn and addn take 8 bit two's complement numbers
+    let program = ["setn r1 -5", "addn r1 -3", "write r1", "halt"];
+
+    assert_eq!(run_headless(&program), Ok(vec![-8]));
+
+    let compiled = Simulator::compile_hmmm(vec!["setn r1 -128".to_string()]).unwrap();
+    let decoded = Instruction::new_from_binary(&compiled[0].binary_contents.join(" ")).unwrap();
+
+    assert_eq!(decoded.text_contents, "r1, -128");
+}
+
+#[test]
+fn high_jump_target_test() {
+    // Jump and call targets are addresses from 0 to 255, not signed numbers
+    let mut program = vec!["jumpn 200"];
+    program.resize(200, "halt");
+    program.extend(&["calln r14 202", "halt", "write r14", "halt"]);
+
+    assert_eq!(run_headless(&program), Ok(vec![201]));
+}
+
+#[test]
+fn perform_neg_test() {
+    let program = ["setn r2 5", "neg r1 r2", "write r1", "halt"];
+
+    assert_eq!(run_headless(&program), Ok(vec![-5]));
+}
+
+#[test]
+fn labels_match_line_numbers_test() {
+    let numbered = Simulator::compile_hmmm(load_file("tests/power.hmmm").unwrap()).unwrap();
+    let labelled = Simulator::compile_hmmm(load_file("tests/power_labels.hmmm").unwrap()).unwrap();
+
+    let binary_1: Vec<Vec<String>> = numbered.iter().map(|x| x.binary_contents.clone()).collect();
+    let binary_2: Vec<Vec<String>> = labelled.iter().map(|x| x.binary_contents.clone()).collect();
+
+    assert_eq!(binary_1, binary_2);
+}
+
+pub fn compile_errors(lines: &[&str]) -> Vec<CompileErr> {
+    let lines = lines.iter().map(|line| line.to_string()).collect();
+
+    Simulator::compile_hmmm(lines)
+        .unwrap_err()
+        .iter()
+        .map(|diagnostic| diagnostic.error.clone())
+        .collect()
+}
+
+#[test]
+fn label_errors_test() {
+    assert_eq!(
+        compile_errors(&["jumpn nowhere", "halt"]),
+        vec![CompileErr::UndefinedLabel]
+    );
+    assert_eq!(
+        compile_errors(&["start: nop", "start: halt"]),
+        vec![CompileErr::DuplicateLabel]
+    );
+    assert_eq!(
+        compile_errors(&["0 nop", "halt"]),
+        vec![CompileErr::LineNumberNotPresent]
+    );
+}
+
+#[test]
+fn all_compile_errors_reported_test() {
+    let lines: Vec<String> = ["0 setn r16, 5", "1 wrte r1", "2 addn r1", "4 halt"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let diagnostics = Simulator::compile_hmmm(lines).unwrap_err();
+
+    let errors: Vec<CompileErr> = diagnostics.iter().map(|d| d.error.clone()).collect();
+    assert_eq!(
+        errors,
+        vec![
+            CompileErr::InvalidRegister,
+            CompileErr::InstructionDoesNotExist,
+            CompileErr::TooFewArguments,
+            CompileErr::InvalidLineNumber,
+        ]
+    );
+
+    // Bad register is underlined, and the expected argument is given
+    assert_eq!(diagnostics[0].line, 1);
+    assert_eq!(diagnostics[0].span, 7..10);
+    assert_eq!(diagnostics[0].expected, Some('r'));
+
+    // Misspelled instruction gets a suggestion
+    assert_eq!(diagnostics[1].span, 2..6);
+    assert_eq!(
+        diagnostics[1].suggestion,
+        Some("did you mean \"write\"?".to_string())
+    );
+
+    // Missing argument points just past the end of the line
+    assert_eq!(diagnostics[2].expected, Some('s'));
+    assert_eq!(diagnostics[2].span, 9..10);
+}
+
+#[test]
+fn corrupted_binary_test() {
+    let raw_binary = vec![
+        "0001 0001 0000 0101".to_string(),
+        "0001 01".to_string(),
+        "0000 0000 0000 0000".to_string(),
+    ];
+    let diagnostics = read_compiled_hmmm(raw_binary).unwrap_err();
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].error, CompileErr::CorruptedBinary);
+    assert_eq!(diagnostics[0].line, 2);
+}
+
+#[test]
+fn runtime_error_context_test() {
+    let program: Vec<String> = ["setn r1 5", "div r2 r1 r0", "halt"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let mut sim = Simulator::new_headless(Simulator::compile_hmmm(program).unwrap());
+
+    sim.step().unwrap();
+    let err = sim.step().unwrap_err();
+
+    assert_eq!(err.kind, RuntimeErr::DivideByZero);
+    assert_eq!(err.address, 1);
+    assert_eq!(err.instruction.instruction_type.names[0], "div");
+    assert_eq!(
+        err.to_string(),
+        "divide by zero at address 1 (div r2, r1, r0)"
+    );
+}
+
+#[test]
+fn directives_test() {
+    let assembly = hmmm_rs::assembler::assemble(&load_file("tests/table.hmmm").unwrap()).unwrap();
+
+    assert_eq!(assembly.constants["COUNT"], 4);
+    assert_eq!(assembly.symbols["table"], 100);
+    assert_eq!(assembly.instructions.len(), 104);
+    assert_eq!(assembly.instructions[103].text_contents, "-5");
+
+    let mut sim = Simulator::new_headless(assembly.instructions);
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![55, 7]);
+}
+
+#[test]
+fn directive_errors_test() {
+    assert_eq!(
+        compile_errors(&[".wrd 5", "halt"]),
+        vec![CompileErr::UnknownDirective]
+    );
+    assert_eq!(
+        compile_errors(&["nop", "nop", ".org 1", "halt"]),
+        vec![CompileErr::InvalidOrigin]
+    );
+    assert_eq!(
+        compile_errors(&[".equ SIZE 1", "SIZE: halt"]),
+        vec![CompileErr::DuplicateLabel]
+    );
+    assert_eq!(
+        compile_errors(&["halt", ".word 70000"]),
+        vec![CompileErr::InvalidNumber]
+    );
+    assert_eq!(
+        compile_errors(&["halt", ".fill 256"]),
+        vec![CompileErr::ProgramTooLarge]
+    );
+
+    // A bad count is only reported by the first pass
+    for count in &[".fill 300", ".fill -1, 5", ".space 300", ".fill", ".space"] {
+        assert_eq!(compile_errors(&["halt", count]).len(), 1, "{}", count);
+    }
+}
+
+#[test]
+fn include_test() {
+    let path = "tests/include.hmmm";
+    let assembly = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path).unwrap();
+
+    // mul.hmmm is included twice, but only spliced in once
+    assert_eq!(assembly.symbols["multiply"], 5);
+    assert_eq!(assembly.symbols["print_result"], 11);
+
+    let mut sim = Simulator::new_headless(assembly.instructions);
+    sim.set_inputs(vec![6, 7]);
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42]);
+}
+
+#[test]
+fn include_errors_test() {
+    let path = "tests/lib/cycle_a.hmmm";
+    let diagnostics =
+        hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path).unwrap_err();
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].error, CompileErr::IncludeCycle);
+    assert_eq!(
+        diagnostics[0].file.as_deref(),
+        Some("tests/lib/cycle_b.hmmm")
+    );
+
+    assert_eq!(
+        compile_errors(&["halt", ".include \"missing.hmmm\""]),
+        vec![CompileErr::IncludeNotFound]
+    );
+
+    // Symbols clashing across files are reported in the file they're in
+    let lines = vec![
+        "multiply: halt".to_string(),
+        ".include \"tests/lib/mul.hmmm\"".to_string(),
+    ];
+    let diagnostics = hmmm_rs::assembler::assemble(&lines).unwrap_err();
+
+    assert_eq!(diagnostics[0].error, CompileErr::DuplicateLabel);
+    assert_eq!(diagnostics[0].file.as_deref(), Some("tests/lib/mul.hmmm"));
+    assert_eq!(diagnostics[0].line, 3);
+}
+
+#[test]
+fn macros_test() {
+    let program = Simulator::compile_hmmm(load_file("tests/macros.hmmm").unwrap()).unwrap();
+
+    assert_eq!(program[6].instruction_type.names[0], "pushr");
+    assert_eq!(program[12].text_contents, "r1 -1");
+    assert_eq!(program[13].text_contents, "r2 r15");
+
+    let mut sim = Simulator::new_headless(program);
+    sim.set_inputs(vec![-4, 9, 0]);
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![4, 9]);
+
+    // A numbered call is numbered by its first word, and takes up every word it expands to
+    let lines: Vec<String> = [
+        ".macro twice reg",
+        "    addn \\reg 1",
+        "    addn \\reg 1",
+        ".endm",
+        "0 setn r15 100",
+        "1 setn r1 5",
+        "2 push r1 r2",
+        "4 twice r1",
+        "6 write r1",
+        "7 halt",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let program = Simulator::compile_hmmm(lines).unwrap();
+    assert_eq!(program.len(), 8);
+
+    let mut sim = Simulator::new_headless(program);
+    while sim.step().is_ok() {}
+    assert_eq!(sim.get_outputs(), vec![7]);
+    assert_eq!(sim.get_word(100), Some(5));
+
+    assert_eq!(
+        compile_errors(&["0 push r1 r2", "1 halt"]),
+        vec![CompileErr::InvalidLineNumber]
+    );
+}
+
+#[test]
+fn macro_errors_test() {
+    let lines: Vec<String> = [
+        ".macro twice reg",
+        "  addn \\reg 2",
+        "  addn \\reg 2",
+        ".endm",
+        "twice r99",
+        "halt",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let diagnostics = Simulator::compile_hmmm(lines).unwrap_err();
+
+    // Errors inside an expansion point at the macro call
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].error, CompileErr::InvalidRegister);
+    assert_eq!(diagnostics[0].line, 5);
+    assert_eq!(diagnostics[0].source, "twice r99");
+    assert_eq!(diagnostics[0].span, 0..5);
+
+    assert_eq!(
+        compile_errors(&[".macro twice reg", ".endm", "twice r1 r2"]),
+        vec![CompileErr::TooManyArguments]
+    );
+
+    assert_eq!(
+        compile_errors(&["inc", "halt"]),
+        vec![CompileErr::TooFewArguments]
+    );
+    assert_eq!(
+        compile_errors(&[".macro loop", "loop", ".endm", "loop"]),
+        vec![CompileErr::MacroRecursion]
+    );
+    assert_eq!(
+        compile_errors(&[".macro add a", ".endm", "halt"]),
+        vec![CompileErr::InvalidMacro]
+    );
+    assert_eq!(
+        compile_errors(&[".macro open", "halt"]),
+        vec![CompileErr::InvalidMacro]
+    );
+}
+
+#[test]
+fn source_map_test() {
+    let lines: Vec<String> = [
+        "setn r1 5",
+        "",
+        "# comment lines don't count",
+        "div r2 r1 r0   # oops",
+        "halt",
+        ".word 1, 2",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let assembly = hmmm_rs::assembler::assemble(&lines).unwrap();
+
+    let location = assembly.source_map.get(1).unwrap();
+    assert_eq!(location.line, 4);
+    assert_eq!(location.column, 0);
+    assert_eq!(location.comment.as_deref(), Some("# oops"));
+
+    // Each value of a .word is located separately
+    assert_eq!(assembly.source_map.get(4).unwrap().column, 9);
+
+    let mut sim = Simulator::new_headless(assembly.instructions);
+    sim.set_source_map(assembly.source_map);
+
+    sim.step().unwrap();
+    let err = sim.step().unwrap_err();
+
+    assert_eq!(err.location.unwrap().line, 4);
+
+    // Macro expansions map back to the line they were used on
+    let path = "tests/macros.hmmm";
+    let assembly = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path).unwrap();
+
+    assert_eq!(assembly.source_map.get(6).unwrap().line, 18);
+    assert_eq!(assembly.source_map.get(7).unwrap().line, 18);
+    assert_eq!(
+        assembly.source_map.get(7).unwrap().file.as_deref(),
+        Some(path)
+    );
+}
+
+#[test]
+fn lint_test() {
+    use hmmm_rs::assembler::{assemble, SourceMap};
+    use hmmm_rs::lint::*;
+
+    let assembly = assemble(&load_file("tests/lint.hmmm").unwrap()).unwrap();
+    let findings = lint(&assembly.instructions, &assembly.source_map);
+    let kinds: Vec<LintKind> = findings.iter().map(|finding| finding.kind).collect();
+
+    assert_eq!(
+        kinds,
+        vec![
+            LintKind::UninitializedRegister,
+            LintKind::WriteToZeroRegister,
+            LintKind::JumpIntoData,
+            LintKind::JumpOutOfProgram,
+            LintKind::UnreachableCode,
+        ]
+    );
+    assert_eq!(findings[2].severity, Severity::Error);
+    assert_eq!(findings[2].location.as_ref().unwrap().line, 5);
+
+    let power = Simulator::compile_hmmm(load_file("tests/power.hmmm").unwrap()).unwrap();
+    assert_eq!(lint(&power, &SourceMap::new()), Vec::new());
+
+    let no_halt =
+        Simulator::compile_hmmm(vec!["read r1".to_string(), "write r1".to_string()]).unwrap();
+    assert_eq!(
+        lint(&no_halt, &SourceMap::new())[0].kind,
+        LintKind::MissingHalt
+    );
+
+    // Instructions from a macro are checked as they were expanded, not as the call was written
+    let lines: Vec<String> = [
+        ".macro init v",
+        "setn r1 \\v",
+        "setn r2 0",
+        ".endm",
+        "init 5",
+        "halt",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let assembly = assemble(&lines).unwrap();
+
+    assert_eq!(
+        lint(&assembly.instructions, &assembly.source_map),
+        Vec::new()
+    );
+}
+
+#[test]
+fn formatter_test() {
+    use hmmm_rs::formatter::format_hmmm;
+
+    // Gaps in the numbering are closed, and jumps follow the lines they pointed at
+    let formatted = format_hmmm(&load_file("tests/messy.hmmm").unwrap(), true).unwrap();
+    assert_eq!(formatted, load_file("tests/messy_formatted.hmmm").unwrap());
+
+    // Formatting never changes what a program compiles to, and is stable
+    for path in &["tests/mod.hmmm", "tests/macros.hmmm", "tests/table.hmmm"] {
+        let original = load_file(path).unwrap();
+        let formatted = format_hmmm(&original, false).unwrap();
+
+        assert_eq!(format_hmmm(&formatted, false).unwrap(), formatted);
+        assert_eq!(
+            Simulator::compile_hmmm(formatted).unwrap(),
+            Simulator::compile_hmmm(original).unwrap()
+        );
+    }
+
+    // Counts and origins are read the way the assembler reads them
+    let lines: Vec<String> = [
+        ".equ COUNT 0b11",
+        "setn r1 1",
+        ".fill COUNT 7",
+        ".space 0x2",
+        ".org 0x10",
+        "halt",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let formatted = format_hmmm(&lines, true).unwrap();
+    assert!(formatted[1].starts_with("0  "));
+    assert!(formatted[5].starts_with("16 "));
+    assert!(Simulator::compile_hmmm(formatted).is_ok());
+
+    // Macros and pseudo-instructions take up every word they expand to
+    let lines: Vec<String> = [
+        ".macro twice reg",
+        "    addn \\reg 1",
+        "    addn \\reg 1",
+        ".endm",
+        "0 setn r15 100",
+        "1 push r1 r2",
+        "2 twice r1",
+        "3 jumpn 5",
+        "loop: jumpn loop",
+        ".org 0x20",
+        "5 halt",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let formatted = format_hmmm(&lines, true).unwrap();
+    let numbers: Vec<&str> = formatted[4..]
+        .iter()
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(numbers, vec!["0", "1", "3", "5", "6", ".org", "32"]);
+    assert!(formatted[7].ends_with("jumpn  32"));
+
+    let program = Simulator::compile_hmmm(formatted).unwrap();
+    assert_eq!(program.len(), 33);
+    assert_eq!(program[32].instruction_type.names[0], "halt");
+
+    // Programs that don't assemble can't be laid out, so aren't renumbered
+    let lines: Vec<String> = ["0 setn r1 1", "1 frobnicate r1", "2 halt"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let diagnostics = format_hmmm(&lines, true).unwrap_err();
+    assert_eq!(diagnostics[0].error, CompileErr::InstructionDoesNotExist);
+    assert_eq!(diagnostics[0].source, "1 frobnicate r1");
+    assert!(format_hmmm(&lines, false).is_ok());
+}
+
+#[test]
+fn listing_test() {
+    let assembly = hmmm_rs::assembler::assemble(&load_file("tests/table.hmmm").unwrap()).unwrap();
+    let listing = hmmm_rs::create_listing(&assembly);
+    let lines: Vec<&str> = listing.lines().collect();
+
+    assert_eq!(
+        lines[1],
+        "000   1164  0001 0001 0110 0100  setn   r1 100         4    setn r1 table       # r1 points at the table"
+    );
+
+    // Words filled by one line only show its source once, and .org gaps are skipped
+    assert_eq!(lines[15], "014   0007  0000 0000 0000 0111  .word  7");
+    assert_eq!(lines[16], "...");
+    assert!(lines[17].starts_with("100   000A"));
+
+    assert!(lines.contains(&"table                  100  0x64"));
+    assert!(lines.contains(&"COUNT                    4"));
+}
+
+#[test]
+fn literals_test() {
+    assert_eq!(parse_literal("10"), Some(10));
+    assert_eq!(parse_literal("007"), Some(7));
+    assert_eq!(parse_literal("0x1F"), Some(31));
+    assert_eq!(parse_literal("-0x10"), Some(-16));
+    assert_eq!(parse_literal("0b1010"), Some(10));
+    assert_eq!(parse_literal("'A'"), Some(65));
+    assert_eq!(parse_literal("'\\n'"), Some(10));
+    assert_eq!(parse_literal("1F"), None);
+    assert_eq!(parse_literal("'AB'"), None);
+    // The only sign a literal can have is the one before its prefix
+    assert_eq!(parse_literal("0x+5"), None);
+    assert_eq!(parse_literal("-0x-5"), None);
+    assert_eq!(parse_literal("0b+1"), None);
+    assert_eq!(parse_literal("0x"), None);
+    assert_eq!(
+        compile_errors(&["setn r1 0x+5", "setn r1 -0x-5", "halt", ".word 0b+1"]),
+        vec![
+            CompileErr::InvalidSignedNumber,
+            CompileErr::InvalidSignedNumber,
+            CompileErr::InvalidNumber
+        ]
+    );
+
+    let lines: Vec<String> = [
+        "setn r1 0x1F",
+        "setn r2 ' '",
+        "addn r1 -0b11",
+        ".word 10, 'z', 0xFFFF",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let program = Simulator::compile_hmmm(lines).unwrap();
+    let values: Vec<String> = program
+        .iter()
+        .map(|word| word.binary_contents[2..].join(""))
+        .collect();
+
+    assert_eq!(values[0], "00011111");
+    assert_eq!(values[1], "00100000");
+    assert_eq!(values[2], "11111101");
+    // Plain numbers are always decimal, never hex
+    assert_eq!(program[3].text_contents, "10");
+    assert_eq!(program[4].text_contents, "122");
+    assert_eq!(program[5].text_contents, "-1");
+
+    // Out of range values report the range they need to fit in
+    let diagnostics = Simulator::compile_hmmm(vec!["setn r1 0xFF".to_string()]).unwrap_err();
+    assert_eq!(diagnostics[0].error, CompileErr::InvalidSignedNumber);
+    assert_eq!(
+        diagnostics[0].suggestion.as_deref(),
+        Some("255 is outside the allowed range of -128 to 127")
+    );
+}
+
+#[test]
+fn packed_test() {
+    use hmmm_rs::packed::{pack, unpack};
+
+    let assembly = hmmm_rs::assembler::assemble_from(
+        &load_file("tests/include.hmmm").unwrap(),
+        "tests/include.hmmm",
+    )
+    .unwrap();
+    let packed = pack(&assembly, true, true);
+
+    // Two bytes a word after the header, where .hb needs 19 characters
+    assert_eq!(&packed[..5], b"HMMM\x01");
+    assert_eq!(
+        packed[5..7],
+        (assembly.instructions.len() as u16).to_be_bytes()
+    );
+
+    let unpacked = unpack(&packed).unwrap();
+    let text: Vec<String> = assembly
+        .instructions
+        .iter()
+        .map(|word| word.binary_contents.join(" "))
+        .collect();
+
+    assert_eq!(unpacked.instructions, read_compiled_hmmm(text).unwrap());
+    assert_eq!(unpacked.symbols, assembly.symbols);
+    assert_eq!(unpacked.constants, assembly.constants);
+    assert_eq!(unpacked.source_map, assembly.source_map);
+
+    // Without the optional sections, only the words are kept
+    let stripped = unpack(&pack(&assembly, false, false)).unwrap();
+    assert_eq!(stripped.instructions, unpacked.instructions);
+    assert!(stripped.symbols.is_empty());
+    assert!(stripped.source_map.is_empty());
+
+    assert_eq!(unpack(b"HMMM"), Err(CompileErr::CorruptedBinary));
+    assert_eq!(
+        unpack(b"HMMM\x02\x00\x00"),
+        Err(CompileErr::UnsupportedVersion)
+    );
+    assert_eq!(
+        unpack(b"HMMM\x00\x00\x00"),
+        Err(CompileErr::UnsupportedVersion)
+    );
+    assert_eq!(
+        unpack(&packed[..packed.len() - 1]),
+        Err(CompileErr::CorruptedBinary)
+    );
+}
+
+#[test]
+fn image_test() {
+    use hmmm_rs::image::{export_image, import_image, ImageFormat};
+
+    let program = Simulator::compile_hmmm(load_file("tests/test.hmmm").unwrap()).unwrap();
+    let image_lines = |text: String| text.lines().map(String::from).collect::<Vec<String>>();
+    let decoded = read_compiled_hmmm(
+        program
+            .iter()
+            .map(|word| word.binary_contents.join(" "))
+            .collect(),
+    )
+    .unwrap();
+
+    // Images read back in the same way as a .hb file
+    for format in ImageFormat::ALL.iter() {
+        let image = export_image(&program, *format);
+        assert_eq!(import_image(&image_lines(image), *format).unwrap(), decoded);
+    }
+
+    let hex = image_lines(export_image(&program, ImageFormat::IntelHex));
+    assert_eq!(hex[0], ":10000000010101020201C207931203020000130062");
+    assert_eq!(hex.last().unwrap(), ":00000001FF");
+
+    assert_eq!(
+        export_image(&program, ImageFormat::Logisim),
+        "v2.0 raw\n101 102 201 c207 9312 302 0 1300\n302 0\n"
+    );
+    assert!(export_image(&program, ImageFormat::ReadMemH).starts_with("0101\n0102\n"));
+    assert!(export_image(&program, ImageFormat::ReadMemB).starts_with("0000_0001_0000_0001\n"));
+
+    // Memory dumps from a circuit, with runs, comments and addresses
+    let dump = image_lines("v2.0 raw\n# ROM contents\n1105 2*0 # padded\n".to_string());
+    let words = import_image(&dump, ImageFormat::Logisim).unwrap();
+    assert_eq!(words.len(), 3);
+    assert_eq!(words[0].instruction_type.names[0], "setn");
+
+    let dump = image_lines("// program\n@2 1105\n0002 // write r0\n".to_string());
+    let words = import_image(&dump, ImageFormat::ReadMemH).unwrap();
+    assert_eq!(words.len(), 4);
+    assert_eq!(words[2].binary_contents.join(""), "0001000100000101");
+    assert_eq!(words[3].instruction_type.names[0], "write");
+
+    // Bad checksums and digits are reported against the line they're on
+    let errors = import_image(
+        &image_lines(":0200000001010FC\n:00000001FF".to_string()),
+        ImageFormat::IntelHex,
+    )
+    .unwrap_err();
+    assert_eq!(errors[0].error, CompileErr::CorruptedBinary);
+    assert_eq!(errors[0].line, 1);
+
+    let errors = import_image(
+        &image_lines("0101\n01G1\n@100 0000\n".to_string()),
+        ImageFormat::ReadMemH,
+    )
+    .unwrap_err();
+    assert_eq!(errors[0].error, CompileErr::CorruptedBinary);
+    assert_eq!(errors[0].span, 0..4);
+    assert_eq!(errors[1].error, CompileErr::ProgramTooLarge);
+    assert_eq!(errors[1].line, 3);
+}
+
+#[test]
+fn disassembler_test() {
+    use hmmm_rs::assembler::{assemble, assemble_from, Assembly};
+    use hmmm_rs::disassembler::disassemble;
+
+    let binary = |assembly: &Assembly| -> Vec<Vec<String>> {
+        assembly
+            .instructions
+            .iter()
+            .map(|word| word.binary_contents.clone())
+            .collect()
+    };
+
+    // Without symbols, labels are made up from how each address is used
+    let source = assemble_from(
+        &load_file("tests/include.hmmm").unwrap(),
+        "tests/include.hmmm",
+    )
+    .unwrap();
+    let stripped = Assembly::from_instructions(source.instructions.clone());
+    let lines = disassemble(&stripped);
+
+    assert!(lines.contains(&"# Subroutine, called from address 2".to_string()));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("2             calln  r14, sub_5")));
+    assert!(lines.iter().any(|line| line
+        .starts_with("6   label_6:  jeqzn  r2, label_10  # If register r2 == 0, jump to line 10")));
+    assert_eq!(binary(&assemble(&lines).unwrap()), binary(&source));
+
+    // Data is kept apart from code, and the program's own labels are used if it has them
+    let source = assemble(&load_file("tests/table.hmmm").unwrap()).unwrap();
+    let lines = disassemble(&source);
+
+    assert!(lines.iter().any(|line| line.starts_with(
+        "10          loadn  r5, filled  # Load register r5 with contents of memory address 13"
+    )));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("13  filled: .word  7")));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("15          .space 85")));
+    assert_eq!(binary(&assemble(&lines).unwrap()), binary(&source));
+}
+
+#[test]
+fn decode_every_word_test() {
+    let mut instruction_types: Vec<InstructionType> = InstructionType::all_names()
+        .iter()
+        .filter_map(|name| InstructionType::from_name(name))
+        .filter(|instruction_type| instruction_type.names[0] != "data")
+        .collect();
+    instruction_types.dedup();
+
+    for word in 0..=u16::MAX {
+        let binary = format!("{:016b}", word);
+        let instruction = Instruction::new_from_binary(&binary).unwrap();
+        let name = instruction.instruction_type.names[0];
+
+        // The simulator's own decoder agrees with the instruction table
+        assert_eq!(
+            Opcode::decode(word).name(),
+            name,
+            "{} decodes differently when run",
+            binary
+        );
+
+        // Exactly one instruction is the most specific match, or the word is data
+        match InstructionType::decode(word) {
+            Some(decoded) => {
+                let most_specific = instruction_types
+                    .iter()
+                    .filter(|other| {
+                        other.matches_word(word) && other.fixed_bits() >= decoded.fixed_bits()
+                    })
+                    .count();
+                assert_eq!(most_specific, 1, "{} is ambiguous", binary);
+                assert_eq!(name, decoded.names[0]);
+            }
+            None => assert_eq!(name, "data", "{} should be data", binary),
+        }
+
+        // Writing the decoded instruction back out gives the same word
+        let text = format!("{} {}", name, instruction.text_contents.replace(",", ""));
+        let encoded = Instruction::new_from_text(text.trim()).unwrap();
+        assert_eq!(
+            encoded.binary_contents.join(""),
+            binary,
+            "{} re-encodes differently",
+            text
+        );
+    }
+
+    let decode = |binary: &str| Instruction::new_from_binary(binary).unwrap();
+
+    assert_eq!(
+        decode("0110 0000 0000 0000").instruction_type.names[0],
+        "nop"
+    );
+    assert_eq!(
+        decode("0110 0101 0000 0000").instruction_type.names[0],
+        "copy"
+    );
+    assert_eq!(
+        decode("0110 0101 0011 0000").instruction_type.names[0],
+        "copy"
+    );
+    assert_eq!(
+        decode("0110 0101 0011 0001").instruction_type.names[0],
+        "add"
+    );
+    assert_eq!(
+        decode("0111 0101 0000 0011").instruction_type.names[0],
+        "neg"
+    );
+    assert_eq!(
+        decode("1011 0000 0000 0101").instruction_type.names[0],
+        "jumpn"
+    );
+    assert_eq!(
+        decode("1011 1110 0000 0101").instruction_type.names[0],
+        "calln"
+    );
+    assert_eq!(
+        decode("0100 0001 0010 0001").instruction_type.names[0],
+        "storer"
+    );
+    assert_eq!(
+        decode("0100 0001 0010 0011").instruction_type.names[0],
+        "pushr"
+    );
+    assert_eq!(
+        decode("0100 0001 0010 0100").instruction_type.names[0],
+        "data"
+    );
+    assert_eq!(
+        decode("0000 0001 0001 0001").instruction_type.names[0],
+        "data"
+    );
+
+    // A loaded instruction runs as its bits say, whatever its type is called
+    let mut mislabelled = decode("0001 0001 0000 0101");
+    mislabelled.instruction_type = InstructionType::from_name("halt").unwrap();
+    let mut sim = Simulator::new_headless(vec![mislabelled, decode("0000 0000 0000 0000")]);
+
+    sim.step().unwrap();
+    assert_eq!(sim.get_register(1), Some(5));
+}
+
+#[test]
+fn link_test() {
+    use hmmm_rs::assembler::assemble_object;
+    use hmmm_rs::linker::{link, LinkErr, RelocationField};
+    use hmmm_rs::packed::{pack_object, unpack_object};
+
+    let object = |path: &str| assemble_object(&load_file(path).unwrap(), path).unwrap();
+    let main = object("tests/link/main.hmmm");
+    let mul = object("tests/link/mul.hmmm");
+
+    assert_eq!(
+        main.imports().into_iter().collect::<Vec<&str>>(),
+        vec!["calls", "multiply"]
+    );
+    assert!(mul.exports.contains("multiply"));
+    assert!(
+        mul.relocations
+            .iter()
+            .any(|relocation| relocation.field == RelocationField::Word
+                && relocation.symbol.is_none())
+    );
+
+    // Objects survive being packed, apart from their name
+    let mut unpacked = unpack_object(&pack_object(&mul)).unwrap();
+    unpacked.name = mul.name.clone();
+    assert_eq!(unpacked.exports, mul.exports);
+    assert_eq!(unpacked.relocations, mul.relocations);
+
+    let linked = link(&[main.clone(), mul.clone()]).unwrap();
+    let base = main.assembly.instructions.len();
+
+    assert_eq!(linked.symbols["multiply"], base);
+    assert_eq!(
+        linked.symbols["calls"],
+        base + mul.assembly.symbols["calls"]
+    );
+
+    // .word multiply is moved along with mul.hmmm
+    let entry = &linked.instructions[base + mul.assembly.symbols["entry"]];
+    assert_eq!(
+        u16::from_str_radix(&entry.binary_contents.join(""), 2).unwrap() as usize,
+        base
+    );
+
+    let mut sim = Simulator::new_headless(linked.instructions);
+    sim.set_inputs(vec![6, 7]);
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42, 1]);
+
+    let errors = |objects: &[hmmm_rs::linker::Object]| -> Vec<LinkErr> {
+        link(objects)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.error)
+            .collect()
+    };
+
+    assert_eq!(
+        errors(std::slice::from_ref(&main)),
+        vec![LinkErr::UndefinedSymbol, LinkErr::UndefinedSymbol]
+    );
+    assert_eq!(
+        errors(&[main.clone(), mul.clone(), mul.clone()]),
+        vec![LinkErr::DuplicateSymbol, LinkErr::DuplicateSymbol]
+    );
+
+    let big = assemble_object(&[".fill 250".to_string()], "big.hmmm").unwrap();
+    assert_eq!(errors(&[main, big]), vec![LinkErr::ProgramTooLarge]);
+}
+
+#[test]
+fn loader_test() {
+    use hmmm_rs::assembler::assemble_object;
+    use hmmm_rs::linker::LinkErr;
+    use hmmm_rs::loader::Loader;
+
+    let source =
+        |lines: &[&str]| -> Vec<String> { lines.iter().map(|line| line.to_string()).collect() };
+
+    // A resident monitor that runs the user program, then writes out what it left in r13
+    let monitor = assemble_object(
+        &source(&["calln r14 user_main", "write r13", "halt"]),
+        "monitor.hmmm",
+    )
+    .unwrap();
+    let user = assemble_object(
+        &source(&[
+            ".global user_main",
+            "user_main:  loadn r1 values",
+            "            loadn r2 pointer",
+            "            addn r2 1",
+            "            loadr r2 r2",
+            "            add r13 r1 r2",
+            "            storen r13 values",
+            "            jumpr r14",
+            "pointer:    .word values",
+        ]),
+        "user.hmmm",
+    )
+    .unwrap();
+
+    let mut loader = Loader::new();
+    loader.add_object(0, monitor);
+    loader.add_object(100, user);
+    loader.add_data(200, "values", &[20, 22]);
+
+    let image = loader.image().unwrap();
+    assert_eq!(image.instructions.len(), 202);
+    assert_eq!(
+        image.source_map.get(100).unwrap().text,
+        "user_main:  loadn r1 values"
+    );
+    assert_eq!(image.symbols["user_main"], 100);
+    assert_eq!(image.symbols["values"], 200);
+
+    let mut sim = loader.simulator().unwrap();
+    sim.set_headless();
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42]);
+    assert_eq!(sim.read_mem(200), Ok(42));
+
+    // Programs from a binary have their jumps moved, and can start anywhere
+    let binary = Simulator::compile_hmmm(source(&[
+        "setn r1 3",
+        "jeqzn r1 5",
+        "write r1",
+        "addn r1 -1",
+        "jumpn 1",
+        "halt",
+    ]))
+    .unwrap();
+    let mut loader = Loader::new();
+    loader.add_program(50, "countdown.hb", binary);
+    loader.set_entry(50);
+
+    let mut sim = loader.simulator().unwrap();
+    sim.set_headless();
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![3, 2, 1]);
+
+    let errors = |loader: &Loader| -> Vec<LinkErr> {
+        loader
+            .image()
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.error)
+            .collect()
+    };
+
+    loader.add_data(54, "table", &[1, 2]);
+    loader.add_data(250, "buffer", &[0; 10]);
+    loader.set_entry(256);
+    assert_eq!(
+        errors(&loader),
+        vec![
+            LinkErr::AddressOutOfRange,
+            LinkErr::Overlap,
+            LinkErr::ProgramTooLarge
+        ]
+    );
+}
+
+#[test]
+fn debug_info_test() {
+    use hmmm_rs::assembler::Assembly;
+    use hmmm_rs::debug_info::{debug_section, strip_debug_section};
+    use hmmm_rs::{read_compiled_assembly, write_compiled_assembly};
+
+    let path = "tests/table.hmmm";
+    let assembly = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path).unwrap();
+    let output = std::env::temp_dir().join("hmmm_rs_debug_info_test.hb");
+    let output = output.to_str().unwrap();
+
+    write_compiled_assembly(output, &assembly).unwrap();
+    let raw_binary = load_file(output).unwrap();
+    std::fs::remove_file(output).unwrap();
+
+    assert!(raw_binary.contains(&"# debug 1".to_string()));
+
+    // The words read back the same, with or without the debug section
+    let loaded = read_compiled_assembly(raw_binary.clone()).unwrap();
+    let words = read_compiled_hmmm(raw_binary.clone()).unwrap();
+
+    assert_eq!(loaded.instructions, words);
+    assert_eq!(loaded.symbols, assembly.symbols);
+    assert_eq!(loaded.constants, assembly.constants);
+    assert_eq!(loaded.source_map, assembly.source_map);
+
+    let stripped = strip_debug_section(&raw_binary);
+    assert_eq!(stripped.len(), assembly.instructions.len());
+    assert_eq!(
+        read_compiled_assembly(stripped).unwrap(),
+        Assembly::from_instructions(words)
+    );
+
+    // Programs with nothing to record get no section at all
+    assert!(debug_section(&Assembly::from_instructions(Vec::new())).is_empty());
+
+    let errors = |lines: &[&str]| -> Vec<CompileErr> {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        read_compiled_assembly(lines)
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.error)
+            .collect()
+    };
+
+    assert_eq!(
+        errors(&["0000 0000 0000 0000", "# debug 2"]),
+        vec![CompileErr::UnsupportedVersion]
+    );
+    assert_eq!(
+        errors(&[
+            "0000 0000 0000 0000",
+            "# debug 1",
+            "# label start",
+            "# line 0 3 1 0 halt"
+        ]),
+        vec![CompileErr::CorruptedBinary; 2]
+    );
+}
+
+#[test]
+fn self_modifying_test() {
+    let program = Simulator::compile_hmmm(load_file("tests/self_modifying.hmmm").unwrap()).unwrap();
+
+    let mut sim = Simulator::new_headless(program.clone());
+    let error = loop {
+        if let Err(error) = sim.step() {
+            break error;
+        }
+    };
+
+    assert_eq!(error.kind, RuntimeErr::Halt);
+    assert_eq!(sim.get_outputs(), vec![3, 2, 1]);
+
+    // Rewritten words are decoded when they're run
+    assert_eq!(sim.get_memory(0).unwrap().instruction_type.names[0], "data");
+    assert_eq!(sim.fetch(0).unwrap().text_contents, "r1, 0");
+
+    // Strict mode won't read an instruction as a number
+    let mut sim = Simulator::new_headless(program);
+    sim.set_strict(true);
+    let error = loop {
+        if let Err(error) = sim.step() {
+            break error;
+        }
+    };
+
+    assert_eq!(error.kind, RuntimeErr::MemoryLocationNotData);
+    assert_eq!(error.address, 3);
+
+    // A popr that traps leaves the stack pointer where it was
+    let program = Simulator::compile_hmmm(
+        ["setn r15 1", "popr r1 r15", "halt"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect(),
+    )
+    .unwrap();
+    let mut sim = Simulator::new_headless(program);
+    sim.set_strict(true);
+    sim.step().unwrap();
+
+    assert_eq!(
+        sim.step().unwrap_err().kind,
+        RuntimeErr::MemoryLocationNotData
+    );
+    assert_eq!(sim.get_register(15).unwrap(), 1);
+
+    // Or run a number it wrote itself. 2 is "write r0", and the
+    // blank word after it is "halt"
+    let program = Simulator::compile_hmmm(
+        ["setn r1 2", "storen r1 3", "jumpn 3", "halt"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect(),
+    )
+    .unwrap();
+
+    let mut sim = Simulator::new_headless(program.clone());
+    let error = loop {
+        if let Err(error) = sim.step() {
+            break error;
+        }
+    };
+
+    assert_eq!(error.kind, RuntimeErr::Halt);
+    assert_eq!(error.address, 4);
+    assert_eq!(sim.get_outputs(), vec![0]);
+
+    let mut sim = Simulator::new_headless(program);
+    sim.set_strict(true);
+    let error = loop {
+        if let Err(error) = sim.step() {
+            break error;
+        }
+    };
+
+    assert_eq!(error.kind, RuntimeErr::InstructionIsData);
+}
+
+#[test]
+fn devices_test() {
+    use hmmm_rs::devices::{Callback, Reader, Writer};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let path = "tests/include.hmmm";
+    let program = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path)
+        .unwrap()
+        .instructions;
+
+    // Numbers from a pipe, split over lines and spaces, written to a file
+    let mut sim = Simulator::new(program.clone());
+    let output = Rc::new(RefCell::new(Writer::new(Vec::new())));
+    sim.set_input_source(Reader::new(&b"6\n\n 7\n"[..]));
+    sim.set_output_sink(Rc::clone(&output));
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42]);
+    drop(sim);
+    let written = Rc::try_unwrap(output).unwrap().into_inner().into_inner();
+    assert_eq!(String::from_utf8(written).unwrap(), "42\n");
+
+    // Numbers from a callback, collected in a vector
+    let mut next = 5;
+    let collected = Rc::new(RefCell::new(Vec::new()));
+    let mut sim = Simulator::new(program.clone());
+    sim.set_input_source(Callback::new(move || {
+        next += 1;
+        Some(next)
+    }));
+    sim.set_output_sink(Rc::clone(&collected));
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(*collected.borrow(), vec![42]);
+
+    // Running out of input, or reading something that isn't a number, stops the program
+    let mut sim = Simulator::new(program.clone());
+    sim.set_input_source(Reader::new(&b"6\n"[..]));
+    sim.set_output_sink(Callback::new(|_| panic!("nothing should be written")));
+    assert_eq!(run_until_error(&mut sim), RuntimeErr::TooManyInputs);
+
+    let mut sim = Simulator::new_headless(program);
+    sim.set_input_source(Reader::new(&b"6 seven"[..]));
+    assert_eq!(run_until_error(&mut sim), RuntimeErr::InvalidInput);
+}
+
+fn run_until_error(sim: &mut Simulator) -> RuntimeErr {
+    loop {
+        if let Err(err) = sim.step() {
+            return err.kind;
+        }
+    }
+}
+
+#[test]
+fn snapshot_test() {
+    use hmmm_rs::snapshot::{self, SnapshotErr};
+
+    let path = "tests/include.hmmm";
+    let assembly = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path).unwrap();
+
+    let mut sim = Simulator::new_headless(assembly.instructions);
+    sim.set_source_map(assembly.source_map);
+    sim.set_inputs(vec![6, 7]);
+    sim.step().unwrap();
+
+    // Inputs that haven't been read yet are kept
+    let saved = snapshot::save(&sim);
+    assert_eq!(
+        snapshot::restore(&saved).unwrap().get_remaining_inputs(),
+        Some(vec![7])
+    );
+
+    // Stop part way through multiply, and carry on from a snapshot
+    for _ in 0..5 {
+        sim.step().unwrap();
+    }
+    sim.write_mem(200, -5).unwrap();
+
+    let saved = snapshot::save(&sim);
+    let mut restored = snapshot::restore(&saved).unwrap();
+    let collected = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    restored.set_output_sink(std::rc::Rc::clone(&collected));
+
+    assert_eq!(snapshot::save(&restored), saved);
+    assert_eq!(restored.registers, sim.registers);
+    assert_eq!(restored.get_program_counter(), sim.get_program_counter());
+    assert_eq!(restored.get_source_location(2), sim.get_source_location(2));
+    for address in 0..256 {
+        assert_eq!(restored.get_memory(address), sim.get_memory(address));
+    }
+
+    while sim.step().is_ok() {}
+    while restored.step().is_ok() {}
+
+    assert_eq!(restored.get_outputs(), vec![42]);
+    assert_eq!(*collected.borrow(), vec![42]);
+    assert_eq!(restored.registers, sim.registers);
+    assert_eq!(restored.counter_log, sim.counter_log);
+
+    // Newer versions are refused, but sections that aren't known are skipped
+    let mut newer = saved.clone();
+    newer[4] = snapshot::VERSION + 1;
+    assert_eq!(
+        snapshot::restore(&newer).unwrap_err(),
+        SnapshotErr::UnsupportedVersion
+    );
+
+    // Versions start at 1, so 0 can only come from a damaged snapshot,
+    // and other files are told apart by their magic bytes
+    let mut unversioned = saved.clone();
+    unversioned[4] = 0;
+    assert_eq!(
+        snapshot::restore(&unversioned).unwrap_err(),
+        SnapshotErr::UnsupportedVersion
+    );
+    assert_eq!(
+        snapshot::restore(b"HMMM\x01\x00\x00").unwrap_err(),
+        SnapshotErr::NotASnapshot
+    );
+
+    let mut extended = saved.clone();
+    extended.extend_from_slice(b"NEW!\0\0\0\x02hi");
+    assert!(snapshot::restore(&extended).is_ok());
+
+    assert_eq!(
+        snapshot::restore(&saved[..saved.len() - 1]).unwrap_err(),
+        SnapshotErr::Corrupted
+    );
+
+    // States a simulator can't get into are refused, rather than run
+    let cpu = saved.windows(4).position(|tag| tag == b"CPU ").unwrap() + 8;
+    let mut bad_counter = saved.clone();
+    bad_counter[cpu + 33..cpu + 35].copy_from_slice(&256u16.to_be_bytes());
+    assert_eq!(
+        snapshot::restore(&bad_counter).unwrap_err(),
+        SnapshotErr::Corrupted
+    );
+
+    let mut bad_register = saved.clone();
+    bad_register[cpu + 36] = 16;
+    assert_eq!(
+        snapshot::restore(&bad_register).unwrap_err(),
+        SnapshotErr::Corrupted
+    );
+}
+
+#[test]
+fn history_test() {
+    let path = "tests/include.hmmm";
+    let program = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path)
+        .unwrap()
+        .instructions;
+
+    let mut sim = Simulator::new_headless(program);
+    sim.set_inputs(vec![6, 7]);
+    sim.set_history_limit(1000);
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42]);
+    let steps = sim.get_step_count();
+
+    // r13 was last written by the add in multiply, and r2 by its addn
+    let (step, write) = sim.get_history().last_register_write(13).unwrap();
+    assert_eq!((step.address, write.before, write.after), (7, 36, 42));
+    assert_eq!(
+        sim.get_history().last_register_write(2).unwrap().0.address,
+        8
+    );
+    assert!(sim.get_history().last_register_write(5).is_none());
+
+    // Going back stops at a breakpoint, taking back the output written since
+    sim.add_breakpoint(Breakpoint::new(11));
+    assert_eq!(sim.reverse_continue(), 2);
+    assert_eq!(sim.get_program_counter(), 11);
+    assert_eq!(sim.get_outputs(), Vec::<i16>::new());
+
+    // Undoing every step gives the inputs back, to be read again
+    while sim.step_back().is_some() {}
+
+    assert_eq!(sim.get_program_counter(), 0);
+    assert_eq!(sim.get_step_count(), 0);
+    assert_eq!(sim.registers, vec![0; 16]);
+    assert_eq!(sim.get_remaining_inputs(), Some(vec![6, 7]));
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![42]);
+    assert_eq!(sim.get_step_count(), steps);
+
+    // Only the most recent steps are kept
+    sim.set_history_limit(3);
+    assert_eq!(sim.get_history().len(), 3);
+
+    // Writes to memory are undone too
+    let program: Vec<String> = ["setn r1 9", "storen r1 10", "halt"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let mut sim = Simulator::new_headless(Simulator::compile_hmmm(program).unwrap());
+    sim.set_history_limit(10);
+    let blank = sim.get_memory(10);
+
+    sim.step().unwrap();
+    sim.step().unwrap();
+    assert_eq!(sim.get_word(10), Some(9));
+    assert_eq!(
+        sim.get_history().last_memory_write(10).unwrap().0.address,
+        1
+    );
+
+    sim.step_back().unwrap();
+    assert_eq!(sim.get_memory(10), blank);
+    assert_eq!(sim.get_program_counter(), 1);
+}
+
+#[test]
+fn breakpoints_test() {
+    let path = "tests/include.hmmm";
+    let program = hmmm_rs::assembler::assemble_from(&load_file(path).unwrap(), path)
+        .unwrap()
+        .instructions;
+
+    let mut sim = Simulator::new_headless(program.clone());
+    sim.set_inputs(vec![6, 7]);
+
+    // A conditional breakpoint on the add in multiply stops once r2 has counted down
+    let mut breakpoint = Breakpoint::new(7);
+    breakpoint.condition = Some("r2 < 3".parse().unwrap());
+    let id = sim.add_breakpoint(breakpoint);
+
+    assert_eq!(sim.run_until(1000), Stop::Breakpoint(id));
+    assert_eq!(
+        (sim.get_program_counter(), sim.get_register(2).unwrap()),
+        (7, 2)
+    );
+    assert_eq!(sim.run_until(1000), Stop::Breakpoint(id));
+    assert_eq!(sim.get_register(2).unwrap(), 1);
+    assert_eq!(sim.get_stops().breakpoints[&id].hits, 2);
+
+    assert!(sim.remove_stop(id));
+    assert!(!sim.remove_stop(id));
+    assert_eq!(sim.run_until(1000), Stop::Halted);
+    assert_eq!(sim.get_outputs(), vec![42]);
+
+    // The first hits can be passed over
+    let mut sim = Simulator::new_headless(program.clone());
+    sim.set_inputs(vec![6, 7]);
+    let mut breakpoint = Breakpoint::new(8);
+    breakpoint.ignore_count = 3;
+    let id = sim.add_breakpoint(breakpoint);
+
+    assert_eq!(sim.run_until(1000), Stop::Breakpoint(id));
+    assert_eq!(sim.get_register(2).unwrap(), 4);
+    assert_eq!(sim.run_until(3), Stop::StepLimit);
+
+    // A breakpoint reached by the last step allowed still stops the run,
+    // so running a step at a time stops at every hit
+    let mut sim = Simulator::new_headless(program.clone());
+    sim.set_inputs(vec![6, 7]);
+    let id = sim.add_breakpoint(Breakpoint::new(2));
+    assert_eq!(sim.run_until(2), Stop::Breakpoint(id));
+
+    let mut sim = Simulator::new_headless(program.clone());
+    sim.set_inputs(vec![6, 7]);
+    let id = sim.add_breakpoint(Breakpoint::new(7));
+    let mut stops = 0;
+
+    loop {
+        match sim.run_until(1) {
+            Stop::Breakpoint(_) => stops += 1,
+            Stop::StepLimit => {}
+            _ => break,
+        }
+    }
+
+    assert_eq!(stops, 7);
+    assert_eq!(sim.get_stops().breakpoints[&id].hits, 7);
+
+    // Watching r13 for a change skips the setn, which leaves it at 0
+    let mut sim = Simulator::new_headless(program.clone());
+    sim.set_inputs(vec![6, 7]);
+    let change = sim.add_watchpoint(Watchpoint::new(Location::Register(13), Access::Change));
+
+    assert_eq!(
+        sim.run_until(1000),
+        Stop::Watchpoint {
+            id: change,
+            value: 6
+        }
+    );
+    assert_eq!(sim.get_program_counter(), 8);
+
+    // Whereas watching it for a write doesn't
+    let mut sim = Simulator::new_headless(program);
+    sim.set_inputs(vec![6, 7]);
+    let write = sim.add_watchpoint(Watchpoint::new(Location::Register(13), Access::Write));
+    let read = sim.add_watchpoint(Watchpoint::new(Location::Register(1), Access::Read));
+
+    assert_eq!(
+        sim.run_until(1000),
+        Stop::Watchpoint {
+            id: write,
+            value: 0
+        }
+    );
+    assert_eq!(sim.get_program_counter(), 6);
+    assert_eq!(
+        sim.run_until(1000),
+        Stop::Watchpoint {
+            id: write,
+            value: 6
+        }
+    );
+    assert_eq!(sim.get_stops().watchpoints[&read].hits, 1);
+
+    // Memory can be watched too
+    let program: Vec<String> = ["setn r1 9", "storen r1 200", "loadn r2 200", "halt"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let mut sim = Simulator::new_headless(Simulator::compile_hmmm(program).unwrap());
+    let read = sim.add_watchpoint(Watchpoint::new("[200]".parse().unwrap(), Access::Read));
+
+    assert_eq!(sim.run_until(1000), Stop::Watchpoint { id: read, value: 9 });
+    assert_eq!(sim.get_register(2).unwrap(), 9);
+
+    // Conditions read registers, memory, the program counter and numbers
+    let condition: Condition = "[200]>=0x9".parse().unwrap();
+    assert_eq!(condition.to_string(), "[200] >= 9");
+    assert!(condition.evaluate(&sim));
+    assert!("pc == 3".parse::<Condition>().unwrap().evaluate(&sim));
+    assert_eq!(
+        "r3".parse::<Condition>().unwrap_err(),
+        ConditionErr::MissingComparison
+    );
+    assert_eq!(
+        "r16 < 2".parse::<Condition>().unwrap_err(),
+        ConditionErr::InvalidOperand
+    );
+
+    // The comparison is the first one after the left operand, not the first in the text
+    for (text, parsed) in &[
+        ("r1 > '<'", "r1 > 60"),
+        ("'=' == 61", "61 == 61"),
+        ("'\\'' != r0", "39 != r0"),
+        ("r1<-5", "r1 < -5"),
+    ] {
+        assert_eq!(text.parse::<Condition>().unwrap().to_string(), *parsed);
+    }
+}
+
+/// Runs one instruction with r1 and r2 set (and r3 set to r1, for addn),
+/// giving what it left in r3
+fn overflow_result(
+    line: &str,
+    r1: i16,
+    r2: i16,
+    overflow: OverflowPolicy,
+) -> Result<i16, RuntimeErr> {
+    let mut sim = Simulator::new_headless(Simulator::compile_hmmm(vec![line.to_string()]).unwrap());
+    sim.set_overflow_policy(overflow);
+    sim.registers[1] = r1;
+    sim.registers[2] = r2;
+    sim.registers[3] = r1;
+
+    sim.step().map_err(|err| err.kind)?;
+    Ok(sim.registers[3])
+}
+
+#[test]
+fn overflow_test() {
+    use hmmm_rs::devices::{Callback, Reader};
+    use OverflowPolicy::*;
+
+    const OUT: Result<i16, RuntimeErr> = Err(RuntimeErr::RegisterOutOfBounds);
+    const ZERO: Result<i16, RuntimeErr> = Err(RuntimeErr::DivideByZero);
+    const BAD: Result<i16, RuntimeErr> = Err(RuntimeErr::InvalidInput);
+
+    // Instruction, r1, r2, then the result when trapping, wrapping and saturating
+    let cases = [
+        ("add r3 r1 r2", 32766, 1, Ok(32767), Ok(32767), Ok(32767)),
+        ("add r3 r1 r2", 32767, 1, OUT, Ok(-32768), Ok(32767)),
+        ("add r3 r1 r2", -32768, -1, OUT, Ok(32767), Ok(-32768)),
+        ("add r3 r1 r2", 32767, 32767, OUT, Ok(-2), Ok(32767)),
+        (
+            "sub r3 r1 r2",
+            -1,
+            32767,
+            Ok(-32768),
+            Ok(-32768),
+            Ok(-32768),
+        ),
+        ("sub r3 r1 r2", -32768, 1, OUT, Ok(32767), Ok(-32768)),
+        ("sub r3 r1 r2", 0, -32768, OUT, Ok(-32768), Ok(32767)),
+        (
+            "mul r3 r1 r2",
+            -256,
+            128,
+            Ok(-32768),
+            Ok(-32768),
+            Ok(-32768),
+        ),
+        ("mul r3 r1 r2", 256, 128, OUT, Ok(-32768), Ok(32767)),
+        ("mul r3 r1 r2", -32768, -1, OUT, Ok(-32768), Ok(32767)),
+        ("mul r3 r1 r2", 32767, 32767, OUT, Ok(1), Ok(32767)),
+        ("mul r3 r1 r2", 32767, -32768, OUT, Ok(-32768), Ok(-32768)),
+        (
+            "div r3 r1 r2",
+            -32768,
+            1,
+            Ok(-32768),
+            Ok(-32768),
+            Ok(-32768),
+        ),
+        ("div r3 r1 r2", -32768, -1, OUT, Ok(-32768), Ok(32767)),
+        (
+            "div r3 r1 r2",
+            32767,
+            -1,
+            Ok(-32767),
+            Ok(-32767),
+            Ok(-32767),
+        ),
+        ("div r3 r1 r2", 1, 0, ZERO, ZERO, ZERO),
+        ("mod r3 r1 r2", -32768, -1, Ok(0), Ok(0), Ok(0)),
+        ("mod r3 r1 r2", -32768, 32767, Ok(-1), Ok(-1), Ok(-1)),
+        ("mod r3 r1 r2", 1, 0, ZERO, ZERO, ZERO),
+        ("addn r3 127", 32640, 0, Ok(32767), Ok(32767), Ok(32767)),
+        ("addn r3 1", 32767, 0, OUT, Ok(-32768), Ok(32767)),
+        ("addn r3 127", 32767, 0, OUT, Ok(-32642), Ok(32767)),
+        ("addn r3 -1", -32768, 0, OUT, Ok(32767), Ok(-32768)),
+        ("addn r3 -128", -32768, 0, OUT, Ok(32640), Ok(-32768)),
+        ("neg r3 r1", -32767, 0, Ok(32767), Ok(32767), Ok(32767)),
+        ("neg r3 r1", 32767, 0, Ok(-32767), Ok(-32767), Ok(-32767)),
+        ("neg r3 r1", -32768, 0, OUT, Ok(-32768), Ok(32767)),
+    ];
+
+    for (line, r1, r2, trap, wrap, saturate) in cases.iter().cloned() {
+        assert_eq!(
+            overflow_result(line, r1, r2, Trap),
+            trap,
+            "{} with {}, {}",
+            line,
+            r1,
+            r2
+        );
+        assert_eq!(
+            overflow_result(line, r1, r2, Wrap),
+            wrap,
+            "{} with {}, {}",
+            line,
+            r1,
+            r2
+        );
+        assert_eq!(
+            overflow_result(line, r1, r2, Saturate),
+            saturate,
+            "{} with {}, {}",
+            line,
+            r1,
+            r2
+        );
+    }
+
+    // Numbers given to read are fitted in the same way, but trap as invalid input
+    let reads = [
+        ("32767", Ok(32767), Ok(32767), Ok(32767)),
+        ("-32768", Ok(-32768), Ok(-32768), Ok(-32768)),
+        ("32768", BAD, Ok(-32768), Ok(32767)),
+        ("-32769", BAD, Ok(32767), Ok(-32768)),
+        ("65535", BAD, Ok(-1), Ok(32767)),
+        ("32767.5", BAD, BAD, BAD),
+    ];
+
+    for (input, trap, wrap, saturate) in reads.iter().cloned() {
+        for (overflow, expected) in [(Trap, trap), (Wrap, wrap), (Saturate, saturate)]
+            .iter()
+            .cloned()
+        {
+            let mut sim = Simulator::new_headless(
+                Simulator::compile_hmmm(vec!["read r3".to_string()]).unwrap(),
+            );
+            sim.set_overflow_policy(overflow);
+            sim.set_input_source(Reader::new(input.as_bytes()));
+
+            let result = sim.step().map(|_| sim.registers[3]).map_err(|err| err.kind);
+            assert_eq!(result, expected, "reading {} with {}", input, overflow);
+        }
+    }
+
+    let mut sim =
+        Simulator::new_headless(Simulator::compile_hmmm(vec!["read r3".to_string()]).unwrap());
+    sim.set_overflow_policy(Wrap);
+    sim.set_input_source(Callback::new(|| Some(100_000)));
+    sim.step().unwrap();
+    assert_eq!(sim.registers[3], -31072);
+
+    // The policy is kept in snapshots, and can be given by name
+    let restored = hmmm_rs::snapshot::restore(&hmmm_rs::snapshot::save(&sim)).unwrap();
+    assert_eq!(restored.get_overflow_policy(), Wrap);
+
+    // Snapshots from before the policy was saved restore as trapping
+    let mut sim =
+        Simulator::new_headless(Simulator::compile_hmmm(vec!["halt".to_string()]).unwrap());
+    sim.set_overflow_policy(Trap);
+    let mut saved = hmmm_rs::snapshot::save(&sim);
+    assert_eq!(saved[4], 2);
+    saved[4] = 1;
+    assert_eq!(
+        hmmm_rs::snapshot::restore(&saved)
+            .unwrap()
+            .get_overflow_policy(),
+        Trap
+    );
+    assert_eq!("saturate".parse::<OverflowPolicy>(), Ok(Saturate));
+    assert_eq!(OverflowPolicy::default(), Trap);
+}